@@ -0,0 +1,169 @@
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    BufReadPost,
+    BufWritePre,
+    BufWritePost,
+    InsertEnter,
+    InsertLeave,
+    ModeChanged,
+    CursorMoved,
+    TextChanged,
+    VimResized,
+    VimLeavePre,
+}
+
+const EVENTS: &[(Event, &str)] = &[
+    (Event::BufReadPost, "BufReadPost"),
+    (Event::BufWritePre, "BufWritePre"),
+    (Event::BufWritePost, "BufWritePost"),
+    (Event::InsertEnter, "InsertEnter"),
+    (Event::InsertLeave, "InsertLeave"),
+    (Event::ModeChanged, "ModeChanged"),
+    (Event::CursorMoved, "CursorMoved"),
+    (Event::TextChanged, "TextChanged"),
+    (Event::VimResized, "VimResized"),
+    (Event::VimLeavePre, "VimLeavePre"),
+];
+
+impl Event {
+    pub fn from_name(name: &str) -> Option<Self> {
+        EVENTS
+            .iter()
+            .find(|(_, n)| n.eq_ignore_ascii_case(name))
+            .map(|(e, _)| *e)
+    }
+
+    pub fn name(&self) -> &str {
+        EVENTS
+            .iter()
+            .find(|(e, _)| e == self)
+            .map(|(_, n)| *n)
+            .unwrap_or_default()
+    }
+}
+
+struct AutoCmd {
+    event: Event,
+    pattern: String,
+    cmd: String,
+}
+
+#[derive(Default)]
+pub struct AutoCmds {
+    cmds: Vec<AutoCmd>,
+}
+impl AutoCmds {
+    pub fn parse_events(events: &str) -> Result<Vec<Event>, String> {
+        events
+            .split(',')
+            .map(|name| {
+                if name == "*" {
+                    Ok(EVENTS.iter().map(|(e, _)| *e).collect())
+                } else {
+                    Event::from_name(name)
+                        .map(|e| vec![e])
+                        .ok_or_else(|| format!("No such event: {}", name))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|events| events.concat())
+    }
+
+    pub fn add(&mut self, events: &[Event], pattern: &str, cmd: &str) {
+        for event in events {
+            self.cmds.push(AutoCmd {
+                event: *event,
+                pattern: pattern.into(),
+                cmd: cmd.into(),
+            });
+        }
+    }
+
+    pub fn remove(&mut self, events: Option<&[Event]>, pattern: Option<&str>) {
+        self.cmds.retain(|au| {
+            let event = events.is_none_or(|events| events.contains(&au.event));
+            let pattern = pattern.is_none_or(|p| p == au.pattern);
+            !(event && pattern)
+        });
+    }
+
+    pub fn describe(&self) -> Vec<String> {
+        self.cmds
+            .iter()
+            .map(|au| format!("{} {} {}", au.event.name(), au.pattern, au.cmd))
+            .collect()
+    }
+
+    pub fn matching(&self, event: Event, filename: Option<&Path>) -> Vec<String> {
+        let full = filename
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let tail = filename
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        self.cmds
+            .iter()
+            .filter(|au| au.event == event)
+            .filter(|au| {
+                let target = if au.pattern.contains('/') {
+                    &full
+                } else {
+                    &tail
+                };
+                expand_braces(&au.pattern)
+                    .iter()
+                    .any(|p| glob_match(p.as_bytes(), target.as_bytes()))
+            })
+            .map(|au| au.cmd.clone())
+            .collect()
+    }
+}
+
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let Some(start) = pattern.find('{') else {
+        return vec![pattern.into()];
+    };
+    let Some(len) = pattern[start..].find('}') else {
+        return vec![pattern.into()];
+    };
+    let (head, tail) = (&pattern[..start], &pattern[start + len + 1..]);
+    pattern[start + 1..start + len]
+        .split(',')
+        .flat_map(|alt| expand_braces(&format!("{}{}{}", head, alt, tail)))
+        .collect()
+}
+
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+        Some((b'?', rest)) => !text.is_empty() && glob_match(rest, &text[1..]),
+        Some((b'[', rest)) if rest.contains(&b']') => {
+            let Some((c, text_rest)) = text.split_first() else {
+                return false;
+            };
+            let end = rest.iter().position(|b| *b == b']').unwrap_or_default();
+            let (class, negate) = match &rest[..end] {
+                [b'!' | b'^', class @ ..] => (class, true),
+                class => (class, false),
+            };
+            let mut found = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == b'-' {
+                    found |= (class[i]..=class[i + 2]).contains(c);
+                    i += 3;
+                } else {
+                    found |= class[i] == *c;
+                    i += 1;
+                }
+            }
+            found != negate && glob_match(&rest[end + 1..], text_rest)
+        }
+        Some((p, rest)) => text.first() == Some(p) && glob_match(rest, &text[1..]),
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use flate2::{read::MultiGzDecoder, write::GzEncoder};
+use xz2::{read::XzDecoder, write::XzEncoder};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const HEADER_LEN: usize = 32;
+
+const GZIP_LEVEL: u32 = 6;
+const ZSTD_LEVEL: i32 = 3;
+const XZ_LEVEL: u32 = 6;
+const XZ_LZMA2: u8 = 0x21;
+const XZ_DICT_SIZES: &[(u32, u64)] = &[
+    (0, 256 << 10),
+    (1, 1 << 20),
+    (2, 2 << 20),
+    (4, 4 << 20),
+    (6, 8 << 20),
+    (7, 16 << 20),
+    (8, 32 << 20),
+    (9, 64 << 20),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    Gzip(u32),
+    Zstd(i32),
+    Xz(u32),
+}
+impl Compression {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::Gzip(_) => "gzip",
+            Compression::Zstd(_) => "zstd",
+            Compression::Xz(_) => "xz",
+        }
+    }
+
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(GZIP_MAGIC) {
+            let level = match bytes.get(8) {
+                Some(2) => 9,
+                Some(4) => 1,
+                _ => GZIP_LEVEL,
+            };
+            Some(Compression::Gzip(level))
+        } else if bytes.starts_with(ZSTD_MAGIC) {
+            Some(Compression::Zstd(ZSTD_LEVEL))
+        } else if bytes.starts_with(XZ_MAGIC) {
+            Some(Compression::Xz(xz_level(bytes).unwrap_or(XZ_LEVEL)))
+        } else {
+            None
+        }
+    }
+
+    pub fn of_file(path: &Path) -> Option<Self> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        File::open(path)
+            .and_then(|f| f.take(HEADER_LEN as u64).read_to_end(&mut header))
+            .ok()?;
+        Self::detect(&header)
+    }
+
+    pub fn decompress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        match self {
+            Compression::Gzip(_) => MultiGzDecoder::new(bytes).read_to_end(&mut out)?,
+            Compression::Zstd(_) => return zstd::decode_all(bytes),
+            Compression::Xz(_) => XzDecoder::new_multi_decoder(bytes).read_to_end(&mut out)?,
+        };
+        Ok(out)
+    }
+
+    pub fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match *self {
+            Compression::Gzip(level) => {
+                let mut enc = GzEncoder::new(Vec::new(), flate2::Compression::new(level));
+                enc.write_all(bytes)?;
+                enc.finish()
+            }
+            Compression::Zstd(level) => zstd::encode_all(bytes, level),
+            Compression::Xz(level) => {
+                let mut enc = XzEncoder::new(Vec::new(), level);
+                enc.write_all(bytes)?;
+                enc.finish()
+            }
+        }
+    }
+}
+
+fn xz_level(bytes: &[u8]) -> Option<u32> {
+    let flags = *bytes.get(13)?;
+    if flags & 0x03 != 0 {
+        return None;
+    }
+    let mut i = 14;
+    for present in [flags & 0x40 != 0, flags & 0x80 != 0] {
+        if present {
+            i += bytes.get(i..)?.iter().position(|b| b & 0x80 == 0)? + 1;
+        }
+    }
+    let (id, len, bits) = (*bytes.get(i)?, *bytes.get(i + 1)?, *bytes.get(i + 2)?);
+    if id != XZ_LZMA2 || len != 1 || bits > 40 {
+        return None;
+    }
+    let dict = (2 | (bits & 1) as u64) << (bits / 2 + 11);
+    XZ_DICT_SIZES
+        .iter()
+        .find(|(_, size)| dict <= *size)
+        .map(|(level, _)| *level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &[u8] = b"line one\nline two\n\xffbinary\n";
+
+    fn detected(c: Compression) -> Option<Compression> {
+        Compression::detect(&c.compress(TEXT).unwrap())
+    }
+
+    #[test]
+    fn round_trip() {
+        for c in [
+            Compression::Gzip(GZIP_LEVEL),
+            Compression::Zstd(ZSTD_LEVEL),
+            Compression::Xz(XZ_LEVEL),
+        ] {
+            let packed = c.compress(TEXT).unwrap();
+            assert_ne!(packed, TEXT);
+            assert_eq!(c.decompress(&packed).unwrap(), TEXT, "{}", c.name());
+        }
+    }
+
+    #[test]
+    fn gzip_level_from_extra_flags() {
+        assert_eq!(detected(Compression::Gzip(9)), Some(Compression::Gzip(9)));
+        assert_eq!(detected(Compression::Gzip(1)), Some(Compression::Gzip(1)));
+        assert_eq!(detected(Compression::Gzip(5)), Some(Compression::Gzip(6)));
+    }
+
+    #[test]
+    fn xz_level_from_dictionary_size() {
+        for (level, found) in [(0, 0), (1, 1), (3, 4), (5, 6), (6, 6), (9, 9)] {
+            assert_eq!(
+                detected(Compression::Xz(level)),
+                Some(Compression::Xz(found))
+            );
+        }
+    }
+
+    #[test]
+    fn zstd_level_is_fixed() {
+        assert_eq!(detected(Compression::Zstd(19)), Some(Compression::Zstd(3)));
+    }
+
+    #[test]
+    fn magic_only() {
+        let junk = [GZIP_MAGIC, b"junk"].concat();
+        let c = Compression::detect(&junk).unwrap();
+        assert!(c.decompress(&junk).is_err());
+        assert_eq!(Compression::detect(b"plain"), None);
+    }
+}
@@ -1,27 +1,119 @@
 use std::{
+    cmp,
+    collections::VecDeque,
     env, fs,
     io::{self, Write},
-    path::{self, PathBuf},
-    time::Duration,
+    mem,
+    ops::Range,
+    path::{self, Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
 use crossterm::{
     cursor::{self, SetCursorStyle},
-    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    event::{
+        self, DisableFocusChange, EnableFocusChange, Event, KeyCode, KeyEvent, KeyEventKind,
+        KeyModifiers,
+    },
     execute,
     terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
 };
 
 use crate::{
-    out::{self, Direction, MessageLevel},
+    autocmd::{AutoCmds, Event as AuEvent},
+    compress::Compression,
+    encoding::{char_width, escaped, join_lines, split_lines, Encoding},
+    hex::{HexRows, BYTES_PER_LINE},
+    history::History,
+    indent::{self, fill, indent_len, indent_width},
+    keymap::{
+        keys_to_string, parse_keys, Action, Key, KeyMaps, MapMode, Resolution, Target, TIMEOUT_LEN,
+    },
+    large::{LargeRows, MIB},
+    mark::{self, GlobalMarks, Marks},
+    options::Options,
+    out::{self, Direction, MessageLevel, Popup, ScreenPos},
+    pattern::Pattern,
+    range::{LineRange, SortFlags},
+    register::Registers,
+    script::{Request, ScriptState, Scripts},
+    undo::Undo,
     TAB_SZ,
 };
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum Mode {
     Normal,
     Insert,
     Command,
+    Visual,
+    Replace,
+}
+const MAX_SCRIPT_DEPTH: usize = 16;
+
+const COMMANDS: &[&str] = &[
+    "autocmd", "cmap", "cnoremap", "copy", "cunmap", "delete", "e", "global", "hex", "imap",
+    "inoremap", "iunmap", "join", "let", "map", "marks", "move", "nmap", "nnoremap", "normal",
+    "noremap", "nunmap", "omap", "onoremap", "ounmap", "q", "q!", "read", "retab", "set", "sign",
+    "sort", "unmap", "vglobal", "vmap", "vnoremap", "vunmap", "w", "wq",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+    ShiftRight,
+    ShiftLeft,
+    Reindent,
+    Filter,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum MotionKind {
+    Exclusive,
+    Inclusive,
+    Linewise,
+}
+
+#[derive(Clone, Copy)]
+enum PendingChar {
+    Find(Action, Option<usize>),
+    Register(Option<usize>),
+    Object(bool, Option<usize>),
+    Record,
+    Execute(Option<usize>),
+    Mark,
+    GotoMark(bool),
+    Replace(Option<usize>),
+    InsertRegister,
+}
+
+#[derive(Clone)]
+enum Input {
+    Action(Action, Option<usize>),
+    Key(Key),
+}
+
+#[derive(Clone, Copy)]
+struct LastFind {
+    c: char,
+    forward: bool,
+    till: bool,
+}
+
+impl Mode {
+    pub fn name(&self) -> &str {
+        match self {
+            Mode::Normal => "normal",
+            Mode::Insert => "insert",
+            Mode::Command => "command",
+            Mode::Visual => "visual",
+            Mode::Replace => "replace",
+        }
+    }
 }
 
 #[derive(Default)]
@@ -59,94 +151,285 @@ impl ERow {
         self.render();
     }
 
+    pub fn replace_range(&mut self, range: Range<usize>, str: &str) {
+        self.raw.replace_range(range, str);
+        self.render();
+    }
+
     pub fn render(&mut self) {
-        let cap = self
-            .raw
-            .chars()
-            .fold(0, |acc, next| acc + if next == '\t' { TAB_SZ } else { 1 });
-        self.render = String::with_capacity(cap);
+        self.render = render_text(&self.raw);
+    }
+}
+
+pub fn render_text(raw: &str) -> String {
+    let cap = raw.chars().fold(0, |acc, next| {
+        acc + if next == '\t' {
+            TAB_SZ
+        } else {
+            char_width(next)
+        }
+    });
+    let mut render = String::with_capacity(cap);
 
-        let mut index = 0;
-        self.raw.chars().for_each(|c| {
+    let mut index = 0;
+    raw.chars().for_each(|c| {
+        if c == '\t' {
             index += 1;
-            if c == '\t' {
-                self.render.push(' ');
-                while index % TAB_SZ != 0 {
-                    self.render.push(' ');
-                    index += 1;
-                }
-            } else {
-                self.render.push(c);
+            render.push(' ');
+            while index % TAB_SZ != 0 {
+                render.push(' ');
+                index += 1;
             }
+        } else if let Some(code) = escaped(c) {
+            index += 4;
+            render.push_str(&format!("<{:02x}>", code));
+        } else {
+            index += 1;
+            render.push(c);
+        }
+    });
+    render
+}
+
+pub struct Sign {
+    pub line: usize,
+    pub text: String,
+    pub group: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct DiskStat {
+    mtime: Option<SystemTime>,
+    size: u64,
+    inode: u64,
+}
+impl DiskStat {
+    fn of(path: &Path) -> Option<Self> {
+        let meta = fs::metadata(path).ok()?;
+        Some(Self {
+            mtime: meta.modified().ok(),
+            size: meta.len(),
+            inode: inode(&meta),
         })
     }
 }
 
+#[derive(Default)]
 pub struct EditorRows {
     rows: Vec<ERow>,
+    signs: Vec<Sign>,
+    marks: Marks,
+    tracked: Vec<Vec<usize>>,
+    disk: Option<DiskStat>,
+    seen: Option<DiskStat>,
+    pub encoding: Encoding,
+    pub compression: Option<Compression>,
+    eol: bool,
+    crlf: bool,
     pub filename: Option<PathBuf>,
 }
 impl EditorRows {
-    fn new() -> io::Result<Self> {
-        let args: Vec<String> = env::args().collect();
-        match args.get(1) {
-            Some(p) => {
-                let path = path::absolute(p)?;
-                Ok(Self::from_file(path)?)
-            }
-            None => {
-                let first_line = ERow::default();
-                Ok(Self {
-                    rows: vec![first_line],
-                    filename: None,
-                })
-            }
+    fn new(path: Option<&str>) -> io::Result<Self> {
+        let first_line = ERow::default();
+        Ok(Self {
+            rows: vec![first_line],
+            filename: path.map(path::absolute).transpose()?,
+            ..Default::default()
+        })
+    }
+
+    #[cfg(test)]
+    pub fn from_lines(lines: &[&str]) -> Self {
+        Self {
+            rows: lines.iter().map(|&l| ERow::new(l.into())).collect(),
+            ..Default::default()
         }
     }
 
-    fn from_file(path: PathBuf) -> io::Result<Self> {
-        let contents = if path.try_exists()? {
-            fs::read_to_string(&path)?
+    fn from_file(path: PathBuf) -> io::Result<(Self, Vec<u8>)> {
+        let bytes = if path.try_exists()? {
+            fs::read(&path)?
         } else {
-            String::new()
+            Vec::new()
         };
-
-        let rows = if contents.is_empty() {
-            vec![ERow::new(String::new())]
-        } else {
-            contents.lines().map(|l| ERow::new(l.into())).collect()
+        // A file that only starts like a compressed one is read as it is.
+        let (bytes, compression) = match Compression::detect(&bytes) {
+            Some(c) => match c.decompress(&bytes) {
+                Ok(plain) => (plain, Some(c)),
+                Err(_) => (bytes, None),
+            },
+            None => (bytes, None),
         };
+        let mut rows = Self::unread(path);
+        rows.load_bytes(&bytes, Encoding::detect(&bytes));
+        rows.compression = compression;
+        Ok((rows, bytes))
+    }
 
-        Ok(Self {
-            rows,
+    fn unread(path: PathBuf) -> Self {
+        let disk = DiskStat::of(&path);
+        Self {
+            rows: vec![ERow::default()],
+            disk,
+            seen: disk,
             filename: Some(path),
-        })
+            ..Default::default()
+        }
+    }
+
+    fn load_bytes(&mut self, bytes: &[u8], encoding: Encoding) {
+        let contents = encoding.decode(bytes);
+        let (lines, eol, crlf) = split_lines(&contents);
+        self.rows = lines.into_iter().map(|l| ERow::new(l.into())).collect();
+        self.encoding = encoding;
+        self.eol = eol;
+        self.crlf = crlf;
     }
 
     fn set_filename(&mut self, name: &str) {
-        self.filename = Some(name.into());
+        let path = path::absolute(name).unwrap_or_else(|_| PathBuf::from(name));
+        if self.filename.as_ref() != Some(&path) {
+            self.disk = None;
+            self.seen = None;
+        }
+        self.filename = Some(path);
     }
 
     pub fn insert_erow(&mut self, i: usize, raw: String) {
         self.rows.insert(i, ERow::new(raw));
+        self.shift_lines(i, 1, 0);
     }
 
     pub fn delete_erow(&mut self, i: usize) {
         if i < self.rows.len() {
             self.rows.remove(i);
+            self.shift_lines(i, 0, 1);
+        }
+    }
+
+    pub fn replace_erows(&mut self, start: usize, removed: usize, lines: &[String]) {
+        self.rows.splice(
+            start..start + removed,
+            lines.iter().map(|l| ERow::new(l.clone())),
+        );
+        let kept = cmp::min(removed, lines.len());
+        self.shift_lines(start + kept, lines.len() - kept, removed - kept);
+    }
+
+    pub fn delete_erows(&mut self, start: usize, end: usize) {
+        let end = cmp::min(end, self.rows.len());
+        if start < end {
+            self.rows.drain(start..end);
+            self.shift_lines(start, 0, end - start);
+        }
+    }
+
+    fn shift_lines(&mut self, i: usize, added: usize, removed: usize) {
+        self.signs
+            .retain(|sign| sign.line < i || sign.line >= i + removed);
+        for sign in self.signs.iter_mut().filter(|sign| sign.line >= i) {
+            sign.line = sign.line + added - removed;
         }
+        self.marks.shift(i, added, removed);
+        for lines in &mut self.tracked {
+            lines.retain(|l| *l < i || *l >= i + removed);
+            for l in lines.iter_mut().filter(|l| **l >= i) {
+                *l = *l + added - removed;
+            }
+        }
+    }
+
+    pub fn get_mark(&self, name: char) -> Option<(usize, usize)> {
+        self.marks.get(name)
+    }
+
+    #[cfg(test)]
+    pub fn set_mark(&mut self, name: char, pos: (usize, usize)) {
+        self.marks.set(name, pos);
+    }
+
+    pub fn place_sign(&mut self, line: usize, text: &str, group: &str) {
+        self.signs.push(Sign {
+            line,
+            text: text.chars().take(2).collect(),
+            group: group.into(),
+        });
+    }
+
+    pub fn unplace_signs(&mut self, line: Option<usize>, group: Option<&str>) {
+        self.signs.retain(|sign| {
+            !(line.is_none_or(|l| l == sign.line) && group.is_none_or(|g| g == sign.group))
+        });
+    }
+
+    pub fn sign_at(&self, line: usize) -> Option<&Sign> {
+        self.signs.iter().rev().find(|sign| sign.line == line)
+    }
+
+    pub fn has_signs(&self) -> bool {
+        !self.signs.is_empty()
     }
 
     pub fn clear_erow(&mut self, i: usize) {
-        self.rows.get_mut(i).map(|r| r.clear());
+        if let Some(r) = self.rows.get_mut(i) {
+            r.clear();
+        }
     }
 
     pub fn join_adj_erows(&mut self, i: usize) {
         let curr_erow = self.rows.remove(i);
+        for sign in self.signs.iter_mut().filter(|sign| sign.line == i) {
+            sign.line = i - 1;
+        }
+        self.shift_lines(i, 0, 1);
         let prev_erow = self.get_erow_mut(i - 1);
         prev_erow.push_str(&curr_erow.raw);
     }
 
+    pub fn get_text(&self, (sx, sy): (usize, usize), (ex, ey): (usize, usize)) -> String {
+        if sy == ey {
+            return self.rows[sy].raw[sx..ex].to_string();
+        }
+        let mut text = self.rows[sy].raw[sx..].to_string();
+        for row in &self.rows[sy + 1..ey] {
+            text.push('\n');
+            text.push_str(&row.raw);
+        }
+        text.push('\n');
+        text.push_str(&self.rows[ey].raw[..ex]);
+        text
+    }
+
+    pub fn delete_text(&mut self, (sx, sy): (usize, usize), (ex, ey): (usize, usize)) {
+        if sy == ey {
+            self.rows[sy].replace_range(sx..ex, "");
+            return;
+        }
+        let tail = self.rows[ey].raw[ex..].to_string();
+        self.delete_erows(sy + 1, ey + 1);
+        let len = self.rows[sy].raw.len();
+        self.rows[sy].replace_range(sx..len, &tail);
+    }
+
+    pub fn insert_text(&mut self, (x, y): (usize, usize), text: &str) -> (usize, usize) {
+        let mut lines = text.split('\n');
+        let first = lines.next().unwrap_or_default();
+        let rest: Vec<&str> = lines.collect();
+        let Some((last, middle)) = rest.split_last() else {
+            self.rows[y].replace_range(x..x, first);
+            return (x + first.len(), y);
+        };
+        let len = self.rows[y].raw.len();
+        let tail = self.rows[y].raw[x..].to_string();
+        self.rows[y].replace_range(x..len, first);
+        for (i, line) in middle.iter().enumerate() {
+            self.insert_erow(y + 1 + i, line.to_string());
+        }
+        let end_y = y + rest.len();
+        self.insert_erow(end_y, format!("{}{}", last, tail));
+        (last.len(), end_y)
+    }
+
     pub fn get_raw(&self, i: usize) -> &str {
         &self.rows[i].raw
     }
@@ -172,268 +455,2099 @@ pub struct Editor {
     mode: Mode,
     output: out::Output,
     e_rows: EditorRows,
-    last_code: Option<KeyCode>,
+    keymaps: KeyMaps,
+    options: Options,
+    registers: Registers,
+    scripts: Scripts,
+    autocmds: AutoCmds,
+    autocmd_running: bool,
+    quit_requested: bool,
+    on_screen: bool,
+    last_cursor: (usize, usize),
+    last_tick: u64,
+    script_depth: usize,
+    typeahead: VecDeque<(Key, bool)>,
+    pending: Vec<(Key, bool)>,
+    pending_at: Instant,
+    pending_char: Option<PendingChar>,
+    count: Option<usize>,
+    op: Option<(Operator, Option<usize>)>,
+    register: Option<char>,
+    last_find: Option<LastFind>,
+    last_change: Vec<Input>,
+    change: Option<(Vec<Input>, u64)>,
+    insert_count: Option<usize>,
+    replaced: Vec<Option<char>>,
+    autoindent_line: Option<usize>,
+    insert_oneshot: bool,
+    completion: Option<(usize, String)>,
+    recording: Option<(char, Vec<Key>)>,
+    record_mark: usize,
+    last_macro: Option<char>,
+    global_marks: GlobalMarks,
+    history: History,
+    readonly: bool,
+    hex: Option<HexRows>,
+    large: Option<LargeRows>,
+    undo: Undo,
 }
 impl Editor {
     pub fn new() -> io::Result<Self> {
-        Ok(Self {
+        let mut path = None;
+        let mut readonly = false;
+        let mut hex = false;
+        for arg in env::args().skip(1) {
+            match arg.as_str() {
+                "-R" => readonly = true,
+                "--hex" => hex = true,
+                _ => path = Some(arg),
+            }
+        }
+        Ok(Self::with_output(
+            out::Output::new()?,
+            EditorRows::new(path.as_deref())?,
+            readonly,
+            hex,
+        ))
+    }
+
+    fn with_output(output: out::Output, e_rows: EditorRows, readonly: bool, hex: bool) -> Self {
+        let undo = Undo::new(e_rows.get_erows(), output.tick());
+        Self {
             mode: Mode::Normal,
-            output: out::Output::new()?,
-            e_rows: EditorRows::new()?,
-            last_code: None,
-        })
+            output,
+            e_rows,
+            keymaps: KeyMaps::new(),
+            options: Options::default(),
+            registers: Registers::default(),
+            scripts: Scripts::new(),
+            autocmds: AutoCmds::default(),
+            autocmd_running: false,
+            quit_requested: false,
+            on_screen: false,
+            last_cursor: (0, 0),
+            last_tick: 0,
+            script_depth: 0,
+            typeahead: VecDeque::new(),
+            pending: Vec::new(),
+            pending_at: Instant::now(),
+            pending_char: None,
+            count: None,
+            op: None,
+            register: None,
+            last_find: None,
+            last_change: Vec::new(),
+            change: None,
+            insert_count: None,
+            replaced: Vec::new(),
+            autoindent_line: None,
+            insert_oneshot: false,
+            completion: None,
+            recording: None,
+            record_mark: 0,
+            last_macro: None,
+            global_marks: mark::load_global(),
+            history: History::load(),
+            readonly,
+            hex: hex.then(HexRows::default),
+            large: None,
+            undo,
+        }
     }
     pub fn init(&mut self) -> io::Result<()> {
-        let mut stdout = io::stdout();
-        terminal::enable_raw_mode()?;
-        execute!(
-            stdout,
-            SetCursorStyle::BlinkingBlock,
-            EnterAlternateScreen,
-            cursor::MoveTo(0, 0)
-        )?;
-        self.output.render_screen(&self.e_rows, &self.mode)?;
-        Ok(())
+        self.enter_screen()?;
+        self.run_script(|scripts| scripts.load_plugins())?;
+        match self.e_rows.filename.clone() {
+            Some(path) => {
+                self.load_file(path)?;
+            }
+            None => {
+                self.options.detect_filetype(None);
+                self.options.readonly = self.readonly;
+            }
+        }
+        self.last_tick = self.output.tick();
+        self.render()
+    }
+
+    fn render(&mut self) -> io::Result<()> {
+        self.output.render_screen(
+            &self.e_rows,
+            self.hex.as_mut(),
+            self.large.as_mut(),
+            &self.mode,
+            &self.options,
+        )
     }
+
     pub fn poll(&mut self) -> io::Result<()> {
-        loop {
-            if event::poll(Duration::from_millis(500))? {
+        while !self.quit_requested {
+            let wait = if self.pending.is_empty() {
+                Duration::from_millis(500)
+            } else {
+                TIMEOUT_LEN.saturating_sub(self.pending_at.elapsed())
+            };
+            if event::poll(wait)? {
                 let event = event::read()?;
                 match event {
-                    Event::Key(KeyEvent {
-                        modifiers: KeyModifiers::CONTROL,
-                        kind: KeyEventKind::Press,
-                        code: KeyCode::Char('s'),
-                        ..
-                    }) => {
-                        self.save().map(|len| {
-                            self.output.set_stt_msg(
-                                &format!("{} bytes written to disk", len),
-                                MessageLevel::Normal,
-                            );
-                            self.output.dirty = 0;
-                        })?;
-                        self.output.render_screen(&self.e_rows, &self.mode)?;
-                    }
-                    Event::Key(KeyEvent {
-                        kind: KeyEventKind::Press,
-                        code: KeyCode::Up,
-                        ..
-                    }) => {
-                        self.output
-                            .move_cursor(Direction::Up, &self.e_rows, &self.mode);
-                        self.output.render_screen(&self.e_rows, &self.mode)?;
-                    }
-                    Event::Key(KeyEvent {
-                        kind: KeyEventKind::Press,
-                        code: KeyCode::Down,
-                        ..
-                    }) => {
-                        self.output
-                            .move_cursor(Direction::Down, &self.e_rows, &self.mode);
-                        self.output.render_screen(&self.e_rows, &self.mode)?;
-                    }
-                    Event::Key(KeyEvent {
-                        kind: KeyEventKind::Press,
-                        code: KeyCode::Left,
-                        ..
-                    }) => {
-                        self.output
-                            .move_cursor(Direction::Left, &self.e_rows, &self.mode);
-                        self.output.render_screen(&self.e_rows, &self.mode)?;
-                    }
-                    Event::Key(KeyEvent {
-                        kind: KeyEventKind::Press,
-                        code: KeyCode::Right,
-                        ..
-                    }) => {
-                        self.output
-                            .move_cursor(Direction::Right, &self.e_rows, &self.mode);
-                        self.output.render_screen(&self.e_rows, &self.mode)?;
-                    }
-                    Event::Key(KeyEvent {
-                        kind: KeyEventKind::Press,
-                        code,
-                        ..
-                    }) => {
-                        match self.mode {
-                            Mode::Normal => self.handle_normal_press(code)?,
-                            Mode::Insert => self.handle_insert_press(code)?,
-                            Mode::Command => {
-                                let q = self.handle_command_press(code)?;
-                                if q {
-                                    break;
-                                }
+                    Event::Key(
+                        key @ KeyEvent {
+                            kind: KeyEventKind::Press,
+                            ..
+                        },
+                    ) => {
+                        let key = Key::from(key);
+                        if let Some((_, keys)) = &mut self.recording {
+                            // Where the key sequence that may stop recording begins.
+                            if self.pending.is_empty() {
+                                self.record_mark = keys.len();
                             }
+                            keys.push(key.clone());
+                        }
+                        self.typeahead.push_back((key, true));
+                        self.pending_at = Instant::now();
+                        if self.process_keys(false)? {
+                            break;
                         }
-                        self.output.render_screen(&self.e_rows, &self.mode)?;
+                        self.sync_undo();
+                        self.fire_state_events()?;
+                        self.render()?;
+                    }
+                    Event::FocusGained if self.check_disk()? => {
+                        self.render()?;
+                    }
+                    Event::Resize(w, h) => {
+                        self.output.resize(w, h);
+                        self.fire(AuEvent::VimResized)?;
+                        self.render()?;
                     }
                     _ => continue,
                 }
+            } else if !self.pending.is_empty() && self.pending_at.elapsed() >= TIMEOUT_LEN {
+                if self.process_keys(true)? {
+                    break;
+                }
+                self.sync_undo();
+                self.fire_state_events()?;
+                self.render()?;
+            } else if (self.pending.is_empty() && self.check_disk()?)
+                || self.large.as_mut().is_some_and(|l| l.poll_progress())
+            {
+                self.render()?;
             }
         }
+        self.fire(AuEvent::VimLeavePre)?;
+        self.store_global_marks();
+        mark::save_global(&self.global_marks).ok();
+        self.history.save().ok();
         Ok(())
     }
 
-    fn handle_normal_press(&mut self, code: KeyCode) -> io::Result<()> {
-        match code {
-            KeyCode::Char(':') => {
-                self.change_mode(Mode::Command)?;
-                self.last_code = Some(code);
+    fn fire(&mut self, event: AuEvent) -> io::Result<()> {
+        if self.autocmd_running {
+            return Ok(());
+        }
+        let cmds = self
+            .autocmds
+            .matching(event, self.e_rows.filename.as_deref());
+
+        self.autocmd_running = true;
+        let mut res = Ok(false);
+        for cmd in &cmds {
+            res = self.exec_cmd_str(cmd);
+            if !matches!(res, Ok(false)) {
+                break;
             }
-            KeyCode::Up | KeyCode::Char('k') => {
-                self.output
-                    .move_cursor(Direction::Up, &self.e_rows, &self.mode);
-                self.last_code = Some(code);
+        }
+        self.autocmd_running = false;
+        // Events fire deep inside other commands, so a quit is picked up by
+        // the key loop instead of being returned through every caller.
+        self.quit_requested |= res?;
+        Ok(())
+    }
+
+    fn fire_state_events(&mut self) -> io::Result<()> {
+        if self.mode == Mode::Command {
+            return Ok(());
+        }
+        if self.output.cursor() != self.last_cursor {
+            self.fire(AuEvent::CursorMoved)?;
+            self.last_cursor = self.output.cursor();
+        }
+        if self.mode == Mode::Normal && self.output.tick() != self.last_tick {
+            self.fire(AuEvent::TextChanged)?;
+            self.last_tick = self.output.tick();
+        }
+        Ok(())
+    }
+
+    fn map_mode(&self) -> MapMode {
+        match self.mode {
+            Mode::Normal if self.op.is_some() => MapMode::Operator,
+            Mode::Normal => MapMode::Normal,
+            Mode::Insert | Mode::Replace => MapMode::Insert,
+            Mode::Command => MapMode::Command,
+            Mode::Visual => MapMode::Visual,
+        }
+    }
+
+    fn process_keys(&mut self, timed_out: bool) -> io::Result<bool> {
+        loop {
+            if self.pending_char.is_some() {
+                let Some((key, _)) = self.typeahead.pop_front() else {
+                    self.keymaps.reset_depth();
+                    return Ok(false);
+                };
+                if self.dispatch(Input::Key(key))? || self.quit_requested {
+                    return Ok(true);
+                }
+                continue;
             }
-            KeyCode::Down | KeyCode::Char('j') => {
-                self.output
-                    .move_cursor(Direction::Down, &self.e_rows, &self.mode);
-                self.last_code = Some(code);
+
+            let flush = timed_out && self.typeahead.is_empty();
+            if !flush || self.pending.is_empty() {
+                match self.typeahead.pop_front() {
+                    Some(key) => self.pending.push(key),
+                    None => {
+                        self.keymaps.reset_depth();
+                        return Ok(false);
+                    }
+                }
             }
-            KeyCode::Left | KeyCode::Char('h') => {
-                self.output
-                    .move_cursor(Direction::Left, &self.e_rows, &self.mode);
-                self.last_code = Some(code);
+            if matches!(self.mode, Mode::Normal | Mode::Visual) && self.pending.len() == 1 {
+                let digit = self.pending[0].0.as_char().and_then(|c| c.to_digit(10));
+                if let Some(d) = digit.filter(|d| *d > 0 || self.count.is_some()) {
+                    let count = self.count.unwrap_or(0).saturating_mul(10);
+                    self.count = Some(count.saturating_add(d as usize));
+                    self.pending.clear();
+                    continue;
+                }
             }
-            KeyCode::Right | KeyCode::Char('l') => {
-                self.output
-                    .move_cursor(Direction::Right, &self.e_rows, &self.mode);
 
-                self.last_code = Some(code);
+            let (len, target) = match self.keymaps.resolve(self.map_mode(), &self.pending, flush) {
+                Resolution::Pending => continue,
+                Resolution::Mapped(len, target) => (len, Some(target)),
+                Resolution::Unmapped => (1, None),
+            };
+            let rest = self.pending.split_off(len);
+            let keys = mem::take(&mut self.pending);
+            for key in rest.into_iter().rev() {
+                self.typeahead.push_front(key);
             }
-            KeyCode::Char('i') => {
-                self.change_mode(Mode::Insert)?;
-                self.last_code = Some(code);
+
+            let quit = match target {
+                Some(Target::Action(action)) => {
+                    let count = self.count.take();
+                    self.dispatch(Input::Action(action, count))?
+                }
+                Some(Target::Keys { rhs, noremap }) => {
+                    if !self.keymaps.enter_mapping() {
+                        self.typeahead.clear();
+                        self.output
+                            .set_cmd_msg("Recursive mapping", MessageLevel::Danger);
+                        continue;
+                    }
+                    for key in rhs.into_iter().rev() {
+                        self.typeahead.push_front((key, !noremap));
+                    }
+                    false
+                }
+                None => self.dispatch(Input::Key(keys[0].0.clone()))?,
+            };
+            if quit || self.quit_requested {
+                return Ok(true);
             }
-            KeyCode::Char('I') => {
-                self.change_mode(Mode::Insert)?;
-                self.output.goto_start_line(&self.e_rows);
-                self.last_code = Some(code);
+        }
+    }
+
+    fn sync_undo(&mut self) {
+        if self.idle() && self.hex.is_none() && self.large.is_none() {
+            let tick = self.output.tick();
+            self.undo
+                .sync(self.e_rows.get_erows(), tick, self.output.cursor());
+        }
+    }
+
+    fn undo_changes(&mut self, count: Option<usize>, redo: bool) {
+        self.sync_undo();
+        let mut cursor = None;
+        for _ in 0..count.unwrap_or(1) {
+            let change = match redo {
+                true => self.undo.redo(),
+                false => self.undo.undo(),
+            };
+            let Some(change) = change else {
+                let msg = match redo {
+                    true => "Already at newest change",
+                    false => "Already at oldest change",
+                };
+                self.output.set_cmd_msg(msg, MessageLevel::Danger);
+                break;
+            };
+            let (lines, removed) = match redo {
+                true => (&change.new, change.old.len()),
+                false => (&change.old, change.new.len()),
+            };
+            self.e_rows.replace_erows(change.start, removed, lines);
+            cursor = Some(change.cursor);
+        }
+        if let Some(cursor) = cursor {
+            self.output.set_cursor(cursor, &self.e_rows, &self.mode);
+            self.output.touch();
+            self.sync_undo();
+        }
+    }
+
+    fn idle(&self) -> bool {
+        self.mode == Mode::Normal
+            && self.op.is_none()
+            && self.pending_char.is_none()
+            && self.register.is_none()
+    }
+
+    fn dispatch(&mut self, input: Input) -> io::Result<bool> {
+        if self.idle() {
+            let ignored = matches!(
+                input,
+                Input::Action(
+                    Action::Repeat
+                        | Action::EnterCommand
+                        | Action::Save
+                        | Action::Undo
+                        | Action::Redo,
+                    _
+                )
+            );
+            self.change = (!ignored).then(|| (Vec::new(), self.output.tick()));
+        }
+        if let Some((inputs, _)) = &mut self.change {
+            inputs.push(input.clone());
+        }
+        let (tick, mode, oneshot) = (self.output.tick(), self.mode, self.insert_oneshot);
+        let quit = self.execute(input)?;
+        if tick != self.output.tick() {
+            self.e_rows.marks.set('.', self.output.cursor());
+        }
+        let inserting = |m| matches!(m, Mode::Insert | Mode::Replace);
+        if !inserting(mode) && inserting(self.mode) {
+            self.e_rows.marks.set('[', self.output.cursor());
+        }
+        if self.idle() {
+            if let Some((inputs, tick)) = self.change.take() {
+                if tick != self.output.tick() {
+                    self.last_change = inputs;
+                }
             }
-            KeyCode::Char('a') => {
+            if oneshot {
+                self.insert_oneshot = false;
                 self.change_mode(Mode::Insert)?;
-                self.output
-                    .move_cursor(Direction::Right, &self.e_rows, &Mode::Insert);
-                self.last_code = Some(code);
             }
-            KeyCode::Char('A') => {
-                self.change_mode(Mode::Insert)?;
-                self.output.goto_end_line(&self.e_rows, &self.mode);
-                self.last_code = Some(code);
+        }
+        Ok(quit)
+    }
+
+    fn execute(&mut self, input: Input) -> io::Result<bool> {
+        if !matches!(
+            input,
+            Input::Action(Action::CompleteNext | Action::CompletePrev, _)
+        ) {
+            self.completion = None;
+            self.output.popup = None;
+        }
+        if self.mode != Mode::Command && self.pending_char.is_none() {
+            if self.hex.is_some() {
+                return self.exec_hex(input);
             }
-            KeyCode::Char('o') => {
-                self.output.new_line(Direction::Down, &mut self.e_rows);
-                self.change_mode(Mode::Insert)?;
-                self.last_code = Some(code);
+            if self.large.is_some() {
+                return self.exec_large(input);
             }
-            KeyCode::Char('O') => {
-                self.output.new_line(Direction::Up, &mut self.e_rows);
-                self.change_mode(Mode::Insert)?;
-                self.last_code = Some(code);
-            }
-            KeyCode::Char('G') => {
-                self.output.goto_y(self.e_rows.num_rows() - 1);
-                self.last_code = Some(code);
-            }
-            KeyCode::Char('g') => {
-                if let Some(c) = self.last_code {
-                    match c {
-                        KeyCode::Char('g') => {
-                            self.output.goto_y(0);
-                            self.last_code = None;
-                        }
-                        _ => self.last_code = Some(code),
-                    }
-                } else {
-                    self.last_code = Some(code)
+        }
+        match input {
+            Input::Action(action, count) => self.exec_action(action, count),
+            Input::Key(key) => {
+                match self.pending_char.take() {
+                    Some(pending) => match key.as_char() {
+                        Some(c) => self.exec_pending_char(pending, c)?,
+                        None => self.cancel(),
+                    },
+                    None => self.exec_unmapped(&key),
                 }
+                Ok(false)
             }
-            KeyCode::Char('d') => {
-                if let Some(c) = self.last_code {
-                    match c {
-                        KeyCode::Char('d') => {
-                            self.output.delete_line(&mut self.e_rows);
-                            self.last_code = None;
-                        }
-                        _ => self.last_code = Some(code),
-                    }
-                } else {
-                    self.last_code = Some(code)
+        }
+    }
+
+    fn exec_hex(&mut self, input: Input) -> io::Result<bool> {
+        let (action, count) = match input {
+            Input::Action(action, count) => (action, count),
+            Input::Key(key) => {
+                let Some(hex) = self.hex.as_mut() else {
+                    return Ok(false);
+                };
+                match key.as_char() {
+                    Some(c) if self.mode == Mode::Replace => match hex.overwrite(c) {
+                        Ok(()) => self.output.touch(),
+                        Err(e) => self.output.set_cmd_msg(&e, MessageLevel::Danger),
+                    },
+                    _ => {}
                 }
+                return Ok(false);
             }
-            KeyCode::Char('x') => {
-                self.output.delete_char(&mut self.e_rows, &self.mode);
-                self.last_code = Some(code);
+        };
+        match action {
+            Action::EnterCommand
+            | Action::Cancel
+            | Action::Save
+            | Action::RecordMacro
+            | Action::ExecuteMacro => return self.exec_action(action, count),
+            Action::NormalMode => self.change_mode(Mode::Normal)?,
+            Action::Insert | Action::Append | Action::ReplaceMode
+                if self.mode == Mode::Normal && self.check_modifiable() =>
+            {
+                self.change_mode(Mode::Replace)?
             }
-            KeyCode::Char('w') => {
-                self.output.next_word(&self.e_rows, false);
-                self.last_code = Some(code);
+            _ => {}
+        }
+        let append = self.mode == Mode::Replace;
+        let (page, half) = (self.output.page_height(), self.output.half_page());
+        let Some(hex) = self.hex.as_mut() else {
+            return Ok(false);
+        };
+        let n = count.unwrap_or(1) as isize;
+        let line = BYTES_PER_LINE as isize;
+        match action {
+            Action::MoveLeft => hex.move_by(-n, append),
+            Action::MoveRight => hex.move_by(n, append),
+            Action::MoveUp | Action::DisplayUp => hex.move_by(-n * line, append),
+            Action::MoveDown | Action::DisplayDown => hex.move_by(n * line, append),
+            Action::PageUp => hex.move_by(-n * line * page as isize, append),
+            Action::PageDown => hex.move_by(n * line * page as isize, append),
+            Action::ScrollHalfUp => hex.move_by(-line * half as isize, append),
+            Action::ScrollHalfDown => hex.move_by(line * half as isize, append),
+            Action::GotoFirst => hex.goto(count.map_or(0, |c| (c - 1) * BYTES_PER_LINE), append),
+            Action::GotoLast => match count {
+                Some(c) => hex.goto((c - 1) * BYTES_PER_LINE, append),
+                None => hex.goto(usize::MAX, append),
+            },
+            Action::FirstColumn | Action::LineStart => hex.line_start(),
+            Action::LineEnd => hex.line_end(append),
+            Action::JumpNewer | Action::InsertTab => hex.toggle_pane(),
+            Action::Backspace if append => hex.back(),
+            Action::NormalMode => hex.goto(hex.cursor, false),
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    fn exec_large(&mut self, input: Input) -> io::Result<bool> {
+        let Input::Action(action, count) = input else {
+            return Ok(false);
+        };
+        match action {
+            Action::EnterCommand | Action::Cancel | Action::RecordMacro | Action::ExecuteMacro => {
+                return self.exec_action(action, count)
             }
-            KeyCode::Char('b') => {
-                self.output.prev_word(&self.e_rows, true);
-                self.last_code = Some(code);
+            Action::NormalMode => return self.change_mode(Mode::Normal).map(|_| false),
+            _ => {}
+        }
+        let (page, half) = (self.output.page_height(), self.output.half_page());
+        let Some(large) = self.large.as_mut() else {
+            return Ok(false);
+        };
+        let n = count.unwrap_or(1);
+        let y = large.cy;
+        match action {
+            Action::MoveLeft => large.move_x(-(n as isize)),
+            Action::MoveRight => large.move_x(n as isize),
+            Action::MoveUp | Action::DisplayUp => large.goto(y.saturating_sub(n)),
+            Action::MoveDown | Action::DisplayDown => large.goto(y.saturating_add(n)),
+            Action::PageUp => large.goto(y.saturating_sub(n * page)),
+            Action::PageDown => large.goto(y.saturating_add(n * page)),
+            Action::ScrollHalfUp => large.goto(y.saturating_sub(half)),
+            Action::ScrollHalfDown => large.goto(y.saturating_add(half)),
+            Action::GotoFirst => large.goto(count.map_or(0, |c| c - 1)),
+            Action::GotoLast => large.goto(count.map_or(usize::MAX, |c| c - 1)),
+            Action::FirstColumn | Action::LineStart => large.move_x(isize::MIN),
+            Action::LineEnd => large.move_x(isize::MAX),
+            _ => {}
+        }
+        if action == Action::GotoLast && count.is_none() && large.indexing() {
+            let msg = format!("Indexing lines, {}% done", large.progress());
+            self.output.set_cmd_msg(&msg, MessageLevel::Normal);
+        }
+        Ok(false)
+    }
+
+    fn toggle_hex(&mut self) {
+        match self.hex.take() {
+            Some(hex) => {
+                let y = hex.bytes()[..hex.cursor]
+                    .iter()
+                    .filter(|b| **b == b'\n')
+                    .count();
+                let encoding = Encoding::from_name(&self.options.fileencoding).unwrap_or_default();
+                self.e_rows.load_bytes(hex.bytes(), encoding);
+                self.output.set_cursor((0, y), &self.e_rows, &self.mode);
             }
-            KeyCode::Char('e') => {
-                if let Some(c) = self.last_code {
-                    match c {
-                        KeyCode::Char('g') => {
-                            self.output.prev_word(&self.e_rows, false);
-                            self.last_code = None;
-                        }
-                        _ => {
-                            self.last_code = Some(code);
-                            self.output.next_word(&self.e_rows, true);
-                        }
-                    }
-                } else {
-                    self.output.next_word(&self.e_rows, true)
+            None => {
+                let y = self.output.cursor().1;
+                let bytes = self.encode_rows(self.e_rows.num_rows(), self.e_rows.eol);
+                let offset = match y {
+                    0 => Ok(Vec::new()),
+                    y => self.encode_rows(y, true),
+                };
+                match (bytes, offset) {
+                    (Ok(bytes), Ok(offset)) => self.hex = Some(HexRows::new(bytes, offset.len())),
+                    (Err(e), _) | (_, Err(e)) => self
+                        .output
+                        .set_cmd_msg(&e.to_string(), MessageLevel::Danger),
                 }
             }
-            KeyCode::Char('_') => {
-                self.output.goto_start_line(&self.e_rows);
-                self.last_code = Some(code);
-            }
-            KeyCode::Char('$') => {
-                self.output.goto_end_line(&self.e_rows, &self.mode);
-                self.last_code = Some(code);
-            }
-            _ => {}
         }
-        Ok(())
     }
 
-    fn handle_insert_press(&mut self, code: KeyCode) -> io::Result<()> {
-        match code {
-            KeyCode::Esc => self.change_mode(Mode::Normal)?,
-            KeyCode::Char(c) => self.output.insert(&mut self.e_rows, c),
-            KeyCode::Tab => self.output.insert(&mut self.e_rows, '\t'),
-            KeyCode::Enter => self.output.break_line(&mut self.e_rows),
-            KeyCode::Backspace => self.output.delete_char(&mut self.e_rows, &self.mode),
-            _ => {}
+    fn repeat_change(&mut self, count: Option<usize>) -> io::Result<()> {
+        let mut inputs = self.last_change.clone();
+        if let Some(n) = count {
+            let mut first = true;
+            for input in &mut inputs {
+                if let Input::Action(action, c) = input {
+                    let counted = first && *action != Action::SelectRegister;
+                    *c = counted.then_some(n);
+                    first &= !counted;
+                }
+            }
+        }
+        for input in inputs {
+            self.dispatch(input)?;
         }
         Ok(())
     }
 
-    fn handle_command_press(&mut self, code: KeyCode) -> io::Result<bool> {
-        match code {
-            KeyCode::Esc => self.change_mode(Mode::Normal)?,
-            KeyCode::Char(c) => self.output.push_cmd(c),
-            KeyCode::Enter => {
-                let q = self.exec_cmd()?;
-                self.change_mode(Mode::Normal)?;
-                return Ok(q);
+    fn repeat_insert(&mut self) -> io::Result<()> {
+        let Some(n) = self.insert_count.take() else {
+            return Ok(());
+        };
+        let Some((inputs, _)) = &self.change else {
+            return Ok(());
+        };
+        let Some((Input::Action(entry, _), typed)) = inputs.split_first() else {
+            return Ok(());
+        };
+        let mut typed = typed.to_vec();
+        typed.pop();
+        if matches!(entry, Action::OpenBelow | Action::OpenAbove) {
+            typed.insert(0, Input::Action(*entry, None));
+        }
+        for _ in 1..n {
+            for input in typed.iter().cloned() {
+                self.execute(input)?;
             }
-            KeyCode::Backspace => self.output.delete_char(&mut self.e_rows, &self.mode),
-            _ => {}
         }
-        Ok(false)
+        Ok(())
     }
 
-    fn exec_cmd(&mut self) -> io::Result<bool> {
-        if let Some(it) = &self.output.get_cmd() {
-            let q = match it[..] {
-                ["q"] => {
+    fn exec_action(&mut self, action: Action, count: Option<usize>) -> io::Result<bool> {
+        if self.mode == Mode::Command && self.edit_cmdline(action) {
+            return Ok(false);
+        }
+        if matches!(
+            action,
+            Action::Insert
+                | Action::InsertStart
+                | Action::Append
+                | Action::AppendEnd
+                | Action::OpenBelow
+                | Action::OpenAbove
+                | Action::ReplaceMode
+        ) && self.mode == Mode::Normal
+        {
+            self.insert_count = count;
+        }
+        if matches!(self.mode, Mode::Normal | Mode::Visual)
+            && changes_text(action)
+            && !self.check_modifiable()
+        {
+            self.cancel();
+            return Ok(false);
+        }
+        let motion = self.motion_kind(action).is_some();
+        if self.op.is_some()
+            && !motion
+            && !matches!(
+                action,
+                Action::Delete
+                    | Action::Change
+                    | Action::Yank
+                    | Action::ShiftRight
+                    | Action::ShiftLeft
+                    | Action::Reindent
+                    | Action::Filter
+                    | Action::InnerObject
+                    | Action::AroundObject
+            )
+        {
+            self.cancel();
+            return Ok(false);
+        }
+        match action {
+            Action::EnterCommand => {
+                let visual = self.mode == Mode::Visual;
+                if visual {
+                    self.change_mode(Mode::Normal)?;
+                }
+                self.change_mode(Mode::Command)?;
+                if visual {
+                    "'<,'>".chars().for_each(|c| self.output.push_cmd(c));
+                }
+            }
+            Action::NormalMode => {
+                if matches!(self.mode, Mode::Insert | Mode::Replace) {
+                    self.repeat_insert()?;
+                    self.drop_auto_indent();
+                }
+                self.change_mode(Mode::Normal)?
+            }
+            Action::Repeat => self.repeat_change(count)?,
+            Action::Undo => self.undo_changes(count, false),
+            Action::Redo => self.undo_changes(count, true),
+            Action::Cancel => self.cancel(),
+            Action::Visual => self.toggle_visual(false)?,
+            Action::VisualLine => self.toggle_visual(true)?,
+            Action::VisualSwap => {
+                if let Some((anchor, linewise)) = self.output.visual {
+                    self.output.visual = Some((self.output.cursor(), linewise));
+                    self.output.set_cursor(anchor, &self.e_rows, &self.mode);
+                }
+            }
+            Action::RecordMacro => match self.recording.take() {
+                Some((name, mut keys)) => {
+                    keys.truncate(self.record_mark);
+                    self.registers.set(name, keys_to_string(&keys), false);
+                    self.output.recording = None;
+                }
+                None => self.pending_char = Some(PendingChar::Record),
+            },
+            Action::ExecuteMacro => self.pending_char = Some(PendingChar::Execute(count)),
+            Action::SetMark => self.pending_char = Some(PendingChar::Mark),
+            Action::GotoMark => self.pending_char = Some(PendingChar::GotoMark(false)),
+            Action::GotoMarkLine => self.pending_char = Some(PendingChar::GotoMark(true)),
+            Action::JumpOlder | Action::JumpNewer => {
+                let cursor = self.output.cursor();
+                let n = count.unwrap_or(1);
+                let pos = match action {
+                    Action::JumpOlder => self.e_rows.marks.jump_back(cursor, n),
+                    _ => self.e_rows.marks.jump_forward(n),
+                };
+                match pos {
+                    Some(pos) => self.output.set_cursor(pos, &self.e_rows, &self.mode),
+                    None => self.abort(),
+                }
+            }
+            Action::InnerObject => self.pending_char = Some(PendingChar::Object(false, count)),
+            Action::AroundObject => self.pending_char = Some(PendingChar::Object(true, count)),
+            Action::Insert => self.change_mode(Mode::Insert)?,
+            Action::ReplaceMode => self.change_mode(Mode::Replace)?,
+            Action::InsertNormal => {
+                self.insert_oneshot = true;
+                self.change_mode(Mode::Normal)?;
+            }
+            Action::InsertRegister => self.pending_char = Some(PendingChar::InsertRegister),
+            Action::DeleteWordBack | Action::DeleteLineBack => self.delete_back(action),
+            Action::CompleteNext => self.complete(true),
+            Action::CompletePrev => self.complete(false),
+            Action::ReplaceChar => self.pending_char = Some(PendingChar::Replace(count)),
+            Action::ToggleCase => self.toggle_case(count.unwrap_or(1)),
+            Action::JoinLines => self.join_lines(count, true),
+            Action::JoinLinesRaw => self.join_lines(count, false),
+            Action::Increment => self.increment(count.unwrap_or(1) as i64),
+            Action::Decrement => self.increment(-(count.unwrap_or(1) as i64)),
+            Action::InsertStart => {
+                self.change_mode(Mode::Insert)?;
+                self.output.goto_start_line(&self.e_rows);
+            }
+            Action::Append => {
+                self.change_mode(Mode::Insert)?;
+                self.output
+                    .move_cursor(Direction::Right, &self.e_rows, &Mode::Insert);
+            }
+            Action::AppendEnd => {
+                self.change_mode(Mode::Insert)?;
+                self.output.goto_end_line(&self.e_rows, &self.mode);
+            }
+            Action::OpenBelow => {
+                let width = self.new_line_indent(true);
+                self.output.new_line(Direction::Down, &mut self.e_rows);
+                self.auto_indent(width);
+                self.change_mode(Mode::Insert)?;
+            }
+            Action::OpenAbove => {
+                let width = self.new_line_indent(false);
+                self.output.new_line(Direction::Up, &mut self.e_rows);
+                self.auto_indent(width);
+                self.change_mode(Mode::Insert)?;
+            }
+            Action::Delete => self.start_operator(Operator::Delete, count)?,
+            Action::Change => self.start_operator(Operator::Change, count)?,
+            Action::Yank => self.start_operator(Operator::Yank, count)?,
+            Action::ShiftRight => self.start_operator(Operator::ShiftRight, count)?,
+            Action::ShiftLeft => self.start_operator(Operator::ShiftLeft, count)?,
+            Action::Reindent => self.start_operator(Operator::Reindent, count)?,
+            Action::Filter => self.start_operator(Operator::Filter, count)?,
+            Action::IndentMore | Action::IndentLess => {
+                let y = self.output.cursor().1;
+                let sw = self.options.shiftwidth();
+                let width = indent_width(self.e_rows.get_raw(y));
+                let width = match action {
+                    Action::IndentMore => (width / sw + 1) * sw,
+                    _ => width.saturating_sub(1) / sw * sw,
+                };
+                self.set_indent(y, width);
+                self.autoindent_line = None;
+            }
+            Action::DeleteLine => {
+                let y = self.output.cursor().1;
+                let end = cmp::min(y + count.unwrap_or(1), self.e_rows.num_rows()) - 1;
+                self.apply_operator(Operator::Delete, (0, y), (0, end), MotionKind::Linewise)?;
+            }
+            Action::DeleteChar if self.mode == Mode::Normal => {
+                let (x, y) = self.output.cursor();
+                let raw = self.e_rows.get_raw(y);
+                let end = raw[x..]
+                    .char_indices()
+                    .nth(count.unwrap_or(1))
+                    .map_or(raw.len(), |(i, _)| x + i);
+                self.apply_operator(Operator::Delete, (x, y), (end, y), MotionKind::Exclusive)?;
+            }
+            Action::Backspace if self.mode == Mode::Replace => match self.replaced.pop() {
+                Some(orig) => self.output.restore_char(&mut self.e_rows, orig),
+                None => self
+                    .output
+                    .move_cursor(Direction::Left, &self.e_rows, &self.mode),
+            },
+            Action::Backspace if self.mode == Mode::Insert && self.backspace_indent() => {}
+            Action::DeleteChar if self.mode == Mode::Insert => {
+                let (x, y) = self.output.cursor();
+                let raw = self.e_rows.get_raw(y);
+                if let Some(c) = raw[x..].chars().next() {
+                    self.e_rows
+                        .get_erow_mut(y)
+                        .replace_range(x..x + c.len_utf8(), "");
+                    self.output.touch();
+                } else if y + 1 < self.e_rows.num_rows() {
+                    self.e_rows.join_adj_erows(y + 1);
+                    self.output.touch();
+                }
+            }
+            Action::DeleteChar | Action::Backspace => {
+                self.output.delete_char(&mut self.e_rows, &self.mode)
+            }
+            Action::Paste => self.paste(false, count.unwrap_or(1)),
+            Action::PasteBefore => self.paste(true, count.unwrap_or(1)),
+            Action::SelectRegister => self.pending_char = Some(PendingChar::Register(count)),
+            Action::FindForward
+            | Action::FindBackward
+            | Action::TillForward
+            | Action::TillBackward => self.pending_char = Some(PendingChar::Find(action, count)),
+            Action::ScrollHalfDown
+            | Action::ScrollHalfUp
+            | Action::PageDown
+            | Action::PageUp
+            | Action::ScrollDown
+            | Action::ScrollUp => {
+                let (lines, with_cursor) = match action {
+                    Action::ScrollHalfDown | Action::ScrollHalfUp => {
+                        (count.unwrap_or(self.output.half_page()), true)
+                    }
+                    Action::PageDown | Action::PageUp => {
+                        let page = self.output.page(&self.e_rows, &self.options);
+                        (page * count.unwrap_or(1), false)
+                    }
+                    _ => (count.unwrap_or(1), false),
+                };
+                let lines = match action {
+                    Action::ScrollHalfUp | Action::PageUp | Action::ScrollUp => -(lines as isize),
+                    _ => lines as isize,
+                };
+                self.output.scroll_view(
+                    lines,
+                    with_cursor,
+                    &self.e_rows,
+                    &self.mode,
+                    &self.options,
+                );
+            }
+            Action::ScrollTop => {
+                self.output
+                    .align_view(ScreenPos::Top, &self.e_rows, &self.options)
+            }
+            Action::ScrollCenter => {
+                self.output
+                    .align_view(ScreenPos::Middle, &self.e_rows, &self.options)
+            }
+            Action::ScrollBottom => {
+                self.output
+                    .align_view(ScreenPos::Bottom, &self.e_rows, &self.options)
+            }
+            Action::InsertTab if self.mode == Mode::Replace => {
+                let orig = self.output.replace_char(&mut self.e_rows, '\t');
+                self.replaced.push(orig);
+            }
+            Action::InsertTab => self.insert_tab(),
+            Action::BreakLine => {
+                self.replaced.clear();
+                let width = self.new_line_indent(true);
+                self.drop_auto_indent();
+                self.output.break_line(&mut self.e_rows);
+                self.auto_indent(width);
+            }
+            Action::ExecCommand => {
+                self.history
+                    .add(self.output.cmd.as_deref().unwrap_or_default());
+                let q = self.exec_cmd()?;
+                if self.mode == Mode::Command {
+                    self.change_mode(Mode::Normal)?;
+                }
+                return Ok(q);
+            }
+            Action::Save => match self.save(false) {
+                Ok(len) => {
+                    self.output.set_stt_msg(
+                        &format!("{} bytes written to disk", len),
+                        MessageLevel::Normal,
+                    );
+                    self.output.dirty = 0;
+                }
+                Err(e) => self
+                    .output
+                    .set_cmd_msg(&e.to_string(), MessageLevel::Danger),
+            },
+            _ => self.exec_motion(action, count)?,
+        }
+        if self.op.is_none() && self.pending_char.is_none() {
+            self.register = None;
+        }
+        Ok(false)
+    }
+
+    fn exec_pending_char(&mut self, pending: PendingChar, c: char) -> io::Result<()> {
+        match pending {
+            PendingChar::Find(action, count) => {
+                let (forward, till) = match action {
+                    Action::FindForward => (true, false),
+                    Action::FindBackward => (false, false),
+                    Action::TillForward => (true, true),
+                    _ => (false, true),
+                };
+                self.last_find = Some(LastFind { c, forward, till });
+                self.exec_motion(action, count)?;
+                if self.op.is_none() {
+                    self.register = None;
+                }
+            }
+            PendingChar::Object(around, count) => {
+                self.select_object(c, around, count)?;
+                self.register = None;
+            }
+            PendingChar::Record => {
+                if c.is_ascii_alphanumeric() || c == '"' {
+                    self.recording = Some((c, Vec::new()));
+                    self.output.recording = Some(c);
+                } else {
+                    self.cancel();
+                }
+            }
+            PendingChar::Execute(count) => {
+                let name = if c == '@' { self.last_macro } else { Some(c) };
+                let Some(name) = name.filter(|n| n.is_ascii_alphanumeric() || *n == '"') else {
+                    self.cancel();
+                    return Ok(());
+                };
+                let Some(text) = self.registers.get(name).map(|r| r.text.clone()) else {
+                    self.output.set_cmd_msg(
+                        &format!("Nothing in register {}", name),
+                        MessageLevel::Danger,
+                    );
+                    return Ok(());
+                };
+                self.last_macro = Some(name);
+                let keys = match parse_keys(&text, &self.keymaps.leader) {
+                    Ok(keys) => keys,
+                    Err(_) => return Ok(()),
+                };
+                for _ in 0..count.unwrap_or(1) {
+                    for key in keys.iter().rev() {
+                        let key = match key.as_char() {
+                            Some('\n') => Key::Press(KeyCode::Enter, KeyModifiers::NONE),
+                            _ => key.clone(),
+                        };
+                        self.typeahead.push_front((key, true));
+                    }
+                }
+            }
+            PendingChar::Mark => match c {
+                'a'..='z' | 'A'..='Z' | '\'' | '`' | '[' | ']' | '<' | '>' => {
+                    let name = if c == '`' { '\'' } else { c };
+                    self.e_rows.marks.set(name, self.output.cursor());
+                    self.global_marks.remove(&name);
+                }
+                _ => self.cancel(),
+            },
+            PendingChar::Replace(count) => {
+                let (x, y) = self.output.cursor();
+                let n = count.unwrap_or(1);
+                let raw = self.e_rows.get_raw(y);
+                let Some(end) = raw[x..]
+                    .char_indices()
+                    .map(|(i, ch)| x + i + ch.len_utf8())
+                    .nth(n - 1)
+                else {
+                    self.abort();
+                    return Ok(());
+                };
+                self.e_rows
+                    .get_erow_mut(y)
+                    .replace_range(x..end, &c.to_string().repeat(n));
+                let last = x + (n - 1) * c.len_utf8();
+                self.output.set_cursor((last, y), &self.e_rows, &self.mode);
+                self.output.touch();
+            }
+            PendingChar::InsertRegister => {
+                let Some(text) = self.registers.get(c).map(|r| r.text.clone()) else {
+                    self.output
+                        .set_cmd_msg(&format!("Nothing in register {}", c), MessageLevel::Danger);
+                    return Ok(());
+                };
+                let end = self.e_rows.insert_text(self.output.cursor(), &text);
+                self.output.set_cursor(end, &self.e_rows, &self.mode);
+                self.output.touch();
+            }
+            PendingChar::GotoMark(linewise) => {
+                self.goto_mark(c, linewise)?;
+                self.register = None;
+            }
+            PendingChar::Register(count) => {
+                if c.is_ascii_alphanumeric() || "\"-_".contains(c) {
+                    self.register = Some(c);
+                    self.count = count;
+                } else {
+                    self.cancel();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn store_global_marks(&mut self) {
+        if let Some(file) = &self.e_rows.filename {
+            for (name, pos) in self.e_rows.marks.global() {
+                self.global_marks.insert(name, (file.clone(), pos));
+            }
+        }
+    }
+
+    fn adopt_global_marks(&mut self) {
+        let Some(file) = &self.e_rows.filename else {
+            return;
+        };
+        let marks = &mut self.e_rows.marks;
+        self.global_marks.retain(|name, (path, pos)| {
+            let here = path == file;
+            if here {
+                marks.set(*name, *pos);
+            }
+            !here
+        });
+    }
+
+    fn abort(&mut self) {
+        self.cancel();
+        self.typeahead.clear();
+    }
+
+    fn cancel(&mut self) {
+        self.count = None;
+        self.op = None;
+        self.register = None;
+        self.pending_char = None;
+    }
+
+    fn motion_kind(&self, action: Action) -> Option<MotionKind> {
+        let forward = self.last_find.is_some_and(|f| f.forward);
+        Some(match action {
+            Action::MoveUp
+            | Action::MoveDown
+            | Action::GotoFirst
+            | Action::GotoLast
+            | Action::ScreenTop
+            | Action::ScreenMiddle
+            | Action::ScreenBottom
+            | Action::GotoMarkLine => MotionKind::Linewise,
+            Action::NextWordEnd
+            | Action::NextBigWordEnd
+            | Action::PrevWordEnd
+            | Action::PrevBigWordEnd
+            | Action::LineEnd
+            | Action::DisplayLineEnd
+            | Action::FindForward
+            | Action::TillForward
+            | Action::MatchPair => MotionKind::Inclusive,
+            Action::RepeatFind if forward => MotionKind::Inclusive,
+            Action::RepeatFindReverse if !forward => MotionKind::Inclusive,
+            Action::MoveLeft
+            | Action::MoveRight
+            | Action::NextWord
+            | Action::NextBigWord
+            | Action::PrevWord
+            | Action::PrevBigWord
+            | Action::LineStart
+            | Action::FirstColumn
+            | Action::DisplayDown
+            | Action::DisplayUp
+            | Action::DisplayLineStart
+            | Action::FindBackward
+            | Action::TillBackward
+            | Action::RepeatFind
+            | Action::RepeatFindReverse
+            | Action::ParagraphForward
+            | Action::ParagraphBackward
+            | Action::SentenceForward
+            | Action::SentenceBackward
+            | Action::GotoMark => MotionKind::Exclusive,
+            _ => return None,
+        })
+    }
+
+    fn exec_motion(&mut self, action: Action, count: Option<usize>) -> io::Result<()> {
+        let op = self.op.take();
+        let count = match op {
+            Some((_, op_count)) => combine_counts(op_count, count),
+            None => count,
+        };
+        let (x, y) = self.output.cursor();
+        let on_blank = self.e_rows.get_raw(y)[x..]
+            .chars()
+            .next()
+            .is_none_or(|c| c.is_whitespace());
+        let change_word = matches!(op, Some((Operator::Change, _))) && !on_blank;
+        let action = match action {
+            Action::NextWord if change_word => Action::NextWordEnd,
+            Action::NextBigWord if change_word => Action::NextBigWordEnd,
+            _ => action,
+        };
+        let Some(kind) = self.motion_kind(action) else {
+            return Ok(());
+        };
+        let mode = if op.is_some() {
+            Mode::Insert
+        } else {
+            self.mode
+        };
+
+        let start = self.output.cursor();
+        let moved = match (action, count) {
+            (Action::GotoFirst | Action::GotoLast, Some(n)) => {
+                let y = cmp::min(n, self.e_rows.num_rows()).saturating_sub(1);
+                self.output.set_cursor((x, y), &self.e_rows, &mode);
+                1
+            }
+            (Action::ScreenTop | Action::ScreenMiddle | Action::ScreenBottom, _) => {
+                let pos = match action {
+                    Action::ScreenTop => ScreenPos::Top,
+                    Action::ScreenMiddle => ScreenPos::Middle,
+                    _ => ScreenPos::Bottom,
+                };
+                let offset = count.unwrap_or(1) - 1;
+                self.output
+                    .goto_screen_line(pos, offset, &self.e_rows, &self.options);
+                1
+            }
+            _ => {
+                let n = count.unwrap_or(1);
+                (0..n)
+                    .take_while(|i| {
+                        let op_end = op.is_some() && i + 1 == n;
+                        self.motion_once(action, mode, op_end, change_word && *i == 0)
+                    })
+                    .count()
+            }
+        };
+        if moved == 0 {
+            self.abort();
+        } else if let Some((op, _)) = op {
+            let end = self.output.cursor();
+            self.apply_operator(op, start, end, kind)?;
+        } else if matches!(
+            action,
+            Action::GotoFirst
+                | Action::GotoLast
+                | Action::MatchPair
+                | Action::ParagraphForward
+                | Action::ParagraphBackward
+                | Action::SentenceForward
+                | Action::SentenceBackward
+                | Action::ScreenTop
+                | Action::ScreenMiddle
+                | Action::ScreenBottom
+        ) {
+            self.set_jump(start);
+        }
+        Ok(())
+    }
+
+    fn set_jump(&mut self, from: (usize, usize)) {
+        self.e_rows.marks.set('\'', from);
+        self.e_rows.marks.push_jump(from);
+    }
+
+    fn goto_mark(&mut self, c: char, linewise: bool) -> io::Result<()> {
+        let name = if c == '`' { '\'' } else { c };
+        let mut pos = self.e_rows.marks.get(name);
+        if let Some((path, p)) = self.global_marks.get(&name).cloned() {
+            if self.op.is_some() || !self.load_file(path)? {
+                self.abort();
+                return Ok(());
+            }
+            pos = Some(p);
+        }
+        let Some(pos) = pos.filter(|(_, y)| *y < self.e_rows.num_rows()) else {
+            self.output
+                .set_cmd_msg("Mark not set", MessageLevel::Danger);
+            self.abort();
+            return Ok(());
+        };
+        let start = self.output.cursor();
+        if let Some((op, _)) = self.op.take() {
+            let kind = if linewise {
+                MotionKind::Linewise
+            } else {
+                MotionKind::Exclusive
+            };
+            return self.apply_operator(op, start, pos, kind);
+        }
+        self.output.set_cursor(pos, &self.e_rows, &self.mode);
+        if linewise {
+            self.output.goto_start_line(&self.e_rows);
+        }
+        self.set_jump(start);
+        Ok(())
+    }
+
+    fn load_file(&mut self, path: PathBuf) -> io::Result<bool> {
+        if self.output.dirty > 0 {
+            self.output.set_cmd_msg(
+                "Found unsaved changes, write them first",
+                MessageLevel::Danger,
+            );
+            return Ok(false);
+        }
+        let limit = self.options.largefile as u64 * MIB;
+        let large = self.hex.is_none()
+            && limit > 0
+            && fs::metadata(&path).is_ok_and(|meta| meta.is_file() && meta.len() > limit)
+            && Compression::of_file(&path).is_none();
+        let loaded = match large {
+            true => LargeRows::open(&path).map(|l| (EditorRows::unread(path), Some(l), Vec::new())),
+            false => EditorRows::from_file(path).map(|(rows, bytes)| (rows, None, bytes)),
+        };
+        let (rows, large, bytes) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                self.output
+                    .set_cmd_msg(&e.to_string(), MessageLevel::Danger);
+                return Ok(false);
+            }
+        };
+        self.store_global_marks();
+        self.e_rows = rows;
+        if let Some(large) = &large {
+            self.e_rows.encoding = large.encoding;
+        }
+        self.large = large;
+        self.adopt_global_marks();
+        self.output.set_cursor((0, 0), &self.e_rows, &self.mode);
+        self.options
+            .detect_filetype(self.e_rows.filename.as_deref());
+        self.options.fileencoding = self.e_rows.encoding.name().into();
+        self.options.readonly = self.readonly
+            || self.large.is_some()
+            || self
+                .e_rows
+                .filename
+                .as_deref()
+                .is_some_and(|path| !writable(path));
+        if self.hex.is_some() {
+            self.hex = Some(HexRows::new(bytes, 0));
+        }
+        self.fire(AuEvent::BufReadPost)?;
+        self.output.dirty = 0;
+        self.undo = Undo::new(self.e_rows.get_erows(), self.output.tick());
+        Ok(true)
+    }
+
+    fn motion_once(&mut self, action: Action, mode: Mode, op_end: bool, stop: bool) -> bool {
+        let before = self.output.cursor();
+        let rows = &self.e_rows;
+        let opts = &self.options;
+        match action {
+            Action::MoveUp => self.output.move_cursor(Direction::Up, rows, &mode),
+            Action::MoveDown => self.output.move_cursor(Direction::Down, rows, &mode),
+            Action::MoveLeft => self.output.move_cursor(Direction::Left, rows, &mode),
+            Action::MoveRight => self.output.move_cursor(Direction::Right, rows, &mode),
+            Action::GotoFirst => self.output.set_cursor((before.0, 0), rows, &mode),
+            Action::GotoLast => {
+                self.output
+                    .set_cursor((before.0, rows.num_rows() - 1), rows, &mode)
+            }
+            Action::NextWord
+            | Action::NextBigWord
+            | Action::NextWordEnd
+            | Action::NextBigWordEnd
+            | Action::PrevWord
+            | Action::PrevBigWord
+            | Action::PrevWordEnd
+            | Action::PrevBigWordEnd => {
+                let big = matches!(
+                    action,
+                    Action::NextBigWord
+                        | Action::NextBigWordEnd
+                        | Action::PrevBigWord
+                        | Action::PrevBigWordEnd
+                );
+                let pos = match action {
+                    Action::NextWord | Action::NextBigWord => {
+                        rows.word_forward(before, big, opts, op_end)
+                    }
+                    Action::NextWordEnd | Action::NextBigWordEnd => {
+                        rows.word_end(before, big, opts, stop)
+                    }
+                    Action::PrevWord | Action::PrevBigWord => rows.word_backward(before, big, opts),
+                    _ => rows.word_end_backward(before, big, opts),
+                };
+                match pos {
+                    Some(pos) => self.output.set_cursor(pos, rows, &mode),
+                    None => return false,
+                }
+            }
+            Action::LineStart => self.output.goto_start_line(rows),
+            Action::LineEnd => self.output.goto_end_line(rows, &mode),
+            Action::FirstColumn => self.output.set_cursor((0, before.1), rows, &mode),
+            Action::DisplayDown => {
+                self.output
+                    .move_display_line(Direction::Down, rows, &mode, &self.options)
+            }
+            Action::DisplayUp => {
+                self.output
+                    .move_display_line(Direction::Up, rows, &mode, &self.options)
+            }
+            Action::DisplayLineStart => {
+                self.output
+                    .goto_display_edge(false, rows, &mode, &self.options)
+            }
+            Action::DisplayLineEnd => {
+                self.output
+                    .goto_display_edge(true, rows, &mode, &self.options)
+            }
+            Action::FindForward
+            | Action::FindBackward
+            | Action::TillForward
+            | Action::TillBackward
+            | Action::RepeatFind
+            | Action::RepeatFindReverse => {
+                let Some(find) = self.last_find else {
+                    return false;
+                };
+                let (forward, skip) = match action {
+                    Action::FindForward | Action::TillForward => (true, false),
+                    Action::FindBackward | Action::TillBackward => (false, false),
+                    Action::RepeatFind => (find.forward, true),
+                    _ => (!find.forward, true),
+                };
+                return self
+                    .output
+                    .find_char(rows, find.c, forward, find.till, skip);
+            }
+            Action::MatchPair => return self.output.goto_match(rows),
+            Action::ParagraphForward | Action::ParagraphBackward => {
+                let pos = rows.paragraph(before.1, action == Action::ParagraphForward);
+                self.output.set_cursor(pos, rows, &mode);
+            }
+            Action::SentenceForward | Action::SentenceBackward => {
+                let pos = rows.sentence(before, action == Action::SentenceForward);
+                self.output.set_cursor(pos, rows, &mode);
+            }
+            _ => return false,
+        }
+        let can_fail = matches!(
+            action,
+            Action::MoveUp
+                | Action::MoveDown
+                | Action::MoveLeft
+                | Action::MoveRight
+                | Action::DisplayDown
+                | Action::DisplayUp
+                | Action::ParagraphForward
+                | Action::ParagraphBackward
+                | Action::SentenceForward
+                | Action::SentenceBackward
+        );
+        !can_fail || self.output.cursor() != before
+    }
+
+    fn toggle_visual(&mut self, linewise: bool) -> io::Result<()> {
+        match self.output.visual {
+            Some((_, current)) if current == linewise => self.change_mode(Mode::Normal),
+            Some((anchor, _)) => {
+                self.output.visual = Some((anchor, linewise));
+                self.change_mode(Mode::Visual)
+            }
+            None => {
+                self.output.visual = Some((self.output.cursor(), linewise));
+                self.change_mode(Mode::Visual)
+            }
+        }
+    }
+
+    fn select_object(&mut self, c: char, around: bool, count: Option<usize>) -> io::Result<()> {
+        let op = self.op.take();
+        let count = match op {
+            Some((_, op_count)) => combine_counts(op_count, count),
+            None => count,
+        }
+        .unwrap_or(1);
+        let pos = self.output.cursor();
+        let object = |rows: &EditorRows, p, n| rows.text_object(p, c, around, n, &self.options);
+
+        if let Some((op, _)) = op {
+            match object(&self.e_rows, pos, count) {
+                Some(((s, e), true)) => {
+                    self.apply_operator(op, s, e, MotionKind::Linewise)?;
+                }
+                Some(((s, e), false)) => self.apply_operator(op, s, e, MotionKind::Exclusive)?,
+                None => self.abort(),
+            }
+            return Ok(());
+        }
+
+        let (Some((s, e)), Some((_, linewise))) = (self.output.selection(), self.output.visual)
+        else {
+            return Ok(());
+        };
+        let key = |(x, y): (usize, usize)| (y, x);
+        let rows = &self.e_rows;
+        let object = |p, n| {
+            let ((os, oe), lw) = object(rows, p, n)?;
+            Some(if lw || key(oe) <= key(os) {
+                (os, oe, lw)
+            } else if os.0 == 0 && oe.0 == 0 && oe.1 > os.1 {
+                (os, (0, oe.1 - 1), true)
+            } else {
+                (os, rows.prev_pos(oe).unwrap_or(oe), false)
+            })
+        };
+        let extend = s != e && pos == e && "wWsp".contains(c);
+        let found = if s == e || "\"'`".contains(c) {
+            object(pos, count)
+        } else if extend {
+            let next = if c == 'p' {
+                (pos.1 + 1 < rows.num_rows()).then_some((0, pos.1 + 1))
+            } else {
+                rows.next_pos(pos).map(|(p, _)| p)
+            };
+            next.and_then(|next| object(next, count))
+                .map(|(_, last, lw)| (s, last, lw))
+        } else {
+            // Word and paragraph objects stop growing at the buffer edges
+            // instead of returning None, so stop once a count adds nothing.
+            let mut prev = None;
+            (count..)
+                .map_while(|n| {
+                    let (os, last, lw) = object(pos, n)?;
+                    (prev.replace((os, last)) != Some((os, last))).then_some((os, last, lw))
+                })
+                .find(|(os, last, _)| key(*os) < key(s) || key(*last) > key(e))
+        };
+        let Some((os, last, object_linewise)) = found else {
+            return Ok(());
+        };
+        let anchor = if extend { s } else { os };
+        self.output.visual = Some((anchor, linewise || object_linewise));
+        self.output.set_cursor(last, &self.e_rows, &self.mode);
+        if object_linewise && !linewise {
+            self.change_mode(Mode::Visual)?;
+        }
+        Ok(())
+    }
+
+    fn start_operator(&mut self, op: Operator, count: Option<usize>) -> io::Result<()> {
+        if self.mode == Mode::Visual {
+            let (Some((s, e)), Some((_, linewise))) = (self.output.selection(), self.output.visual)
+            else {
+                return Ok(());
+            };
+            self.change_mode(Mode::Normal)?;
+            let kind = if linewise {
+                MotionKind::Linewise
+            } else {
+                MotionKind::Inclusive
+            };
+            return self.apply_operator(op, s, e, kind);
+        }
+        match self.op.take() {
+            None => self.op = Some((op, count)),
+            Some((pending, pending_count)) if pending == op => {
+                let n = combine_counts(pending_count, count).unwrap_or(1);
+                let y = self.output.cursor().1;
+                let end = cmp::min(y + n, self.e_rows.num_rows()) - 1;
+                self.apply_operator(op, (0, y), (0, end), MotionKind::Linewise)?;
+            }
+            Some(_) => self.cancel(),
+        }
+        Ok(())
+    }
+
+    fn apply_operator(
+        &mut self,
+        op: Operator,
+        start: (usize, usize),
+        end: (usize, usize),
+        kind: MotionKind,
+    ) -> io::Result<()> {
+        let (s, mut e) = if (start.1, start.0) <= (end.1, end.0) {
+            (start, end)
+        } else {
+            (end, start)
+        };
+        if matches!(
+            op,
+            Operator::ShiftRight | Operator::ShiftLeft | Operator::Reindent
+        ) {
+            self.shift_lines(op, s.1, e.1);
+            return Ok(());
+        }
+        if op == Operator::Filter {
+            self.output.set_cursor(s, &self.e_rows, &self.mode);
+            self.change_mode(Mode::Command)?;
+            let range = match e.1 - s.1 {
+                0 => ".!".to_string(),
+                n => format!(".,.+{}!", n),
+            };
+            range.chars().for_each(|c| self.output.push_cmd(c));
+            return Ok(());
+        }
+        let mut kind = kind;
+        self.e_rows.marks.set('[', s);
+        self.e_rows
+            .marks
+            .set(']', if op == Operator::Yank { e } else { s });
+        if kind == MotionKind::Exclusive && e.0 == 0 && e.1 > s.1 {
+            let raw = self.e_rows.get_raw(s.1);
+            let indent = raw.len() - raw.trim_start().len();
+            e = (self.e_rows.get_raw(e.1 - 1).len(), e.1 - 1);
+            if s.0 <= indent {
+                kind = MotionKind::Linewise;
+            }
+        }
+
+        if kind == MotionKind::Linewise {
+            let text: String = (s.1..=e.1)
+                .map(|y| format!("{}\n", self.e_rows.get_raw(y)))
+                .collect();
+            self.store_register(text, true, op == Operator::Yank);
+            match op {
+                Operator::Delete => {
+                    self.e_rows.delete_erows(s.1, e.1 + 1);
+                    if self.e_rows.num_rows() == 0 {
+                        self.e_rows.insert_erow(0, String::new());
+                    }
+                    self.output.set_cursor((0, s.1), &self.e_rows, &self.mode);
+                    self.output.goto_start_line(&self.e_rows);
+                    self.output.touch();
+                }
+                Operator::Change => {
+                    self.e_rows.delete_erows(s.1 + 1, e.1 + 1);
+                    self.e_rows.clear_erow(s.1);
+                    self.output
+                        .set_cursor((0, s.1), &self.e_rows, &Mode::Insert);
+                    self.output.touch();
+                    self.change_mode(Mode::Insert)?;
+                }
+                _ => self.output.set_cursor(s, &self.e_rows, &self.mode),
+            }
+            return Ok(());
+        }
+
+        if kind == MotionKind::Inclusive {
+            let raw = self.e_rows.get_raw(e.1);
+            e.0 = raw[e.0..]
+                .chars()
+                .next()
+                .map_or(e.0, |c| e.0 + c.len_utf8());
+        }
+        if s != e {
+            let text = self.e_rows.get_text(s, e);
+            self.store_register(text, false, op == Operator::Yank);
+        }
+        match op {
+            Operator::Delete => {
+                if s != e {
+                    self.e_rows.delete_text(s, e);
+                    self.output.touch();
+                }
+                self.output.set_cursor(s, &self.e_rows, &self.mode);
+            }
+            Operator::Change => {
+                self.e_rows.delete_text(s, e);
+                self.output.set_cursor(s, &self.e_rows, &Mode::Insert);
+                self.output.touch();
+                self.change_mode(Mode::Insert)?;
+            }
+            _ => self.output.set_cursor(s, &self.e_rows, &self.mode),
+        }
+        Ok(())
+    }
+
+    fn shift_lines(&mut self, op: Operator, start: usize, end: usize) {
+        let sw = self.options.shiftwidth();
+        for y in start..=end {
+            let raw = self.e_rows.get_raw(y);
+            if raw.trim().is_empty() {
+                if op == Operator::Reindent && !raw.is_empty() {
+                    self.e_rows.clear_erow(y);
+                    self.output.touch();
+                }
+                continue;
+            }
+            let width = indent_width(raw);
+            let width = match op {
+                Operator::ShiftRight => width + sw,
+                Operator::ShiftLeft => width.saturating_sub(sw),
+                _ => {
+                    let prev = (0..y)
+                        .rev()
+                        .map(|i| self.e_rows.get_raw(i))
+                        .find(|l| !l.trim().is_empty());
+                    let width = prev.map_or(0, |p| {
+                        let opens = indent::opens_block(p, &self.options.filetype);
+                        indent_width(p) + if opens { sw } else { 0 }
+                    });
+                    if indent::closes_block(raw) {
+                        width.saturating_sub(sw)
+                    } else {
+                        width
+                    }
+                }
+            };
+            self.set_indent(y, width);
+        }
+        self.e_rows.marks.set('[', (0, start));
+        self.e_rows.marks.set(']', (0, end));
+        self.output.set_cursor((0, start), &self.e_rows, &self.mode);
+        self.output.goto_start_line(&self.e_rows);
+    }
+
+    fn set_indent(&mut self, y: usize, width: usize) {
+        let raw = self.e_rows.get_raw(y);
+        let old = indent_len(raw);
+        let new = fill(0, width, self.options.expandtab);
+        if raw[..old] == new {
+            return;
+        }
+        self.e_rows.get_erow_mut(y).replace_range(0..old, &new);
+        let (x, cy) = self.output.cursor();
+        if cy == y {
+            let x = if x >= old {
+                x - old + new.len()
+            } else {
+                new.len()
+            };
+            self.output.set_cursor((x, y), &self.e_rows, &self.mode);
+        }
+        self.output.touch();
+    }
+
+    fn new_line_indent(&self, below: bool) -> Option<usize> {
+        if !self.options.autoindent && !self.options.smartindent {
+            return None;
+        }
+        let raw = self.e_rows.get_raw(self.output.cursor().1);
+        let width = indent_width(raw);
+        let opens =
+            indent::opens_block(raw, &self.options.filetype) || indent::starts_with_cinword(raw);
+        if below && self.options.smartindent && opens {
+            return Some(width + self.options.shiftwidth());
+        }
+        Some(width)
+    }
+
+    fn auto_indent(&mut self, width: Option<usize>) {
+        let Some(mut width) = width else {
+            return;
+        };
+        let y = self.output.cursor().1;
+        if self.options.smartindent && indent::closes_block(self.e_rows.get_raw(y)) {
+            width = width.saturating_sub(self.options.shiftwidth());
+        }
+        self.set_indent(y, width);
+        let x = indent_len(self.e_rows.get_raw(y));
+        self.output.set_cursor((x, y), &self.e_rows, &Mode::Insert);
+        self.autoindent_line = Some(y);
+    }
+
+    fn drop_auto_indent(&mut self) {
+        let y = self.output.cursor().1;
+        if self.autoindent_line.take() == Some(y) && self.e_rows.get_raw(y).trim().is_empty() {
+            self.e_rows.clear_erow(y);
+            self.output.set_cursor((0, y), &self.e_rows, &self.mode);
+        }
+    }
+
+    fn indent_closing_brace(&mut self) {
+        let (x, y) = self.output.cursor();
+        let raw = self.e_rows.get_raw(y);
+        if !raw[..x - 1].trim().is_empty() {
+            return;
+        }
+        if let Some((_, open)) = self.e_rows.match_pair((x - 1, y), 0..y + 1) {
+            let width = indent_width(self.e_rows.get_raw(open));
+            self.set_indent(y, width);
+        }
+    }
+
+    fn insert_tab(&mut self) {
+        let (x, y) = self.output.cursor();
+        let (et, sts) = (self.options.expandtab, self.options.softtabstop);
+        if !et && sts == 0 {
+            self.output.insert(&mut self.e_rows, '\t');
+            return;
+        }
+        let raw = self.e_rows.get_raw(y);
+        let step = if sts > 0 { sts } else { TAB_SZ };
+        let target = (indent::width(&raw[..x], 0) / step + 1) * step;
+        let start = if et {
+            x
+        } else {
+            raw[..x].trim_end_matches([' ', '\t']).len()
+        };
+        let text = fill(indent::width(&raw[..start], 0), target, et);
+        self.e_rows.get_erow_mut(y).replace_range(start..x, &text);
+        self.output
+            .set_cursor((start + text.len(), y), &self.e_rows, &self.mode);
+        self.output.touch();
+    }
+
+    fn backspace_indent(&mut self) -> bool {
+        let (x, y) = self.output.cursor();
+        let (et, sts) = (self.options.expandtab, self.options.softtabstop);
+        let before = &self.e_rows.get_raw(y)[..x];
+        if x == 0 || !before.trim().is_empty() || (!et && sts == 0) {
+            return false;
+        }
+        let step = if sts > 0 {
+            sts
+        } else {
+            self.options.shiftwidth()
+        };
+        let width = (indent::width(before, 0) - 1) / step * step;
+        let text = fill(0, width, et);
+        self.e_rows.get_erow_mut(y).replace_range(0..x, &text);
+        self.output
+            .set_cursor((text.len(), y), &self.e_rows, &self.mode);
+        self.output.touch();
+        true
+    }
+
+    fn delete_back(&mut self, action: Action) {
+        let (x, y) = self.output.cursor();
+        if x == 0 {
+            self.output.delete_char(&mut self.e_rows, &self.mode);
+            return;
+        }
+        let raw = self.e_rows.get_raw(y);
+        let start = if action == Action::DeleteLineBack {
+            let indent = indent_len(raw);
+            if x > indent {
+                indent
+            } else {
+                0
+            }
+        } else {
+            let before = raw[..x].trim_end();
+            let keyword = before
+                .chars()
+                .next_back()
+                .map(|c| self.options.is_keyword(c));
+            let word = before
+                .char_indices()
+                .rev()
+                .take_while(|(_, c)| {
+                    !c.is_whitespace() && Some(self.options.is_keyword(*c)) == keyword
+                })
+                .last();
+            word.map_or(before.len(), |(i, _)| i)
+        };
+        self.e_rows.get_erow_mut(y).replace_range(start..x, "");
+        self.output.set_cursor((start, y), &self.e_rows, &self.mode);
+        self.output.touch();
+    }
+
+    fn complete(&mut self, forward: bool) {
+        let (x, y) = self.output.cursor();
+        let x = match self.mode {
+            Mode::Command => self.output.cmd_cursor(),
+            _ => x,
+        };
+        if self.completion.is_none() {
+            let (start, items) = match self.mode {
+                Mode::Command => {
+                    let cmd = self.output.cmd.clone().unwrap_or_default();
+                    self.cmdline_matches(&cmd[..x])
+                }
+                _ => {
+                    let raw = self.e_rows.get_raw(y);
+                    let start = raw[..x]
+                        .char_indices()
+                        .rev()
+                        .take_while(|(_, c)| self.options.is_keyword(*c))
+                        .last()
+                        .map_or(x, |(i, _)| i);
+                    (start, self.keyword_matches(&raw[start..x], (start, y), x))
+                }
+            };
+            if items.is_empty() {
+                self.output
+                    .set_cmd_msg("Pattern not found", MessageLevel::Danger);
+                return;
+            }
+            let original = match self.mode {
+                Mode::Command => self.output.cmd.as_deref().unwrap_or_default()[start..x].into(),
+                _ => self.e_rows.get_raw(y)[start..x].into(),
+            };
+            self.completion = Some((start, original));
+            self.output.popup = Some(Popup {
+                items,
+                selected: None,
+                col: start,
+            });
+        }
+        let (Some((start, original)), Some(popup)) = (&self.completion, &mut self.output.popup)
+        else {
+            return;
+        };
+        let n = popup.items.len();
+        popup.selected = match (popup.selected, forward) {
+            (None, true) => Some(0),
+            (None, false) => Some(n - 1),
+            (Some(i), true) => (i + 1 < n).then_some(i + 1),
+            (Some(i), false) => i.checked_sub(1),
+        };
+        let text = popup.selected.map_or(original, |i| &popup.items[i]).clone();
+        let start = *start;
+        if self.mode == Mode::Command {
+            if let Some(cmd) = &mut self.output.cmd {
+                cmd.replace_range(start..x, &text);
+            }
+            self.output.set_cmd_cursor(start + text.len());
+            if n == 1 {
+                self.completion = None;
+                self.output.popup = None;
+            }
+            return;
+        }
+        self.e_rows.get_erow_mut(y).replace_range(start..x, &text);
+        self.output
+            .set_cursor((start + text.len(), y), &self.e_rows, &self.mode);
+        self.output.touch();
+    }
+
+    fn cmdline_matches(&self, line: &str) -> (usize, Vec<String>) {
+        let Some((head, word)) = line.rsplit_once(' ') else {
+            let mut names: Vec<String> = COMMANDS
+                .iter()
+                .map(|c| c.to_string())
+                .chain(self.scripts.command_names().cloned())
+                .filter(|c| c.starts_with(line))
+                .collect();
+            names.sort();
+            names.dedup();
+            return (0, names);
+        };
+        let start = head.len() + 1;
+        let items = match line.split(' ').next().unwrap_or_default() {
+            "set" | "se" => self.options.complete(word),
+            "w" | "w!" | "wq" | "e" | "e!" | "r" => complete_path(word),
+            _ => Vec::new(),
+        };
+        (start, items)
+    }
+
+    fn edit_cmdline(&mut self, action: Action) -> bool {
+        let cmd = self.output.cmd.clone().unwrap_or_default();
+        let i = self.output.cmd_cursor();
+        match action {
+            Action::MoveLeft => {
+                let prev = cmd[..i].char_indices().last().map_or(0, |(p, _)| p);
+                self.output.set_cmd_cursor(prev);
+            }
+            Action::MoveRight => {
+                let next = cmd[i..].chars().next().map_or(i, |c| i + c.len_utf8());
+                self.output.set_cmd_cursor(next);
+            }
+            Action::FirstColumn => self.output.set_cmd_cursor(0),
+            Action::LineEnd => self.output.set_cmd_cursor(cmd.len()),
+            Action::DeleteWordBack | Action::DeleteLineBack => {
+                let start = match action {
+                    Action::DeleteWordBack => {
+                        let before = cmd[..i].trim_end();
+                        let keyword = before
+                            .chars()
+                            .next_back()
+                            .map(|c| self.options.is_keyword(c));
+                        before
+                            .char_indices()
+                            .rev()
+                            .take_while(|(_, c)| {
+                                !c.is_whitespace() && Some(self.options.is_keyword(*c)) == keyword
+                            })
+                            .last()
+                            .map_or(before.len(), |(p, _)| p)
+                    }
+                    _ => 0,
+                };
+                if let Some(cmd) = &mut self.output.cmd {
+                    cmd.replace_range(start..i, "");
+                }
+                self.output.set_cmd_cursor(start);
+            }
+            Action::MoveUp | Action::MoveDown => {
+                let entry = match action {
+                    Action::MoveUp => self.history.older(&cmd),
+                    _ => self.history.newer(),
+                };
+                if let Some(entry) = entry {
+                    let len = entry.len();
+                    self.output.cmd = Some(entry);
+                    self.output.set_cmd_cursor(len);
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn keyword_matches(&self, prefix: &str, (start, y): (usize, usize), x: usize) -> Vec<String> {
+        let mut words: Vec<String> = Vec::new();
+        let lines = (y..self.e_rows.num_rows()).chain(0..=y);
+        for (k, line) in lines.enumerate() {
+            let raw = self.e_rows.get_raw(line);
+            let mut word_start = None;
+            for (i, c) in raw.char_indices().chain([(raw.len(), ' ')]) {
+                match (word_start, self.options.is_keyword(c) && i < raw.len()) {
+                    (None, true) => word_start = Some(i),
+                    (Some(ws), false) => {
+                        word_start = None;
+                        let skip = line == y && if k == 0 { ws < x } else { ws >= start };
+                        let word = &raw[ws..i];
+                        if !skip
+                            && word.len() > prefix.len()
+                            && word.starts_with(prefix)
+                            && !words.iter().any(|w| w == word)
+                        {
+                            words.push(word.to_string());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        words
+    }
+
+    fn toggle_case(&mut self, count: usize) {
+        let (x, y) = self.output.cursor();
+        let raw = self.e_rows.get_raw(y);
+        let end = raw[x..]
+            .char_indices()
+            .nth(count)
+            .map_or(raw.len(), |(i, _)| x + i);
+        if x == end {
+            return;
+        }
+        let toggled: String = raw[x..end]
+            .chars()
+            .flat_map(|c| {
+                if c.is_lowercase() {
+                    c.to_uppercase().collect::<Vec<_>>()
+                } else {
+                    c.to_lowercase().collect()
+                }
+            })
+            .collect();
+        self.e_rows.get_erow_mut(y).replace_range(x..end, &toggled);
+        self.output
+            .set_cursor((x + toggled.len(), y), &self.e_rows, &self.mode);
+        self.output.touch();
+    }
+
+    fn join_lines(&mut self, count: Option<usize>, spaces: bool) {
+        let y = self.output.cursor().1;
+        let last = cmp::min(
+            y + cmp::max(count.unwrap_or(2), 2) - 1,
+            self.e_rows.num_rows() - 1,
+        );
+        if last == y {
+            self.abort();
+            return;
+        }
+        let mut x = 0;
+        for _ in y..last {
+            let cur = self.e_rows.get_raw(y);
+            x = cur.len();
+            if spaces {
+                let next = self.e_rows.get_raw(y + 1);
+                let trimmed = next.trim_start();
+                let sep = if cur.is_empty()
+                    || cur.ends_with(char::is_whitespace)
+                    || trimmed.is_empty()
+                    || trimmed.starts_with(')')
+                {
+                    ""
+                } else {
+                    " "
+                };
+                let indent = next.len() - trimmed.len();
+                self.e_rows
+                    .get_erow_mut(y + 1)
+                    .replace_range(0..indent, sep);
+            }
+            self.e_rows.join_adj_erows(y + 1);
+        }
+        self.output.set_cursor((x, y), &self.e_rows, &self.mode);
+        self.output.touch();
+    }
+
+    fn increment(&mut self, delta: i64) {
+        let (x, y) = self.output.cursor();
+        let raw = self.e_rows.get_raw(y);
+        let Some((range, text)) = find_number(raw, x).and_then(|(range, radix)| {
+            let text = add_to_number(&raw[range.clone()], radix, delta)?;
+            Some((range, text))
+        }) else {
+            self.abort();
+            return;
+        };
+        let end = range.start + text.len() - 1;
+        self.e_rows.get_erow_mut(y).replace_range(range, &text);
+        self.output.set_cursor((end, y), &self.e_rows, &self.mode);
+        self.output.touch();
+    }
+
+    fn store_register(&mut self, text: String, linewise: bool, yank: bool) {
+        match self.register.take() {
+            Some(name) => {
+                self.registers.set(name, text, linewise);
+                if let Some(reg) = self.registers.get(name).filter(|_| name != '"').cloned() {
+                    self.registers.set('"', reg.text, reg.linewise);
+                }
+            }
+            None if yank => self.registers.yank(text, linewise),
+            None => self.registers.delete(text, linewise),
+        }
+    }
+
+    fn paste(&mut self, before: bool, count: usize) {
+        let name = self.register.take().unwrap_or('"');
+        let Some(reg) = self.registers.get(name).cloned() else {
+            self.output.set_cmd_msg(
+                &format!("Nothing in register {}", name),
+                MessageLevel::Danger,
+            );
+            return;
+        };
+        let (x, y) = self.output.cursor();
+        if reg.linewise {
+            let at = if before { y } else { y + 1 };
+            let text = reg.text.strip_suffix('\n').unwrap_or(&reg.text);
+            let lines: Vec<&str> = text.split('\n').collect();
+            for i in 0..count {
+                for (j, line) in lines.iter().enumerate() {
+                    self.e_rows
+                        .insert_erow(at + i * lines.len() + j, line.to_string());
+                }
+            }
+            self.e_rows.marks.set('[', (0, at));
+            self.e_rows
+                .marks
+                .set(']', (0, at + count * lines.len() - 1));
+            self.output.set_cursor((0, at), &self.e_rows, &self.mode);
+            self.output.goto_start_line(&self.e_rows);
+        } else {
+            let raw = self.e_rows.get_raw(y);
+            let x = if before {
+                x
+            } else {
+                raw[x..].chars().next().map_or(x, |c| x + c.len_utf8())
+            };
+            let text = reg.text.repeat(count);
+            let (ex, ey) = self.e_rows.insert_text((x, y), &text);
+            let last = self.e_rows.get_raw(ey)[..ex].char_indices().last();
+            let last = (last.map_or(0, |(i, _)| i), ey);
+            self.e_rows.marks.set('[', (x, y));
+            self.e_rows.marks.set(']', last);
+            let pos = if text.contains('\n') { (x, y) } else { last };
+            self.output.set_cursor(pos, &self.e_rows, &self.mode);
+        }
+        self.output.touch();
+    }
+
+    fn exec_unmapped(&mut self, key: &Key) {
+        if let Some(c) = key.as_char() {
+            match self.mode {
+                Mode::Insert => {
+                    self.output.insert(&mut self.e_rows, c);
+                    self.autoindent_line = None;
+                    if c == '}' && self.options.smartindent {
+                        self.indent_closing_brace();
+                    }
+                }
+                Mode::Replace => {
+                    let orig = self.output.replace_char(&mut self.e_rows, c);
+                    self.replaced.push(orig);
+                }
+                Mode::Command => self.output.push_cmd(c),
+                Mode::Normal | Mode::Visual => self.cancel(),
+            }
+        }
+    }
+
+    fn exec_cmd(&mut self) -> io::Result<bool> {
+        if let Some(cmd) = self.output.cmd.clone() {
+            let cur = self.output.cursor().1;
+            let line = cmd.trim_start();
+            let parsed = match (&self.hex, &self.large) {
+                (None, None) => self.e_rows.parse_range(line, cur),
+                _ => Ok((None, line)),
+            };
+            let (range, cmd) = match parsed {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    self.output.set_cmd_msg(&e, MessageLevel::Danger);
+                    return Ok(false);
+                }
+            };
+            let zero = line[..line.len() - cmd.len()].trim() == "0";
+            let cmd = cmd.trim_start();
+            if let Some(q) = self.exec_range_cmd(range, cmd, zero)? {
+                return Ok(q);
+            }
+            if range.is_some() {
+                self.output
+                    .set_cmd_msg("No range allowed", MessageLevel::Danger);
+                return Ok(false);
+            }
+            let it: Vec<&str> = cmd.split(' ').collect();
+            let q = match it[..] {
+                ["q"] => {
                     if self.output.dirty > 0 {
                         self.output.set_cmd_msg(
                             "Found unsaved changes, q! to force quit",
@@ -445,97 +2559,877 @@ impl Editor {
                     }
                 }
                 ["q!"] => true,
-                ["w"] => {
-                    if self.e_rows.filename.is_none() {
-                        self.output
-                            .set_cmd_msg("No file name specified", MessageLevel::Danger);
-                        return Ok(false);
+                [w @ ("w" | "w!"), ref name @ ..] if name.len() < 2 => {
+                    self.write_cmd(name.first().copied(), w.ends_with('!'));
+                    false
+                }
+                [wq @ ("wq" | "wq!"), ref name @ ..] if name.len() < 2 => {
+                    self.write_cmd(name.first().copied(), wq.ends_with('!'))
+                }
+                [e @ ("e" | "e!"), ref name @ ..] if name.len() < 2 => {
+                    self.edit_cmd(name.first().copied(), e.ends_with('!'))?;
+                    false
+                }
+                ["hex"] if self.large.is_some() => {
+                    self.output
+                        .set_cmd_msg("Not available for large files", MessageLevel::Danger);
+                    false
+                }
+                ["hex"] => {
+                    self.toggle_hex();
+                    false
+                }
+                [n] if self.large.is_some() && n.parse::<usize>().is_ok() => {
+                    if let Some(large) = self.large.as_mut() {
+                        large.goto(n.parse::<usize>().unwrap_or(1).saturating_sub(1));
                     }
-                    self.save().map(|len| {
+                    false
+                }
+                [cmd, ref args @ ..] if map_modes(cmd).is_some() => {
+                    let (modes, noremap) = map_modes(cmd).unwrap_or_default();
+                    self.map_cmd(&modes, args, noremap);
+                    false
+                }
+                [cmd, lhs] if unmap_modes(cmd).is_some() => {
+                    let modes = unmap_modes(cmd).unwrap_or_default();
+                    if let Err(e) = self.keymaps.unmap(&modes, lhs) {
+                        self.output.set_cmd_msg(&e, MessageLevel::Danger);
+                    }
+                    false
+                }
+                [name, ref args @ ..] if self.scripts.has_command(name) => {
+                    let args = args.join(" ");
+                    return self.run_script(|scripts| {
+                        scripts
+                            .call_command(name, &args)
+                            .err()
+                            .into_iter()
+                            .collect()
+                    });
+                }
+                [se @ ("set" | "se"), ref args @ ..] => {
+                    if args.is_empty() {
                         self.output.set_cmd_msg(
-                            &format!("{} bytes written to disk", len),
-                            MessageLevel::Normal,
+                            &format!("Usage: {} {{option}}", se),
+                            MessageLevel::Danger,
                         );
-                        self.output.dirty = 0;
-                    })?;
+                    }
+                    for arg in args.iter().filter(|a| !a.is_empty()) {
+                        match self.options.set(arg) {
+                            Ok(Some(msg)) => self.output.set_cmd_msg(&msg, MessageLevel::Normal),
+                            Ok(None) => {}
+                            Err(e) => {
+                                self.output.set_cmd_msg(&e, MessageLevel::Danger);
+                                break;
+                            }
+                        }
+                    }
+                    false
+                }
+                ["marks", ref args @ ..] => {
+                    self.marks_cmd(&args.concat());
+                    false
+                }
+                ["sign", ref args @ ..] => {
+                    self.sign_cmd(args);
+                    false
+                }
+                [au @ ("autocmd" | "au" | "autocmd!" | "au!"), ref args @ ..] => {
+                    self.autocmd_cmd(au.ends_with('!'), args);
+                    false
+                }
+                ["let", ref args @ ..] => {
+                    match args.concat().strip_prefix("mapleader=") {
+                        Some(value) => {
+                            let value = value.trim_matches(|c| c == '"' || c == '\'');
+                            match parse_keys(value, &self.keymaps.leader) {
+                                Ok(keys) if keys.len() == 1 => {
+                                    self.keymaps.leader = keys[0].clone()
+                                }
+                                _ => self
+                                    .output
+                                    .set_cmd_msg("Invalid mapleader", MessageLevel::Danger),
+                            }
+                        }
+                        None => self.output.set_cmd_msg(
+                            &format!("Unknown variable \'{}\'", args.join(" ")),
+                            MessageLevel::Danger,
+                        ),
+                    }
                     false
                 }
-                ["wq"] => {
-                    match self.save() {
-                        Ok(len) => {
+                _ => {
+                    self.output.set_cmd_msg(
+                        &format!("Unknown command \'{}\'", it.join(" ")),
+                        MessageLevel::Danger,
+                    );
+                    false
+                }
+            };
+            Ok(q)
+        } else {
+            self.output
+                .set_cmd_msg("No command found", MessageLevel::Danger);
+            Ok(false)
+        }
+    }
+
+    fn exec_range_cmd(
+        &mut self,
+        range: Option<LineRange>,
+        cmd: &str,
+        zero: bool,
+    ) -> io::Result<Option<bool>> {
+        let name_len = cmd.len()
+            - cmd
+                .trim_start_matches(|c: char| c.is_ascii_alphabetic())
+                .len();
+        let (name, rest) = cmd.split_at(name_len);
+        let (bang, rest) = match rest.strip_prefix('!') {
+            // The `!` of `:r!cmd` starts the command, it is not a bang.
+            Some(rest) if !matches!(name, "r" | "read") => (true, rest),
+            _ => (false, rest),
+        };
+        let changes = match name {
+            "" => (bang && range.is_some()) || rest.starts_with(['>', '<']),
+            "r" | "read" | "d" | "de" | "del" | "delete" | "m" | "mo" | "move" | "t" | "co"
+            | "copy" | "j" | "join" | "sor" | "sort" | "ret" | "retab" => true,
+            _ => false,
+        };
+        let pipe = matches!(name, "w" | "write") && !bang && rest.trim_start().starts_with('!');
+        if self.hex.is_some() && (changes || pipe) {
+            self.output
+                .set_cmd_msg("Not available in hex view", MessageLevel::Danger);
+            return Ok(Some(false));
+        }
+        if self.large.is_some() && (changes || pipe) {
+            self.output
+                .set_cmd_msg("Not available for large files", MessageLevel::Danger);
+            return Ok(Some(false));
+        }
+        if changes && !self.check_modifiable() {
+            return Ok(Some(false));
+        }
+        let y = self.output.cursor().1;
+        let current = range.unwrap_or((y, y));
+        let all = range.unwrap_or((0, self.e_rows.num_rows() - 1));
+        let q = match name {
+            "" if bang && rest.trim().is_empty() => {
+                self.output
+                    .set_cmd_msg("Argument required", MessageLevel::Danger);
+                false
+            }
+            "" if bang => {
+                match range {
+                    Some(range) => self.filter_cmd(range, rest),
+                    None => self.shell_cmd(rest, None)?,
+                }
+                false
+            }
+            "r" | "read" => {
+                self.read_cmd(if zero { 0 } else { current.1 + 1 }, rest.trim());
+                false
+            }
+            "w" | "write" if pipe => {
+                let text: String = (all.0..=all.1)
+                    .map(|y| format!("{}\n", self.e_rows.get_raw(y)))
+                    .collect();
+                self.shell_cmd(&rest.trim_start()[1..], Some(text))?;
+                false
+            }
+            "" if rest.is_empty() => {
+                let Some((_, y)) = range else {
+                    return Ok(None);
+                };
+                self.set_jump(self.output.cursor());
+                self.output.set_cursor((0, y), &self.e_rows, &self.mode);
+                false
+            }
+            "" if rest.starts_with(['>', '<']) => {
+                self.shift_cmd(current, rest);
+                false
+            }
+            "g" | "global" | "v" | "vglobal" => {
+                let invert = bang || name.starts_with('v');
+                self.global_cmd(all, rest, invert)?
+            }
+            "norm" | "normal" if rest.is_empty() || rest.starts_with(' ') => {
+                self.normal_cmd(current, rest.strip_prefix(' ').unwrap_or(rest), bang)?
+            }
+            "d" | "de" | "del" | "delete" => {
+                self.delete_cmd(current, rest);
+                false
+            }
+            "m" | "mo" | "move" | "t" | "co" | "copy" => {
+                self.move_cmd(current, rest, name.starts_with('m'));
+                false
+            }
+            "j" | "join" => {
+                self.join_cmd(current, rest, !bang);
+                false
+            }
+            "sor" | "sort" => {
+                match SortFlags::parse(rest, bang) {
+                    Ok(flags) => {
+                        let removed = self.e_rows.sort_lines(all, &flags);
+                        if removed > 0 {
                             self.output.set_cmd_msg(
-                                &format!("{} bytes written to disk", len),
+                                &format!("{} fewer lines", removed),
                                 MessageLevel::Normal,
                             );
-                            self.output.dirty = 0;
-                        }
-                        Err(e) => {
-                            self.output
-                                .set_cmd_msg(&e.to_string(), MessageLevel::Danger);
-                            return Ok(false);
                         }
+                        self.output.set_cursor((0, all.0), &self.e_rows, &self.mode);
+                        self.output.touch();
                     }
-                    true
+                    Err(e) => self.output.set_cmd_msg(&e, MessageLevel::Danger),
+                }
+                false
+            }
+            "ret" | "retab" => {
+                if !matches!(rest.trim(), "" | "8") {
+                    self.output.set_cmd_msg(
+                        &format!("Tab stop is fixed at {}", TAB_SZ),
+                        MessageLevel::Danger,
+                    );
+                } else if self.e_rows.retab_lines(all, self.options.expandtab, bang) > 0 {
+                    self.output.touch();
+                }
+                false
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(q))
+    }
+
+    fn locked(&self) -> Option<&'static str> {
+        if !self.options.modifiable {
+            Some("Cannot make changes, 'modifiable' is off")
+        } else if self.options.readonly {
+            Some("File is read-only, :set noreadonly to edit")
+        } else {
+            None
+        }
+    }
+
+    fn check_modifiable(&mut self) -> bool {
+        let Some(msg) = self.locked() else {
+            return true;
+        };
+        self.output.set_cmd_msg(msg, MessageLevel::Danger);
+        false
+    }
+
+    fn write_cmd(&mut self, name: Option<&str>, force: bool) -> bool {
+        if let Some(name) = name {
+            self.e_rows.set_filename(name);
+        }
+        match self.save(force) {
+            Ok(len) => {
+                self.output.set_cmd_msg(
+                    &format!("{} bytes written to disk", len),
+                    MessageLevel::Normal,
+                );
+                self.output.dirty = 0;
+                true
+            }
+            Err(e) => {
+                self.output
+                    .set_cmd_msg(&e.to_string(), MessageLevel::Danger);
+                false
+            }
+        }
+    }
+
+    fn edit_cmd(&mut self, name: Option<&str>, force: bool) -> io::Result<()> {
+        let path = match name.map(path::absolute) {
+            Some(Ok(path)) => path,
+            Some(Err(e)) => {
+                self.output
+                    .set_cmd_msg(&e.to_string(), MessageLevel::Danger);
+                return Ok(());
+            }
+            None => match self.e_rows.filename.clone() {
+                Some(path) => path,
+                None => {
+                    self.output
+                        .set_cmd_msg("No file name specified", MessageLevel::Danger);
+                    return Ok(());
+                }
+            },
+        };
+        let cursor = self.output.cursor();
+        if force {
+            self.output.dirty = 0;
+        }
+        if self.load_file(path)? && name.is_none() {
+            self.output.set_cursor(cursor, &self.e_rows, &self.mode);
+        }
+        Ok(())
+    }
+
+    fn check_disk(&mut self) -> io::Result<bool> {
+        let Some(path) = self.e_rows.filename.clone() else {
+            return Ok(false);
+        };
+        let current = DiskStat::of(&path);
+        if current == self.e_rows.seen {
+            return Ok(false);
+        }
+        self.e_rows.seen = current;
+        if current.is_none() {
+            self.output
+                .set_cmd_msg("File no longer available", MessageLevel::Danger);
+        } else if self.options.autoread && self.output.dirty == 0 && self.mode == Mode::Normal {
+            self.edit_cmd(None, false)?;
+            self.output
+                .set_cmd_msg("File reloaded", MessageLevel::Normal);
+        } else {
+            self.output.set_cmd_msg(
+                "File changed on disk since reading, :e! to reload",
+                MessageLevel::Danger,
+            );
+        }
+        Ok(true)
+    }
+
+    fn filter_cmd(&mut self, (start, end): LineRange, cmd: &str) {
+        let input: String = (start..=end)
+            .map(|y| format!("{}\n", self.e_rows.get_raw(y)))
+            .collect();
+        let output = match run_filter(cmd, Some(&input)) {
+            Ok(output) => output,
+            Err(e) => {
+                self.output.set_cmd_msg(&e, MessageLevel::Danger);
+                return;
+            }
+        };
+        self.e_rows.delete_erows(start, end + 1);
+        let lines: Vec<&str> = output.lines().collect();
+        for (i, line) in lines.iter().enumerate() {
+            self.e_rows.insert_erow(start + i, line.to_string());
+        }
+        if self.e_rows.num_rows() == 0 {
+            self.e_rows.insert_erow(0, String::new());
+        }
+        self.e_rows.marks.set('[', (0, start));
+        self.e_rows
+            .marks
+            .set(']', (0, start + lines.len().saturating_sub(1)));
+        self.output.set_cursor((0, start), &self.e_rows, &self.mode);
+        self.output.goto_start_line(&self.e_rows);
+        self.output.touch();
+        self.output.set_cmd_msg(
+            &format!("{} lines filtered", end + 1 - start),
+            MessageLevel::Normal,
+        );
+    }
+
+    fn read_cmd(&mut self, at: usize, arg: &str) {
+        let text = match arg.strip_prefix('!') {
+            Some(cmd) => run_filter(cmd, None),
+            None if arg.is_empty() => Err("No file name specified".into()),
+            None => fs::read(arg)
+                .map(|bytes| Encoding::detect(&bytes).decode(&bytes))
+                .map_err(|e| format!("Can't open file {}: {}", arg, e)),
+        };
+        let text = match text {
+            Ok(text) => text,
+            Err(e) => {
+                self.output.set_cmd_msg(&e, MessageLevel::Danger);
+                return;
+            }
+        };
+        let lines: Vec<&str> = text.lines().collect();
+        if lines.is_empty() {
+            return;
+        }
+        for (i, line) in lines.iter().enumerate() {
+            self.e_rows.insert_erow(at + i, line.to_string());
+        }
+        self.e_rows.marks.set('[', (0, at));
+        self.e_rows.marks.set(']', (0, at + lines.len() - 1));
+        self.output.set_cursor((0, at), &self.e_rows, &self.mode);
+        self.output.goto_start_line(&self.e_rows);
+        self.output.touch();
+    }
+
+    fn shell_cmd(&mut self, cmd: &str, input: Option<String>) -> io::Result<()> {
+        self.leave_screen()?;
+        let mut command = shell(cmd);
+        if input.is_some() {
+            command.stdin(Stdio::piped());
+        }
+        let status = command.spawn().and_then(|mut child| {
+            if let (Some(text), Some(mut stdin)) = (input, child.stdin.take()) {
+                stdin.write_all(text.as_bytes()).ok();
+            }
+            child.wait()
+        });
+        let mut stdout = io::stdout();
+        if let Err(e) = &status {
+            writeln!(stdout, "{}", e)?;
+        }
+        write!(stdout, "\nPress ENTER to continue")?;
+        stdout.flush()?;
+        io::stdin().read_line(&mut String::new())?;
+        self.enter_screen()?;
+
+        match status {
+            Ok(status) if !status.success() => self.output.set_cmd_msg(
+                &format!("Shell returned {}", status.code().unwrap_or(-1)),
+                MessageLevel::Danger,
+            ),
+            Ok(_) => {}
+            Err(e) => self
+                .output
+                .set_cmd_msg(&e.to_string(), MessageLevel::Danger),
+        }
+        Ok(())
+    }
+
+    fn with_count(&mut self, (start, end): LineRange, arg: &str) -> Option<LineRange> {
+        let arg = arg.trim();
+        if arg.is_empty() {
+            return Some((start, end));
+        }
+        match arg.parse::<usize>() {
+            Ok(n) if n > 0 => Some((end, cmp::min(end + n - 1, self.e_rows.num_rows() - 1))),
+            _ => {
+                self.output
+                    .set_cmd_msg(&format!("Invalid argument: {}", arg), MessageLevel::Danger);
+                None
+            }
+        }
+    }
+
+    fn delete_cmd(&mut self, range: LineRange, args: &str) {
+        let args = args.trim_start();
+        let (register, args) = match args.chars().next() {
+            Some(c) if !c.is_ascii_digit() && (c.is_ascii_alphanumeric() || "\"-_".contains(c)) => {
+                (Some(c), &args[c.len_utf8()..])
+            }
+            _ => (None, args),
+        };
+        let Some((start, end)) = self.with_count(range, args) else {
+            return;
+        };
+        let text: String = (start..=end)
+            .map(|y| format!("{}\n", self.e_rows.get_raw(y)))
+            .collect();
+        self.register = register;
+        self.store_register(text, true, false);
+        self.e_rows.delete_erows(start, end + 1);
+        if self.e_rows.num_rows() == 0 {
+            self.e_rows.insert_erow(0, String::new());
+        }
+        self.e_rows.marks.set('[', (0, start));
+        self.e_rows.marks.set(']', (0, start));
+        self.output.set_cursor((0, start), &self.e_rows, &self.mode);
+        self.output.goto_start_line(&self.e_rows);
+        self.output.touch();
+    }
+
+    fn move_cmd(&mut self, range: LineRange, args: &str, remove: bool) {
+        let res = self
+            .e_rows
+            .parse_dest(args, self.output.cursor().1)
+            .and_then(|dest| match remove {
+                true => self.e_rows.move_lines(range, dest),
+                false => Ok(self.e_rows.copy_lines(range, dest)),
+            });
+        match res {
+            Ok(last) => {
+                self.e_rows.marks.set('[', (0, last + range.0 - range.1));
+                self.e_rows.marks.set(']', (0, last));
+                self.output.set_cursor((0, last), &self.e_rows, &self.mode);
+                self.output.goto_start_line(&self.e_rows);
+                self.output.touch();
+            }
+            Err(e) => self.output.set_cmd_msg(&e, MessageLevel::Danger),
+        }
+    }
+
+    fn join_cmd(&mut self, range: LineRange, args: &str, spaces: bool) {
+        let Some((start, end)) = self.with_count(range, args) else {
+            return;
+        };
+        self.output.set_cursor((0, start), &self.e_rows, &self.mode);
+        self.join_lines(Some(end - start + 1), spaces);
+    }
+
+    fn shift_cmd(&mut self, range: LineRange, args: &str) {
+        let c = args.chars().next().unwrap_or('>');
+        let depth = args.chars().take_while(|d| *d == c).count();
+        let Some((start, end)) = self.with_count(range, &args[depth..]) else {
+            return;
+        };
+        let op = match c {
+            '>' => Operator::ShiftRight,
+            _ => Operator::ShiftLeft,
+        };
+        for _ in 0..depth {
+            self.shift_lines(op, start, end);
+        }
+        self.output.set_cursor((0, end), &self.e_rows, &self.mode);
+        self.output.goto_start_line(&self.e_rows);
+    }
+
+    fn global_cmd(
+        &mut self,
+        (start, end): LineRange,
+        args: &str,
+        invert: bool,
+    ) -> io::Result<bool> {
+        let Some(delim) = args
+            .chars()
+            .next()
+            .filter(|c| !c.is_alphanumeric() && *c != ' ')
+        else {
+            self.output
+                .set_cmd_msg("Usage: g/{pattern}/{command}", MessageLevel::Danger);
+            return Ok(false);
+        };
+        let args = &args[delim.len_utf8()..];
+        let (pat, cmd) = args.split_once(delim).unwrap_or((args, ""));
+        if pat.is_empty() {
+            self.output
+                .set_cmd_msg("Empty pattern", MessageLevel::Danger);
+            return Ok(false);
+        }
+        if !self.e_rows.tracked.is_empty() {
+            self.output
+                .set_cmd_msg("Cannot do :global recursive", MessageLevel::Danger);
+            return Ok(false);
+        }
+        let pattern = match Pattern::new(pat) {
+            Ok(pattern) => pattern,
+            Err(e) => {
+                self.output.set_cmd_msg(&e, MessageLevel::Danger);
+                return Ok(false);
+            }
+        };
+        let lines: Vec<usize> = (start..=end)
+            .filter(|y| pattern.is_match(self.e_rows.get_raw(*y)) != invert)
+            .collect();
+        if lines.is_empty() {
+            self.output
+                .set_cmd_msg(&format!("Pattern not found: {}", pat), MessageLevel::Danger);
+            return Ok(false);
+        }
+        if cmd.trim().is_empty() {
+            let msg: Vec<String> = lines
+                .iter()
+                .map(|y| format!("{} {}", y + 1, self.e_rows.get_raw(*y).trim()))
+                .collect();
+            self.output
+                .set_cmd_msg(&msg.join(" | "), MessageLevel::Normal);
+            return Ok(false);
+        }
+        self.for_each_line(lines, |editor| editor.exec_cmd_str(cmd))
+    }
+
+    fn normal_cmd(
+        &mut self,
+        (start, end): LineRange,
+        keys: &str,
+        noremap: bool,
+    ) -> io::Result<bool> {
+        let keys = match parse_keys(keys, &self.keymaps.leader) {
+            Ok(keys) => keys,
+            Err(e) => {
+                self.output.set_cmd_msg(&e, MessageLevel::Danger);
+                return Ok(false);
+            }
+        };
+        if keys.is_empty() {
+            return Ok(false);
+        }
+        self.for_each_line((start..=end).collect(), |editor| {
+            editor.exec_normal(&keys, noremap)
+        })
+    }
+
+    fn for_each_line<F>(&mut self, lines: Vec<usize>, mut f: F) -> io::Result<bool>
+    where
+        F: FnMut(&mut Self) -> io::Result<bool>,
+    {
+        let depth = self.e_rows.tracked.len();
+        self.e_rows.tracked.push(lines);
+        let mut quit = false;
+        while let Some(y) = self
+            .e_rows
+            .tracked
+            .get_mut(depth)
+            .and_then(|lines| (!lines.is_empty()).then(|| lines.remove(0)))
+        {
+            self.output.set_cursor((0, y), &self.e_rows, &self.mode);
+            quit = f(self)?;
+            if quit {
+                break;
+            }
+        }
+        self.e_rows.tracked.truncate(depth);
+        Ok(quit)
+    }
+
+    fn exec_normal(&mut self, keys: &[Key], noremap: bool) -> io::Result<bool> {
+        if self.script_depth >= MAX_SCRIPT_DEPTH {
+            self.output
+                .set_cmd_msg("Commands nested too deeply", MessageLevel::Danger);
+            return Ok(false);
+        }
+        if self.mode != Mode::Normal {
+            let cursor = self.output.cursor();
+            self.change_mode(Mode::Normal)?;
+            self.output.set_cursor(cursor, &self.e_rows, &self.mode);
+        }
+        let typeahead = mem::take(&mut self.typeahead);
+        let pending = mem::take(&mut self.pending);
+        self.typeahead
+            .extend(keys.iter().map(|key| (key.clone(), !noremap)));
+
+        self.script_depth += 1;
+        let quit = self.process_keys(true);
+        self.script_depth -= 1;
+
+        self.cancel();
+        self.pending.clear();
+        if self.mode != Mode::Normal && quit.is_ok() {
+            self.exec_action(Action::NormalMode, None)?;
+        }
+        self.typeahead = typeahead;
+        self.pending = pending;
+        quit
+    }
+
+    fn marks_cmd(&mut self, names: &str) {
+        let mut marks: Vec<(char, (usize, usize), String)> = self
+            .e_rows
+            .marks
+            .list()
+            .into_iter()
+            .filter(|(_, (_, y))| *y < self.e_rows.num_rows())
+            .map(|(name, pos)| (name, pos, self.e_rows.get_raw(pos.1).trim().to_string()))
+            .collect();
+        marks.extend(
+            self.global_marks
+                .iter()
+                .map(|(name, (file, pos))| (*name, *pos, file.display().to_string())),
+        );
+        marks.retain(|(name, _, _)| names.is_empty() || names.contains(*name));
+        marks.sort_by_key(|(name, _, _)| (name.is_ascii_alphabetic(), *name));
+        if marks.is_empty() {
+            self.output
+                .set_cmd_msg("No marks set", MessageLevel::Danger);
+            return;
+        }
+        let msg: Vec<String> = marks
+            .iter()
+            .map(|(name, (x, y), text)| format!("{} {} {} {}", name, y + 1, x, text))
+            .collect();
+        self.output
+            .set_cmd_msg(&msg.join(" | "), MessageLevel::Normal);
+    }
+
+    fn sign_cmd(&mut self, args: &[&str]) {
+        let line = |arg: &str| arg.parse::<usize>().ok().filter(|l| *l > 0).map(|l| l - 1);
+        match args {
+            ["place", l, text, ref group @ ..] if line(l).is_some() && group.len() < 2 => {
+                let group = group.first().copied().unwrap_or_default();
+                self.e_rows
+                    .place_sign(line(l).unwrap_or_default(), text, group);
+            }
+            ["unplace", "*", ref group @ ..] if group.len() < 2 => {
+                self.e_rows.unplace_signs(None, group.first().copied());
+            }
+            ["unplace", l, ref group @ ..] if line(l).is_some() && group.len() < 2 => {
+                self.e_rows.unplace_signs(line(l), group.first().copied());
+            }
+            _ => self.output.set_cmd_msg(
+                "Usage: sign place {line} {text} [group] | sign unplace {line}|* [group]",
+                MessageLevel::Danger,
+            ),
+        }
+    }
+
+    fn exec_cmd_str(&mut self, cmd: &str) -> io::Result<bool> {
+        let cmd = cmd.strip_prefix(':').unwrap_or(cmd);
+        let typed = self.output.cmd.replace(cmd.into());
+        let res = self.exec_cmd();
+        self.output.cmd = typed;
+        res
+    }
+
+    fn autocmd_cmd(&mut self, bang: bool, args: &[&str]) {
+        let (events, pattern, cmd) = match args {
+            [] => (None, None, None),
+            [events] => (Some(*events), None, None),
+            [events, pattern] => (Some(*events), Some(*pattern), None),
+            [events, pattern, cmd @ ..] => (Some(*events), Some(*pattern), Some(cmd.join(" "))),
+        };
+        let events = match events.map(AutoCmds::parse_events).transpose() {
+            Ok(events) => events,
+            Err(e) => {
+                self.output.set_cmd_msg(&e, MessageLevel::Danger);
+                return;
+            }
+        };
+
+        if bang {
+            self.autocmds.remove(events.as_deref(), pattern);
+        }
+        match (events, pattern, cmd) {
+            (Some(events), Some(pattern), Some(cmd)) => {
+                self.autocmds.add(&events, pattern, &cmd);
+            }
+            (None, _, _) if !bang => {
+                let lines = self.autocmds.describe();
+                if lines.is_empty() {
+                    self.output
+                        .set_cmd_msg("No autocommands defined", MessageLevel::Normal);
+                } else {
+                    self.output
+                        .set_cmd_msg(&lines.join(" | "), MessageLevel::Normal);
                 }
-                ["w", name] => {
-                    self.e_rows.set_filename(name);
-                    match self.save() {
-                        Ok(len) => {
-                            self.output.set_cmd_msg(
-                                &format!("{} bytes written to disk", len),
-                                MessageLevel::Normal,
-                            );
-                            self.output.dirty = 0;
-                        }
-                        Err(e) => {
-                            self.output
-                                .set_cmd_msg(&e.to_string(), MessageLevel::Danger);
-                            return Ok(false);
-                        }
+            }
+            _ if !bang => {
+                self.output
+                    .set_cmd_msg("Missing autocommand command", MessageLevel::Danger);
+            }
+            _ => {}
+        }
+    }
+
+    fn run_script<F>(&mut self, f: F) -> io::Result<bool>
+    where
+        F: FnOnce(&mut Scripts) -> Vec<String>,
+    {
+        if self.script_depth >= MAX_SCRIPT_DEPTH {
+            self.output
+                .set_cmd_msg("Script commands nested too deeply", MessageLevel::Danger);
+            return Ok(false);
+        }
+        let mut state = ScriptState::new(
+            mem::take(&mut self.e_rows),
+            self.output.cursor(),
+            self.mode.name(),
+            mem::take(&mut self.registers),
+        );
+        state.locked = self.locked();
+        self.scripts.enter(state);
+        let errors = f(&mut self.scripts);
+        let state = self.scripts.leave();
+
+        self.e_rows = state.rows;
+        self.registers = state.registers;
+        if self.e_rows.num_rows() == 0 {
+            self.e_rows.insert_erow(0, String::new());
+        }
+        if state.changed {
+            self.output.touch();
+        }
+        self.output
+            .set_cursor(state.cursor, &self.e_rows, &self.mode);
+
+        for (msg, error) in state.messages {
+            let level = if error {
+                MessageLevel::Danger
+            } else {
+                MessageLevel::Normal
+            };
+            self.output.set_cmd_msg(&msg, level);
+        }
+        if let Some(e) = errors.last() {
+            self.output.set_cmd_msg(e, MessageLevel::Danger);
+        }
+
+        self.script_depth += 1;
+        let mut quit = false;
+        for request in state.requests {
+            match request {
+                Request::Map {
+                    modes,
+                    lhs,
+                    rhs,
+                    noremap,
+                } => {
+                    let res = match modes_for(&modes) {
+                        Some(modes) => self.keymaps.map(&modes, &lhs, &rhs, noremap),
+                        None => Err(format!("Invalid map mode \'{}\'", modes)),
                     };
-                    false
-                }
-                ["wq", name] => {
-                    self.e_rows.set_filename(name);
-                    match self.save() {
-                        Ok(len) => {
-                            self.output.set_cmd_msg(
-                                &format!("{} bytes written to disk", len),
-                                MessageLevel::Normal,
-                            );
-                            self.output.dirty = 0;
-                        }
-                        Err(e) => {
-                            self.output
-                                .set_cmd_msg(&e.to_string(), MessageLevel::Danger);
-                            return Ok(false);
-                        }
+                    if let Err(e) = res {
+                        self.output.set_cmd_msg(&e, MessageLevel::Danger);
                     }
-                    true
                 }
-                _ => {
-                    self.output.set_cmd_msg(
-                        &format!("Unknown command \'{}\'", it.join(" ")),
-                        MessageLevel::Danger,
-                    );
-                    false
+                Request::Command(cmd) => {
+                    if self.exec_cmd_str(&cmd)? {
+                        quit = true;
+                        break;
+                    }
                 }
-            };
-            return Ok(q);
-        } else {
-            self.output
-                .set_cmd_msg("No command found", MessageLevel::Danger);
-            Ok(false)
+            }
+        }
+        self.script_depth -= 1;
+        Ok(quit)
+    }
+
+    fn map_cmd(&mut self, modes: &[MapMode], args: &[&str], noremap: bool) {
+        let res = match args {
+            [] => self.keymaps.describe(modes, None).map(Some),
+            [lhs] => self.keymaps.describe(modes, Some(lhs)).map(Some),
+            [lhs, rhs @ ..] => self
+                .keymaps
+                .map(modes, lhs, &rhs.join(" "), noremap)
+                .map(|_| None),
+        };
+        match res {
+            Ok(Some(lines)) if lines.is_empty() => {
+                self.output
+                    .set_cmd_msg("No mapping found", MessageLevel::Normal);
+            }
+            Ok(Some(lines)) => {
+                self.output
+                    .set_cmd_msg(&lines.join(" | "), MessageLevel::Normal);
+            }
+            Ok(None) => {}
+            Err(e) => self.output.set_cmd_msg(&e, MessageLevel::Danger),
         }
     }
+
     fn change_mode(&mut self, mode: Mode) -> io::Result<()> {
         let mut stdout = io::stdout();
 
         match mode {
             Mode::Normal => {
                 execute!(stdout, SetCursorStyle::BlinkingBlock)?;
-                self.output
-                    .move_cursor(Direction::Left, &self.e_rows, &Mode::Normal);
+                if self.mode == Mode::Visual {
+                    if let Some((start, end)) = self.output.selection() {
+                        self.e_rows.marks.set('<', start);
+                        self.e_rows.marks.set('>', end);
+                    }
+                    self.output.visual = None;
+                    self.output.clear_stt_msg();
+                } else if self.insert_oneshot {
+                    self.output
+                        .set_stt_msg("-- (insert) --", MessageLevel::Normal);
+                } else {
+                    if matches!(self.mode, Mode::Insert | Mode::Replace) {
+                        let cursor = self.output.cursor();
+                        self.e_rows.marks.set('^', cursor);
+                        self.e_rows.marks.set(']', cursor);
+                    }
+                    self.output
+                        .move_cursor(Direction::Left, &self.e_rows, &Mode::Normal);
+                }
+            }
+            Mode::Visual => {
+                execute!(stdout, SetCursorStyle::BlinkingBlock)?;
+                self.output.clear_cmd_msg();
+                let msg = match self.output.visual {
+                    Some((_, true)) => "-- VISUAL LINE --",
+                    _ => "-- VISUAL --",
+                };
+                self.output.set_stt_msg(msg, MessageLevel::Normal);
             }
             Mode::Insert => {
                 execute!(stdout, SetCursorStyle::BlinkingUnderScore)?;
@@ -543,37 +3437,113 @@ impl Editor {
                 self.output
                     .set_stt_msg("-- INSERT --", MessageLevel::Normal);
             }
+            Mode::Replace => {
+                execute!(stdout, SetCursorStyle::BlinkingUnderScore)?;
+                self.replaced.clear();
+                self.output.clear_cmd_msg();
+                self.output
+                    .set_stt_msg("-- REPLACE --", MessageLevel::Normal);
+            }
             Mode::Command => {
                 execute!(stdout, SetCursorStyle::BlinkingUnderScore)?;
                 self.output.reset_cmd_cursor();
+                self.history.reset();
                 self.output.clear_stt_msg();
                 self.output.clear_cmd_msg();
                 self.output.clear_cmd();
             }
         };
-        self.mode = mode;
-        Ok(())
+        let prev = mem::replace(&mut self.mode, mode);
+        if prev == mode {
+            return Ok(());
+        }
+        if matches!(prev, Mode::Insert | Mode::Replace) {
+            self.fire(AuEvent::InsertLeave)?;
+        }
+        if matches!(mode, Mode::Insert | Mode::Replace) {
+            self.fire(AuEvent::InsertEnter)?;
+        }
+        self.fire(AuEvent::ModeChanged)
+    }
+
+    fn enter_screen(&mut self) -> io::Result<()> {
+        terminal::enable_raw_mode()?;
+        self.on_screen = true;
+        execute!(
+            io::stdout(),
+            SetCursorStyle::BlinkingBlock,
+            EnterAlternateScreen,
+            EnableFocusChange,
+            cursor::MoveTo(0, 0)
+        )
+    }
+
+    fn leave_screen(&mut self) -> io::Result<()> {
+        self.on_screen = false;
+        self.output.clear_screen()?;
+        terminal::disable_raw_mode()?;
+        execute!(io::stdout(), DisableFocusChange, LeaveAlternateScreen)
+    }
+
+    fn save(&mut self, force: bool) -> io::Result<usize> {
+        let Some(path) = self.e_rows.filename.clone() else {
+            return Err(io::Error::other("No file name specified"));
+        };
+        if self.large.is_some() {
+            return Err(io::Error::other("Large files cannot be written"));
+        }
+        if !force && self.options.readonly {
+            return Err(io::Error::other(
+                "'readonly' option is set, add ! to override",
+            ));
+        }
+        let current = DiskStat::of(&path);
+        if !force && current.is_some() && current != self.e_rows.disk {
+            return Err(io::Error::other(match self.e_rows.disk {
+                Some(_) => "File changed on disk since reading, add ! to override",
+                None => "File exists, add ! to override",
+            }));
+        }
+        self.fire(AuEvent::BufWritePre)?;
+        let len = match self.write_file() {
+            Err(e) if force && e.kind() == io::ErrorKind::PermissionDenied => {
+                with_write_permission(&path, || self.write_file())?
+            }
+            res => res?,
+        };
+        self.e_rows.disk = DiskStat::of(&path);
+        self.e_rows.seen = self.e_rows.disk;
+        self.fire(AuEvent::BufWritePost)?;
+        Ok(len)
     }
 
-    fn save(&self) -> io::Result<usize> {
+    fn encode_rows(&self, end: usize, eol: bool) -> io::Result<Vec<u8>> {
+        let rows = self.e_rows.get_erows()[..end].iter();
+        let contents = join_lines(rows.map(|r| r.raw.as_str()), eol, self.e_rows.crlf);
+        Encoding::from_name(&self.options.fileencoding)
+            .unwrap_or_default()
+            .encode(&contents)
+            .map_err(io::Error::other)
+    }
+
+    fn write_file(&self) -> io::Result<usize> {
         match &self.e_rows.filename {
-            None => Err(io::Error::new(
-                io::ErrorKind::Other,
-                "No file name specified",
-            )),
+            None => Err(io::Error::other("No file name specified")),
             Some(name) => {
-                let mut f = fs::OpenOptions::new().write(true).create(true).open(name)?;
-                let contents = self
-                    .e_rows
-                    .get_erows()
-                    .iter()
-                    .map(|r| r.raw.as_str())
-                    .collect::<Vec<&str>>()
-                    .join("\n");
-                f.set_len(contents.len() as u64)?;
+                let mut bytes = match &self.hex {
+                    Some(hex) => hex.bytes().to_vec(),
+                    None => self.encode_rows(self.e_rows.num_rows(), self.e_rows.eol)?,
+                };
+                if let Some(c) = self.e_rows.compression {
+                    bytes = c.compress(&bytes)?;
+                }
 
-                let bytes = contents.as_bytes();
-                f.write_all(bytes)?;
+                let mut f = fs::OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(name)?;
+                f.write_all(&bytes)?;
                 Ok(bytes.len())
             }
         }
@@ -582,9 +3552,394 @@ impl Editor {
 
 impl Drop for Editor {
     fn drop(&mut self) {
-        let mut stdout = io::stdout();
-        self.output.clear_screen().expect("Failed to clear screen");
-        terminal::disable_raw_mode().expect("Failed to disable raw mode");
-        execute!(stdout, LeaveAlternateScreen).expect("Failed to leave alternate screen");
+        if self.on_screen {
+            self.leave_screen().expect("Failed to restore terminal");
+        }
+    }
+}
+
+fn find_number(line: &str, x: usize) -> Option<(Range<usize>, u32)> {
+    let b = line.as_bytes();
+    let digits = |from: usize, radix: u32| {
+        (from..b.len())
+            .find(|j| !(b[*j] as char).is_digit(radix))
+            .unwrap_or(b.len())
+    };
+    let prefixed = |i: usize, p: u8, radix: u32| {
+        b[i] == b'0'
+            && b.get(i + 1).is_some_and(|c| c.to_ascii_lowercase() == p)
+            && b.get(i + 2).is_some_and(|c| (*c as char).is_digit(radix))
+    };
+    let mut i = 0;
+    while i < b.len() {
+        let (start, end, radix) = if prefixed(i, b'x', 16) {
+            (i, digits(i + 2, 16), 16)
+        } else if prefixed(i, b'b', 2) {
+            (i, digits(i + 2, 2), 2)
+        } else if b[i].is_ascii_digit() {
+            let start = if i > 0 && b[i - 1] == b'-' { i - 1 } else { i };
+            (start, digits(i, 10), 10)
+        } else {
+            i += 1;
+            continue;
+        };
+        if end > x {
+            return Some((start..end, radix));
+        }
+        i = end;
+    }
+    None
+}
+
+fn add_to_number(text: &str, radix: u32, delta: i64) -> Option<String> {
+    if radix == 10 {
+        let n: i64 = text.parse().ok()?;
+        let n = n.saturating_add(delta);
+        let digits = text.trim_start_matches('-');
+        if digits.len() > 1 && digits.starts_with('0') {
+            let sign = if n < 0 { "-" } else { "" };
+            return Some(format!(
+                "{}{:0w$}",
+                sign,
+                n.unsigned_abs(),
+                w = digits.len()
+            ));
+        }
+        return Some(n.to_string());
+    }
+    let (prefix, digits) = text.split_at(2);
+    let n = u64::from_str_radix(digits, radix)
+        .ok()?
+        .wrapping_add(delta as u64);
+    let w = digits.len();
+    Some(match radix {
+        2 => format!("{}{:0w$b}", prefix, n),
+        _ if digits.chars().any(|c| c.is_ascii_uppercase()) => format!("{}{:0w$X}", prefix, n),
+        _ => format!("{}{:0w$x}", prefix, n),
+    })
+}
+
+fn changes_text(action: Action) -> bool {
+    matches!(
+        action,
+        Action::Insert
+            | Action::InsertStart
+            | Action::Append
+            | Action::AppendEnd
+            | Action::OpenBelow
+            | Action::OpenAbove
+            | Action::DeleteLine
+            | Action::DeleteChar
+            | Action::Delete
+            | Action::Change
+            | Action::Paste
+            | Action::PasteBefore
+            | Action::ShiftRight
+            | Action::ShiftLeft
+            | Action::Reindent
+            | Action::Filter
+            | Action::ReplaceMode
+            | Action::ReplaceChar
+            | Action::ToggleCase
+            | Action::JoinLines
+            | Action::JoinLinesRaw
+            | Action::Increment
+            | Action::Decrement
+            | Action::Undo
+            | Action::Redo
+    )
+}
+
+fn writable(path: &Path) -> bool {
+    !path.exists() || fs::OpenOptions::new().write(true).open(path).is_ok()
+}
+
+#[cfg(unix)]
+fn with_write_permission<F>(path: &Path, write: F) -> io::Result<usize>
+where
+    F: FnOnce() -> io::Result<usize>,
+{
+    use std::os::unix::fs::PermissionsExt;
+    let perms = fs::metadata(path)?.permissions();
+    fs::set_permissions(path, fs::Permissions::from_mode(perms.mode() | 0o200))?;
+    let res = write();
+    fs::set_permissions(path, perms)?;
+    res
+}
+
+#[cfg(not(unix))]
+fn with_write_permission<F>(_: &Path, write: F) -> io::Result<usize>
+where
+    F: FnOnce() -> io::Result<usize>,
+{
+    write()
+}
+
+#[cfg(unix)]
+fn inode(meta: &fs::Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::ino(meta)
+}
+
+#[cfg(not(unix))]
+fn inode(_: &fs::Metadata) -> u64 {
+    0
+}
+
+fn shell(cmd: &str) -> Command {
+    let mut command = Command::new(env::var("SHELL").unwrap_or_else(|_| "sh".into()));
+    command.arg("-c").arg(cmd);
+    command
+}
+
+fn run_filter(cmd: &str, input: Option<&str>) -> Result<String, String> {
+    let stdin = if input.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    };
+    let mut child = shell(cmd)
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    let writer = match (input, child.stdin.take()) {
+        (Some(text), Some(mut stdin)) => {
+            let text = text.to_string();
+            Some(thread::spawn(move || stdin.write_all(text.as_bytes())))
+        }
+        _ => None,
+    };
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if let Some(writer) = writer {
+        writer.join().ok();
+    }
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(match stderr.lines().next() {
+            Some(line) => line.to_string(),
+            None => format!("Shell returned {}", output.status.code().unwrap_or(-1)),
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn complete_path(word: &str) -> Vec<String> {
+    let (dir, base) = match word.rfind('/') {
+        Some(i) => (&word[..=i], &word[i + 1..]),
+        None => ("", word),
+    };
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(base) || (name.starts_with('.') && !base.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, name, slash))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+fn combine_counts(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (None, None) => None,
+        _ => Some(a.unwrap_or(1).saturating_mul(b.unwrap_or(1))),
+    }
+}
+
+fn map_modes(cmd: &str) -> Option<(Vec<MapMode>, bool)> {
+    let (prefix, noremap) = match cmd.strip_suffix("noremap") {
+        Some(prefix) => (prefix, true),
+        None => (cmd.strip_suffix("map")?, false),
+    };
+    modes_for(prefix).map(|modes| (modes, noremap))
+}
+
+fn unmap_modes(cmd: &str) -> Option<Vec<MapMode>> {
+    modes_for(cmd.strip_suffix("unmap")?)
+}
+
+fn modes_for(prefix: &str) -> Option<Vec<MapMode>> {
+    match prefix {
+        "" => Some(vec![MapMode::Normal, MapMode::Visual, MapMode::Operator]),
+        "n" => Some(vec![MapMode::Normal]),
+        "o" => Some(vec![MapMode::Operator]),
+        "i" => Some(vec![MapMode::Insert]),
+        "c" => Some(vec![MapMode::Command]),
+        "v" => Some(vec![MapMode::Visual]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor(lines: &[&str]) -> Editor {
+        Editor::with_output(
+            out::Output::with_size((80, 20)),
+            EditorRows::from_lines(lines),
+            false,
+            false,
+        )
+    }
+
+    fn normal(ed: &mut Editor, keys: &str) {
+        let keys = parse_keys(keys, &ed.keymaps.leader).unwrap();
+        ed.exec_normal(&keys, false).unwrap();
+        ed.sync_undo();
+    }
+
+    fn lines(ed: &Editor) -> Vec<&str> {
+        ed.e_rows
+            .get_erows()
+            .iter()
+            .map(|r| r.raw.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn linewise_mark_on_whitespace_row() {
+        let mut ed = editor(&["x", "   ", "y"]);
+        normal(&mut ed, "jllmaG'a");
+        assert_eq!(ed.output.cursor(), (2, 1));
+        normal(&mut ed, "gg0ma'a");
+        assert_eq!(ed.output.cursor(), (0, 0));
+    }
+
+    #[test]
+    fn shift_onto_whitespace_row() {
+        let mut ed = editor(&["  \t ", "x"]);
+        normal(&mut ed, ">>");
+        assert_eq!(ed.e_rows.get_raw(0), "  \t ");
+        assert_eq!(ed.output.cursor(), (3, 0));
+        normal(&mut ed, "<<");
+        assert_eq!((ed.output.cursor(), ed.output.dirty), ((3, 0), 0));
+        normal(&mut ed, "==");
+        assert_eq!(ed.e_rows.get_raw(0), "");
+        assert_eq!(ed.output.dirty, 1);
+        assert_eq!(ed.output.cursor(), (0, 0));
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut ed = editor(&["one", "two", "three"]);
+        normal(&mut ed, "j");
+        normal(&mut ed, "dd");
+        normal(&mut ed, "Ax<Esc>");
+        assert_eq!(lines(&ed), ["one", "threex"]);
+        normal(&mut ed, "u");
+        assert_eq!(lines(&ed), ["one", "three"]);
+        normal(&mut ed, "u");
+        assert_eq!(lines(&ed), ["one", "two", "three"]);
+        assert_eq!(ed.output.cursor(), (0, 1));
+        normal(&mut ed, "u");
+        assert_eq!(lines(&ed), ["one", "two", "three"]);
+        normal(&mut ed, "2<C-r>");
+        assert_eq!(lines(&ed), ["one", "threex"]);
+        normal(&mut ed, "u");
+        normal(&mut ed, "ox<Esc>");
+        normal(&mut ed, "<C-r>");
+        assert_eq!(lines(&ed), ["one", "three", "x"]);
+    }
+
+    #[test]
+    fn global_matches_patterns_and_undoes_at_once() {
+        let mut ed = editor(&["a1", "  ", "b", "a22", ""]);
+        ed.exec_cmd_str("g/^\\s*$/d").unwrap();
+        ed.sync_undo();
+        assert_eq!(lines(&ed), ["a1", "b", "a22"]);
+        ed.exec_cmd_str("v/^a\\d\\+$/normal Ax").unwrap();
+        ed.sync_undo();
+        assert_eq!(lines(&ed), ["a1", "bx", "a22"]);
+        normal(&mut ed, "u");
+        assert_eq!(lines(&ed), ["a1", "b", "a22"]);
+        normal(&mut ed, "u");
+        assert_eq!(lines(&ed), ["a1", "  ", "b", "a22", ""]);
+    }
+
+    #[test]
+    fn line_commands_onto_whitespace_rows() {
+        let mut ed = editor(&["a", "  ", "b"]);
+        ed.exec_cmd_str("1d").unwrap();
+        assert_eq!((lines(&ed), ed.output.cursor()), (vec!["  ", "b"], (1, 0)));
+        ed.exec_cmd_str("1m$").unwrap();
+        assert_eq!((lines(&ed), ed.output.cursor()), (vec!["b", "  "], (1, 1)));
+        ed.exec_cmd_str(">").unwrap();
+        assert_eq!((lines(&ed), ed.output.cursor()), (vec!["b", "  "], (1, 1)));
+        ed.exec_cmd_str("2t0").unwrap();
+        assert_eq!(
+            (lines(&ed), ed.output.cursor()),
+            (vec!["  ", "b", "  "], (1, 0))
+        );
+    }
+
+    #[test]
+    fn linewise_put_and_delete_onto_whitespace_rows() {
+        let mut ed = editor(&["a", " \t", "b"]);
+        normal(&mut ed, "dd");
+        assert_eq!((lines(&ed), ed.output.cursor()), (vec![" \t", "b"], (1, 0)));
+        normal(&mut ed, "yyjp");
+        assert_eq!(
+            (lines(&ed), ed.output.cursor()),
+            (vec![" \t", "b", " \t"], (1, 2))
+        );
+        normal(&mut ed, "P");
+        assert_eq!(ed.output.cursor(), (1, 2));
+    }
+
+    #[test]
+    fn read_command_output() {
+        let mut ed = editor(&["a"]);
+        ed.exec_cmd_str("r!echo hi").unwrap();
+        assert_eq!((lines(&ed), ed.output.cursor()), (vec!["a", "hi"], (0, 1)));
+        ed.exec_cmd_str("0read !printf '  \\n'").unwrap();
+        assert_eq!(
+            (lines(&ed), ed.output.cursor()),
+            (vec!["  ", "a", "hi"], (1, 0))
+        );
+        ed.exec_cmd_str("3!printf ' \\n'").unwrap();
+        assert_eq!(
+            (lines(&ed), ed.output.cursor()),
+            (vec!["  ", "a", " "], (0, 2))
+        );
+    }
+
+    #[test]
+    fn write_over_a_longer_file() {
+        let path = env::temp_dir().join(format!("sage-write-{}.txt", std::process::id()));
+        fs::write(&path, "a much longer line\nand more\n").unwrap();
+        let mut ed = editor(&["ab"]);
+        ed.exec_cmd_str(&format!("w! {}", path.display())).unwrap();
+        let written = fs::read_to_string(&path);
+        fs::remove_file(&path).ok();
+        assert_eq!(written.unwrap(), "ab");
+    }
+
+    #[test]
+    fn compressed_and_fake_compressed_files() {
+        let path = env::temp_dir().join(format!("sage-gz-{}.gz", std::process::id()));
+        let gzip = Compression::Gzip(6);
+        fs::write(&path, gzip.compress(b"one\ntwo\n").unwrap()).unwrap();
+        let (rows, _) = EditorRows::from_file(path.clone()).unwrap();
+        assert_eq!(rows.compression, Some(gzip));
+        assert_eq!(rows.get_raw(1), "two");
+
+        fs::write(&path, b"\x1f\x8bjunk\n").unwrap();
+        let (rows, bytes) = EditorRows::from_file(path.clone()).unwrap();
+        fs::remove_file(&path).ok();
+        assert_eq!(rows.compression, None);
+        assert_eq!(bytes, b"\x1f\x8bjunk\n");
+        assert_eq!(
+            (rows.num_rows(), rows.get_raw(0).ends_with("junk")),
+            (1, true)
+        );
     }
 }
@@ -0,0 +1,320 @@
+const ESCAPE_BASE: u32 = 0xF700;
+
+const CP1252: [Option<char>; 32] = [
+    Some('€'),
+    None,
+    Some('‚'),
+    Some('ƒ'),
+    Some('„'),
+    Some('…'),
+    Some('†'),
+    Some('‡'),
+    Some('ˆ'),
+    Some('‰'),
+    Some('Š'),
+    Some('‹'),
+    Some('Œ'),
+    None,
+    Some('Ž'),
+    None,
+    None,
+    Some('‘'),
+    Some('’'),
+    Some('“'),
+    Some('”'),
+    Some('•'),
+    Some('–'),
+    Some('—'),
+    Some('˜'),
+    Some('™'),
+    Some('š'),
+    Some('›'),
+    Some('œ'),
+    None,
+    Some('ž'),
+    Some('Ÿ'),
+];
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf8Bom,
+    Latin1,
+    Cp1252,
+    Utf16Le,
+    Utf16Be,
+}
+impl Encoding {
+    pub const NAMES: &[&str] = &[
+        "utf-8",
+        "utf-8-bom",
+        "latin1",
+        "cp1252",
+        "utf-16le",
+        "utf-16be",
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf8Bom => "utf-8-bom",
+            Encoding::Latin1 => "latin1",
+            Encoding::Cp1252 => "cp1252",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Encoding::Utf8,
+            "utf-8-bom" | "utf8-bom" => Encoding::Utf8Bom,
+            "latin1" | "iso-8859-1" => Encoding::Latin1,
+            "cp1252" | "windows-1252" => Encoding::Cp1252,
+            "utf-16le" | "utf16le" => Encoding::Utf16Le,
+            "utf-16be" | "utf16be" => Encoding::Utf16Be,
+            _ => return None,
+        })
+    }
+
+    pub fn detect(bytes: &[u8]) -> Self {
+        match bytes {
+            [0xEF, 0xBB, 0xBF, ..] => Encoding::Utf8Bom,
+            [0xFF, 0xFE, ..] => Encoding::Utf16Le,
+            [0xFE, 0xFF, ..] => Encoding::Utf16Be,
+            _ if std::str::from_utf8(bytes).is_ok() => Encoding::Utf8,
+            _ if bytes.contains(&0) || has_utf8_sequence(bytes) => Encoding::Utf8,
+            _ if bytes.iter().any(|b| (0x80..0xA0).contains(b)) => Encoding::Cp1252,
+            _ => Encoding::Latin1,
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => decode_utf8(bytes),
+            Encoding::Utf8Bom => {
+                decode_utf8(bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(bytes))
+            }
+            Encoding::Latin1 => bytes.iter().map(|b| char::from(*b)).collect(),
+            Encoding::Cp1252 => bytes
+                .iter()
+                .map(|b| match b {
+                    0x80..0xA0 => CP1252[(b - 0x80) as usize].unwrap_or(escape(*b)),
+                    _ => char::from(*b),
+                })
+                .collect(),
+            Encoding::Utf16Le => {
+                decode_utf16(bytes.strip_prefix(&[0xFF, 0xFE]).unwrap_or(bytes), false)
+            }
+            Encoding::Utf16Be => {
+                decode_utf16(bytes.strip_prefix(&[0xFE, 0xFF]).unwrap_or(bytes), true)
+            }
+        }
+    }
+
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, String> {
+        let mut bytes = match self {
+            Encoding::Utf8Bom => vec![0xEF, 0xBB, 0xBF],
+            Encoding::Utf16Le => vec![0xFF, 0xFE],
+            Encoding::Utf16Be => vec![0xFE, 0xFF],
+            _ => Vec::new(),
+        };
+        for c in text.chars() {
+            if let Some(b) = escaped_byte(c) {
+                bytes.push(b);
+                continue;
+            }
+            match self {
+                Encoding::Utf8 | Encoding::Utf8Bom => {
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes())
+                }
+                Encoding::Latin1 => bytes.push(single_byte(c, self)?),
+                Encoding::Cp1252 => match CP1252.iter().position(|m| *m == Some(c)) {
+                    Some(i) => bytes.push(0x80 + i as u8),
+                    None if (0x80..0xA0).contains(&(c as u32)) => {
+                        return Err(conversion_error(c, self))
+                    }
+                    None => bytes.push(single_byte(c, self)?),
+                },
+                Encoding::Utf16Le | Encoding::Utf16Be => {
+                    for unit in c.encode_utf16(&mut [0; 2]) {
+                        match self {
+                            Encoding::Utf16Le => bytes.extend_from_slice(&unit.to_le_bytes()),
+                            _ => bytes.extend_from_slice(&unit.to_be_bytes()),
+                        }
+                    }
+                }
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+pub fn split_lines(text: &str) -> (Vec<&str>, bool, bool) {
+    let mut lines: Vec<&str> = text.split('\n').collect();
+    let eol = lines.len() > 1 && lines.last() == Some(&"");
+    if eol {
+        lines.pop();
+    }
+    // Only lines followed by a newline count towards CRLF, so a trailing
+    // '\r' on an unterminated last line stays part of the text.
+    let ended = if eol { lines.len() } else { lines.len() - 1 };
+    let crlf = ended > 0 && lines[..ended].iter().all(|l| l.ends_with('\r'));
+    if crlf {
+        for line in &mut lines[..ended] {
+            *line = &line[..line.len() - 1];
+        }
+    }
+    (lines, eol, crlf)
+}
+
+pub fn join_lines<'a>(lines: impl Iterator<Item = &'a str>, eol: bool, crlf: bool) -> String {
+    let newline = if crlf { "\r\n" } else { "\n" };
+    let mut text = lines.collect::<Vec<&str>>().join(newline);
+    if eol {
+        text.push_str(newline);
+    }
+    text
+}
+
+fn has_utf8_sequence(bytes: &[u8]) -> bool {
+    bytes.utf8_chunks().any(|chunk| !chunk.valid().is_ascii())
+}
+
+fn decode_utf8(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match escaped_byte(c) {
+                Some(_) => text.extend(c.encode_utf8(&mut [0; 4]).bytes().map(escape)),
+                None => text.push(c),
+            }
+        }
+        text.extend(chunk.invalid().iter().map(|b| escape(*b)));
+    }
+    text
+}
+
+fn decode_utf16(bytes: &[u8], big_endian: bool) -> String {
+    let pairs = bytes.chunks_exact(2);
+    let odd = pairs.remainder();
+    let units = pairs.map(|p| match big_endian {
+        true => u16::from_be_bytes([p[0], p[1]]),
+        false => u16::from_le_bytes([p[0], p[1]]),
+    });
+    let mut text = String::with_capacity(bytes.len() / 2);
+    for c in char::decode_utf16(units) {
+        let unit = match c {
+            Ok(c) if escaped_byte(c).is_none() => {
+                text.push(c);
+                continue;
+            }
+            Ok(c) => c as u16,
+            Err(e) => e.unpaired_surrogate(),
+        };
+        let raw = match big_endian {
+            true => unit.to_be_bytes(),
+            false => unit.to_le_bytes(),
+        };
+        text.extend(raw.iter().map(|b| escape(*b)));
+    }
+    text.extend(odd.iter().map(|b| escape(*b)));
+    text
+}
+
+fn single_byte(c: char, enc: &Encoding) -> Result<u8, String> {
+    u8::try_from(c as u32).map_err(|_| conversion_error(c, enc))
+}
+
+fn conversion_error(c: char, enc: &Encoding) -> String {
+    format!("Cannot convert '{}' to {}", c, enc.name())
+}
+
+fn escape(b: u8) -> char {
+    char::from_u32(ESCAPE_BASE + b as u32).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+pub fn escaped_byte(c: char) -> Option<u8> {
+    (c as u32)
+        .checked_sub(ESCAPE_BASE)
+        .and_then(|b| u8::try_from(b).ok())
+}
+
+pub fn escaped(c: char) -> Option<u32> {
+    match escaped_byte(c) {
+        Some(b) => Some(b as u32),
+        None if c != '\t' && c.is_control() => Some(c as u32),
+        None => None,
+    }
+}
+
+pub fn char_width(c: char) -> usize {
+    if escaped(c).is_some() {
+        4
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(enc: Encoding, bytes: &[u8]) {
+        let text = enc.decode(bytes);
+        let (lines, eol, crlf) = split_lines(&text);
+        let text = join_lines(lines.into_iter(), eol, crlf);
+        assert_eq!(enc.encode(&text).unwrap(), bytes, "{}", enc.name());
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        round_trip(Encoding::Utf8, b"ok \xff\xfe \xc3 \xe2\x82 end");
+        round_trip(Encoding::Utf8, "caf\u{e9} \u{f7ff}\u{f700}".as_bytes());
+        round_trip(Encoding::Utf8Bom, b"\xef\xbb\xbfa\x80b");
+    }
+
+    #[test]
+    fn lone_surrogates() {
+        round_trip(
+            Encoding::Utf16Le,
+            &[0xff, 0xfe, 0x61, 0x00, 0x00, 0xd8, 0x62, 0x00],
+        );
+        round_trip(Encoding::Utf16Be, &[0xfe, 0xff, 0xdc, 0x00, 0x00, 0x61]);
+        round_trip(Encoding::Utf16Le, &[0xff, 0xfe, 0x3d, 0xd8, 0x00, 0xde]);
+    }
+
+    #[test]
+    fn odd_trailing_byte() {
+        round_trip(Encoding::Utf16Le, &[0xff, 0xfe, 0x61, 0x00, 0x62]);
+        round_trip(Encoding::Utf16Be, &[0xfe, 0xff, 0x00, 0x61, 0x0a]);
+    }
+
+    #[test]
+    fn undefined_cp1252_bytes() {
+        round_trip(Encoding::Cp1252, b"\x80 \x81 \x8d \x8f \x90 \x9d \x9f");
+        round_trip(Encoding::Latin1, b"\x81\xe9\xff");
+    }
+
+    #[test]
+    fn line_endings() {
+        for bytes in [
+            &b""[..],
+            b"\n",
+            b"\r",
+            b"abc\r",
+            b"a\r\nb\r",
+            b"a\r\nb\r\n",
+            b"a\r\nb",
+            b"a\nb\r\n",
+            b"a\r\r\nb\r\n",
+            b"\r\n\r\n",
+            b"a\n\n",
+        ] {
+            round_trip(Encoding::Utf8, bytes);
+        }
+        assert_eq!(split_lines("a\r\nb\r"), (vec!["a", "b\r"], false, true));
+        assert_eq!(split_lines("abc\r"), (vec!["abc\r"], false, false));
+    }
+}
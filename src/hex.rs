@@ -0,0 +1,151 @@
+use std::cmp;
+
+pub const BYTES_PER_LINE: usize = 16;
+const OFFSET_WIDTH: usize = 10;
+const HEX_WIDTH: usize = BYTES_PER_LINE / 2 * 5;
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Pane {
+    #[default]
+    Hex,
+    Ascii,
+}
+
+#[derive(Default)]
+pub struct HexRows {
+    bytes: Vec<u8>,
+    pub cursor: usize,
+    pub low: bool,
+    pub pane: Pane,
+    y_offset: usize,
+}
+impl HexRows {
+    pub fn new(bytes: Vec<u8>, cursor: usize) -> Self {
+        let mut hex = Self {
+            bytes,
+            ..Default::default()
+        };
+        hex.goto(cursor, false);
+        hex
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn num_lines(&self) -> usize {
+        cmp::max(
+            self.bytes.len().div_ceil(BYTES_PER_LINE),
+            self.cursor / BYTES_PER_LINE + 1,
+        )
+    }
+
+    pub fn line(&self, y: usize) -> &[u8] {
+        let start = cmp::min(y * BYTES_PER_LINE, self.bytes.len());
+        let end = cmp::min(start + BYTES_PER_LINE, self.bytes.len());
+        &self.bytes[start..end]
+    }
+
+    pub fn y_offset(&self) -> usize {
+        self.y_offset
+    }
+
+    pub fn goto(&mut self, offset: usize, append: bool) {
+        let last = if append {
+            self.bytes.len()
+        } else {
+            self.bytes.len().saturating_sub(1)
+        };
+        self.cursor = cmp::min(offset, last);
+        self.low = false;
+    }
+
+    pub fn move_by(&mut self, delta: isize, append: bool) {
+        self.goto(self.cursor.saturating_add_signed(delta), append);
+    }
+
+    pub fn line_start(&mut self) {
+        self.goto(self.cursor - self.cursor % BYTES_PER_LINE, false);
+    }
+
+    pub fn line_end(&mut self, append: bool) {
+        let start = self.cursor - self.cursor % BYTES_PER_LINE;
+        self.goto(start + BYTES_PER_LINE - 1, append);
+    }
+
+    pub fn toggle_pane(&mut self) {
+        self.pane = match self.pane {
+            Pane::Hex => Pane::Ascii,
+            Pane::Ascii => Pane::Hex,
+        };
+        self.low = false;
+    }
+
+    pub fn overwrite(&mut self, c: char) -> Result<(), String> {
+        let value = match self.pane {
+            Pane::Hex => c.to_digit(16).ok_or("Not a hex digit")? as u8,
+            Pane::Ascii => u8::try_from(c as u32).map_err(|_| "Not a single byte character")?,
+        };
+        if self.cursor == self.bytes.len() {
+            self.bytes.push(0);
+        }
+        let byte = &mut self.bytes[self.cursor];
+        *byte = match (self.pane, self.low) {
+            (Pane::Ascii, _) => value,
+            (Pane::Hex, false) => value << 4 | *byte & 0x0f,
+            (Pane::Hex, true) => *byte & 0xf0 | value,
+        };
+        if self.pane == Pane::Hex && !self.low {
+            self.low = true;
+        } else {
+            self.goto(self.cursor + 1, true);
+        }
+        Ok(())
+    }
+
+    pub fn back(&mut self) {
+        if self.pane == Pane::Hex && self.low {
+            self.low = false;
+        } else if self.cursor > 0 {
+            self.cursor -= 1;
+            self.low = self.pane == Pane::Hex;
+        }
+    }
+
+    pub fn scroll(&mut self, height: usize) {
+        let y = self.cursor / BYTES_PER_LINE;
+        self.y_offset = cmp::min(self.y_offset, y);
+        if y >= self.y_offset + height {
+            self.y_offset = y + 1 - height;
+        }
+    }
+
+    pub fn screen_pos(&self) -> (usize, usize) {
+        let i = self.cursor % BYTES_PER_LINE;
+        let x = match self.pane {
+            Pane::Hex => hex_col(i) + self.low as usize,
+            Pane::Ascii => ascii_col(i),
+        };
+        (x, self.cursor / BYTES_PER_LINE - self.y_offset)
+    }
+}
+
+fn hex_col(i: usize) -> usize {
+    OFFSET_WIDTH + i / 2 * 5 + i % 2 * 2
+}
+
+fn ascii_col(i: usize) -> usize {
+    OFFSET_WIDTH + HEX_WIDTH + 1 + i
+}
+
+pub fn printable(b: u8) -> char {
+    if b.is_ascii_graphic() || b == b' ' {
+        b as char
+    } else {
+        '.'
+    }
+}
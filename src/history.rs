@@ -0,0 +1,88 @@
+use std::{fs, io, path::PathBuf};
+
+use crate::config_dir;
+
+const MAX_HISTORY: usize = 100;
+
+#[derive(Default)]
+pub struct History {
+    entries: Vec<String>,
+    pos: Option<usize>,
+    prefix: String,
+}
+impl History {
+    fn file() -> Option<PathBuf> {
+        Some(config_dir()?.join("history"))
+    }
+
+    pub fn load() -> Self {
+        let entries = Self::file()
+            .and_then(|f| fs::read_to_string(f).ok())
+            .map(|contents| contents.lines().map(String::from).collect())
+            .unwrap_or_default();
+        Self {
+            entries,
+            ..Default::default()
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let Some(file) = Self::file() else {
+            return Ok(());
+        };
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents: String = self.entries.iter().map(|e| format!("{}\n", e)).collect();
+        fs::write(file, contents)
+    }
+
+    pub fn add(&mut self, cmd: &str) {
+        self.reset();
+        if cmd.trim().is_empty() {
+            return;
+        }
+        self.entries.retain(|e| e != cmd);
+        self.entries.push(cmd.into());
+        if self.entries.len() > MAX_HISTORY {
+            self.entries.remove(0);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.pos = None;
+        self.prefix.clear();
+    }
+
+    pub fn older(&mut self, current: &str) -> Option<String> {
+        let pos = match self.pos {
+            Some(pos) => pos,
+            None => {
+                self.prefix = current.into();
+                self.entries.len()
+            }
+        };
+        let i = self.entries[..pos]
+            .iter()
+            .rposition(|e| e.starts_with(&self.prefix))?;
+        self.pos = Some(i);
+        Some(self.entries[i].clone())
+    }
+
+    pub fn newer(&mut self) -> Option<String> {
+        let pos = self.pos?;
+        match self.entries[pos + 1..]
+            .iter()
+            .position(|e| e.starts_with(&self.prefix))
+        {
+            Some(i) => {
+                self.pos = Some(pos + 1 + i);
+                Some(self.entries[pos + 1 + i].clone())
+            }
+            None => {
+                self.pos = None;
+                Some(self.prefix.clone())
+            }
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::TAB_SZ;
+
+const CINWORDS: &[&str] = &[
+    "if", "else", "while", "do", "for", "switch", "loop", "match",
+];
+
+pub fn indent_len(raw: &str) -> usize {
+    raw.len() - raw.trim_start().len()
+}
+
+pub fn indent_width(raw: &str) -> usize {
+    width(&raw[..indent_len(raw)], 0)
+}
+
+pub fn width(text: &str, from: usize) -> usize {
+    text.chars().fold(from, |col, c| match c {
+        '\t' => col + TAB_SZ - col % TAB_SZ,
+        _ => col + 1,
+    })
+}
+
+pub fn fill(from: usize, to: usize, expandtab: bool) -> String {
+    let mut text = String::new();
+    let mut col = from;
+    if !expandtab {
+        while col + TAB_SZ - col % TAB_SZ <= to {
+            text.push('\t');
+            col += TAB_SZ - col % TAB_SZ;
+        }
+    }
+    text.push_str(&" ".repeat(to.saturating_sub(col)));
+    text
+}
+
+pub fn opens_block(raw: &str, filetype: &str) -> bool {
+    let line = raw.trim_end();
+    if line.ends_with(['{', '(', '[']) {
+        return true;
+    }
+    match filetype {
+        "python" | "yaml" => line.ends_with(':'),
+        "lua" => line.ends_with(" do") || line.ends_with(" then"),
+        "ruby" => line.ends_with(" do") || line.trim_start().starts_with("def "),
+        "sh" => line.ends_with(" do") || line.ends_with(" then"),
+        _ => false,
+    }
+}
+
+pub fn starts_with_cinword(raw: &str) -> bool {
+    let line = raw.trim();
+    let word = line
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or_default();
+    CINWORDS.contains(&word) && !line.ends_with([';', '}', '{'])
+}
+
+pub fn closes_block(raw: &str) -> bool {
+    raw.trim_start().starts_with(['}', ')', ']'])
+}
+
+pub fn retab(raw: &str, expandtab: bool, all: bool) -> String {
+    let mut text = String::new();
+    let mut col = 0;
+    let mut rest = raw;
+    while !rest.is_empty() {
+        let len = rest.len() - rest.trim_start_matches([' ', '\t']).len();
+        if len == 0 {
+            let c = rest.chars().next().unwrap_or_default();
+            text.push(c);
+            col = width(&rest[..c.len_utf8()], col);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        let (white, tail) = rest.split_at(len);
+        let end = width(white, col);
+        if white.contains('\t') || (all && len > 1) {
+            text.push_str(&fill(col, end, expandtab));
+        } else {
+            text.push_str(white);
+        }
+        col = end;
+        rest = tail;
+    }
+    text
+}
@@ -0,0 +1,899 @@
+use std::{collections::HashMap, time::Duration};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+pub const TIMEOUT_LEN: Duration = Duration::from_millis(1000);
+const MAX_MAP_DEPTH: usize = 1000;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    Press(KeyCode, KeyModifiers),
+    Plug(String),
+}
+impl Key {
+    pub fn char(c: char) -> Self {
+        Key::Press(KeyCode::Char(c), KeyModifiers::NONE)
+    }
+
+    pub fn as_char(&self) -> Option<char> {
+        match self {
+            Key::Press(KeyCode::Char(c), m) if (*m - KeyModifiers::SHIFT).is_empty() => Some(*c),
+            _ => None,
+        }
+    }
+
+    pub fn to_notation(&self) -> String {
+        let (code, mods) = match self {
+            Key::Plug(name) => return format!("<Plug>{}", name),
+            Key::Press(code, mods) => (code, mods),
+        };
+        let name = match code {
+            KeyCode::Char(' ') => "Space".to_string(),
+            KeyCode::Char('<') => "lt".to_string(),
+            KeyCode::Char('|') => "Bar".to_string(),
+            KeyCode::Char(c) if mods.is_empty() => return c.to_string(),
+            KeyCode::Char(c) => c.to_string(),
+            KeyCode::Esc => "Esc".to_string(),
+            KeyCode::Enter => "CR".to_string(),
+            KeyCode::Tab => "Tab".to_string(),
+            KeyCode::BackTab => "S-Tab".to_string(),
+            KeyCode::Backspace => "BS".to_string(),
+            KeyCode::Delete => "Del".to_string(),
+            KeyCode::Insert => "Insert".to_string(),
+            KeyCode::Up => "Up".to_string(),
+            KeyCode::Down => "Down".to_string(),
+            KeyCode::Left => "Left".to_string(),
+            KeyCode::Right => "Right".to_string(),
+            KeyCode::Home => "Home".to_string(),
+            KeyCode::End => "End".to_string(),
+            KeyCode::PageUp => "PageUp".to_string(),
+            KeyCode::PageDown => "PageDown".to_string(),
+            KeyCode::F(n) => format!("F{}", n),
+            _ => "Nop".to_string(),
+        };
+        let mut prefix = String::new();
+        if mods.contains(KeyModifiers::CONTROL) {
+            prefix.push_str("C-");
+        }
+        if mods.contains(KeyModifiers::ALT) {
+            prefix.push_str("A-");
+        }
+        if mods.contains(KeyModifiers::SHIFT) {
+            prefix.push_str("S-");
+        }
+        format!("<{}{}>", prefix, name)
+    }
+}
+impl From<KeyEvent> for Key {
+    fn from(event: KeyEvent) -> Self {
+        let mut mods =
+            event.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+        if let KeyCode::Char(_) | KeyCode::BackTab = event.code {
+            mods -= KeyModifiers::SHIFT;
+        }
+        Key::Press(event.code, mods)
+    }
+}
+
+pub fn parse_keys(notation: &str, leader: &Key) -> Result<Vec<Key>, String> {
+    let mut keys = Vec::new();
+    let mut rest = notation;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>') {
+                let name = &rest[1..end];
+                if name.eq_ignore_ascii_case("plug") {
+                    let tail = &rest[end + 1..];
+                    let len = if tail.starts_with('(') {
+                        tail.find(')').map(|i| i + 1).unwrap_or(tail.len())
+                    } else {
+                        tail.find('<').unwrap_or(tail.len())
+                    };
+                    if len == 0 {
+                        return Err("Missing <Plug> name".into());
+                    }
+                    keys.push(Key::Plug(tail[..len].into()));
+                    rest = &tail[len..];
+                    continue;
+                }
+                if let Some(key) = parse_special(name, leader) {
+                    keys.push(key);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+        keys.push(Key::char(c));
+        rest = &rest[c.len_utf8()..];
+    }
+    if keys.is_empty() {
+        return Err("Empty key sequence".into());
+    }
+    Ok(keys)
+}
+
+fn parse_special(name: &str, leader: &Key) -> Option<Key> {
+    let mut mods = KeyModifiers::NONE;
+    let mut base = name;
+    while base.len() > 2 && base.as_bytes()[1] == b'-' {
+        match base.as_bytes()[0].to_ascii_uppercase() {
+            b'C' => mods |= KeyModifiers::CONTROL,
+            b'A' | b'M' => mods |= KeyModifiers::ALT,
+            b'S' => mods |= KeyModifiers::SHIFT,
+            _ => return None,
+        }
+        base = &base[2..];
+    }
+
+    let code = match base.to_ascii_lowercase().as_str() {
+        "leader" if mods.is_empty() => return Some(leader.clone()),
+        "esc" => KeyCode::Esc,
+        "cr" | "enter" | "return" => KeyCode::Enter,
+        "tab" if mods.contains(KeyModifiers::SHIFT) => {
+            mods -= KeyModifiers::SHIFT;
+            KeyCode::BackTab
+        }
+        "tab" => KeyCode::Tab,
+        "bs" | "backspace" => KeyCode::Backspace,
+        "del" | "delete" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        "bar" => KeyCode::Char('|'),
+        "bslash" => KeyCode::Char('\\'),
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "nop" => KeyCode::Null,
+        f if f.len() > 1 && f.starts_with('f') => KeyCode::F(f[1..].parse().ok()?),
+        _ if base.chars().count() == 1 && !mods.is_empty() => {
+            let c = base.chars().next()?;
+            let c = if mods.contains(KeyModifiers::CONTROL) {
+                c.to_ascii_lowercase()
+            } else if mods.contains(KeyModifiers::SHIFT) {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            };
+            mods -= KeyModifiers::SHIFT;
+            KeyCode::Char(c)
+        }
+        _ => return None,
+    };
+    Some(Key::Press(code, mods))
+}
+
+pub fn keys_to_string(keys: &[Key]) -> String {
+    keys.iter().map(|k| k.to_notation()).collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MapMode {
+    Normal,
+    Insert,
+    Command,
+    Visual,
+    Operator,
+}
+impl MapMode {
+    fn prefix(&self) -> char {
+        match self {
+            MapMode::Normal => 'n',
+            MapMode::Insert => 'i',
+            MapMode::Command => 'c',
+            MapMode::Visual => 'v',
+            MapMode::Operator => 'o',
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    EnterCommand,
+    NormalMode,
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Insert,
+    InsertStart,
+    Append,
+    AppendEnd,
+    OpenBelow,
+    OpenAbove,
+    GotoFirst,
+    GotoLast,
+    DeleteLine,
+    DeleteChar,
+    Delete,
+    Change,
+    Yank,
+    Paste,
+    PasteBefore,
+    SelectRegister,
+    Cancel,
+    Visual,
+    VisualLine,
+    VisualSwap,
+    Repeat,
+    Undo,
+    Redo,
+    RecordMacro,
+    ExecuteMacro,
+    ShiftRight,
+    ShiftLeft,
+    Reindent,
+    Filter,
+    IndentMore,
+    IndentLess,
+    DeleteWordBack,
+    DeleteLineBack,
+    InsertRegister,
+    InsertNormal,
+    CompleteNext,
+    CompletePrev,
+    ReplaceMode,
+    ReplaceChar,
+    ToggleCase,
+    JoinLines,
+    JoinLinesRaw,
+    Increment,
+    Decrement,
+    SetMark,
+    GotoMark,
+    GotoMarkLine,
+    JumpOlder,
+    JumpNewer,
+    InnerObject,
+    AroundObject,
+    NextWord,
+    NextWordEnd,
+    PrevWord,
+    PrevWordEnd,
+    NextBigWord,
+    NextBigWordEnd,
+    PrevBigWord,
+    PrevBigWordEnd,
+    LineStart,
+    LineEnd,
+    FirstColumn,
+    FindForward,
+    FindBackward,
+    TillForward,
+    TillBackward,
+    RepeatFind,
+    RepeatFindReverse,
+    MatchPair,
+    ParagraphForward,
+    ParagraphBackward,
+    SentenceForward,
+    SentenceBackward,
+    ScreenTop,
+    ScreenMiddle,
+    ScreenBottom,
+    ScrollHalfDown,
+    ScrollHalfUp,
+    PageDown,
+    PageUp,
+    ScrollDown,
+    ScrollUp,
+    ScrollTop,
+    ScrollCenter,
+    ScrollBottom,
+    DisplayDown,
+    DisplayUp,
+    DisplayLineStart,
+    DisplayLineEnd,
+    InsertTab,
+    BreakLine,
+    Backspace,
+    ExecCommand,
+    Save,
+}
+
+const ACTIONS: &[(Action, &str)] = &[
+    (Action::EnterCommand, "(enter-command)"),
+    (Action::NormalMode, "(normal-mode)"),
+    (Action::MoveUp, "(move-up)"),
+    (Action::MoveDown, "(move-down)"),
+    (Action::MoveLeft, "(move-left)"),
+    (Action::MoveRight, "(move-right)"),
+    (Action::Insert, "(insert)"),
+    (Action::InsertStart, "(insert-start)"),
+    (Action::Append, "(append)"),
+    (Action::AppendEnd, "(append-end)"),
+    (Action::OpenBelow, "(open-below)"),
+    (Action::OpenAbove, "(open-above)"),
+    (Action::GotoFirst, "(goto-first)"),
+    (Action::GotoLast, "(goto-last)"),
+    (Action::DeleteLine, "(delete-line)"),
+    (Action::DeleteChar, "(delete-char)"),
+    (Action::Delete, "(delete)"),
+    (Action::Change, "(change)"),
+    (Action::Yank, "(yank)"),
+    (Action::Paste, "(paste)"),
+    (Action::PasteBefore, "(paste-before)"),
+    (Action::SelectRegister, "(select-register)"),
+    (Action::Cancel, "(cancel)"),
+    (Action::Visual, "(visual)"),
+    (Action::VisualLine, "(visual-line)"),
+    (Action::VisualSwap, "(visual-swap)"),
+    (Action::Repeat, "(repeat)"),
+    (Action::Undo, "(undo)"),
+    (Action::Redo, "(redo)"),
+    (Action::RecordMacro, "(record-macro)"),
+    (Action::ExecuteMacro, "(execute-macro)"),
+    (Action::ShiftRight, "(shift-right)"),
+    (Action::ShiftLeft, "(shift-left)"),
+    (Action::Reindent, "(reindent)"),
+    (Action::Filter, "(filter)"),
+    (Action::IndentMore, "(indent-more)"),
+    (Action::IndentLess, "(indent-less)"),
+    (Action::DeleteWordBack, "(delete-word-back)"),
+    (Action::DeleteLineBack, "(delete-line-back)"),
+    (Action::InsertRegister, "(insert-register)"),
+    (Action::InsertNormal, "(insert-normal)"),
+    (Action::CompleteNext, "(complete-next)"),
+    (Action::CompletePrev, "(complete-prev)"),
+    (Action::ReplaceMode, "(replace-mode)"),
+    (Action::ReplaceChar, "(replace-char)"),
+    (Action::ToggleCase, "(toggle-case)"),
+    (Action::JoinLines, "(join-lines)"),
+    (Action::JoinLinesRaw, "(join-lines-raw)"),
+    (Action::Increment, "(increment)"),
+    (Action::Decrement, "(decrement)"),
+    (Action::SetMark, "(set-mark)"),
+    (Action::GotoMark, "(goto-mark)"),
+    (Action::GotoMarkLine, "(goto-mark-line)"),
+    (Action::JumpOlder, "(jump-older)"),
+    (Action::JumpNewer, "(jump-newer)"),
+    (Action::InnerObject, "(inner-object)"),
+    (Action::AroundObject, "(around-object)"),
+    (Action::NextWord, "(next-word)"),
+    (Action::NextWordEnd, "(next-word-end)"),
+    (Action::PrevWord, "(prev-word)"),
+    (Action::PrevWordEnd, "(prev-word-end)"),
+    (Action::NextBigWord, "(next-big-word)"),
+    (Action::NextBigWordEnd, "(next-big-word-end)"),
+    (Action::PrevBigWord, "(prev-big-word)"),
+    (Action::PrevBigWordEnd, "(prev-big-word-end)"),
+    (Action::LineStart, "(line-start)"),
+    (Action::LineEnd, "(line-end)"),
+    (Action::FirstColumn, "(first-column)"),
+    (Action::FindForward, "(find-forward)"),
+    (Action::FindBackward, "(find-backward)"),
+    (Action::TillForward, "(till-forward)"),
+    (Action::TillBackward, "(till-backward)"),
+    (Action::RepeatFind, "(repeat-find)"),
+    (Action::RepeatFindReverse, "(repeat-find-reverse)"),
+    (Action::MatchPair, "(match-pair)"),
+    (Action::ParagraphForward, "(paragraph-forward)"),
+    (Action::ParagraphBackward, "(paragraph-backward)"),
+    (Action::SentenceForward, "(sentence-forward)"),
+    (Action::SentenceBackward, "(sentence-backward)"),
+    (Action::ScreenTop, "(screen-top)"),
+    (Action::ScreenMiddle, "(screen-middle)"),
+    (Action::ScreenBottom, "(screen-bottom)"),
+    (Action::ScrollHalfDown, "(scroll-half-down)"),
+    (Action::ScrollHalfUp, "(scroll-half-up)"),
+    (Action::PageDown, "(page-down)"),
+    (Action::PageUp, "(page-up)"),
+    (Action::ScrollDown, "(scroll-down)"),
+    (Action::ScrollUp, "(scroll-up)"),
+    (Action::ScrollTop, "(scroll-top)"),
+    (Action::ScrollCenter, "(scroll-center)"),
+    (Action::ScrollBottom, "(scroll-bottom)"),
+    (Action::DisplayDown, "(display-down)"),
+    (Action::DisplayUp, "(display-up)"),
+    (Action::DisplayLineStart, "(display-line-start)"),
+    (Action::DisplayLineEnd, "(display-line-end)"),
+    (Action::InsertTab, "(insert-tab)"),
+    (Action::BreakLine, "(break-line)"),
+    (Action::Backspace, "(backspace)"),
+    (Action::ExecCommand, "(exec-command)"),
+    (Action::Save, "(save)"),
+];
+
+const MOTION_DEFAULTS: &[(&str, Action)] = &[
+    ("k", Action::MoveUp),
+    ("<Up>", Action::MoveUp),
+    ("j", Action::MoveDown),
+    ("<Down>", Action::MoveDown),
+    ("h", Action::MoveLeft),
+    ("<Left>", Action::MoveLeft),
+    ("l", Action::MoveRight),
+    ("<Right>", Action::MoveRight),
+    ("gg", Action::GotoFirst),
+    ("G", Action::GotoLast),
+    ("w", Action::NextWord),
+    ("e", Action::NextWordEnd),
+    ("b", Action::PrevWord),
+    ("ge", Action::PrevWordEnd),
+    ("W", Action::NextBigWord),
+    ("E", Action::NextBigWordEnd),
+    ("B", Action::PrevBigWord),
+    ("gE", Action::PrevBigWordEnd),
+    ("_", Action::LineStart),
+    ("$", Action::LineEnd),
+    ("0", Action::FirstColumn),
+    ("f", Action::FindForward),
+    ("F", Action::FindBackward),
+    ("t", Action::TillForward),
+    ("T", Action::TillBackward),
+    (";", Action::RepeatFind),
+    (",", Action::RepeatFindReverse),
+    ("%", Action::MatchPair),
+    ("}", Action::ParagraphForward),
+    ("{", Action::ParagraphBackward),
+    (")", Action::SentenceForward),
+    ("(", Action::SentenceBackward),
+    ("H", Action::ScreenTop),
+    ("M", Action::ScreenMiddle),
+    ("L", Action::ScreenBottom),
+    ("`", Action::GotoMark),
+    ("'", Action::GotoMarkLine),
+    ("gj", Action::DisplayDown),
+    ("gk", Action::DisplayUp),
+    ("g0", Action::DisplayLineStart),
+    ("g$", Action::DisplayLineEnd),
+];
+
+const NORMAL_DEFAULTS: &[(&str, Action)] = &[
+    (":", Action::EnterCommand),
+    ("<Esc>", Action::Cancel),
+    ("i", Action::Insert),
+    ("I", Action::InsertStart),
+    ("a", Action::Append),
+    ("A", Action::AppendEnd),
+    ("o", Action::OpenBelow),
+    ("O", Action::OpenAbove),
+    ("v", Action::Visual),
+    ("V", Action::VisualLine),
+    ("R", Action::ReplaceMode),
+    ("x", Action::DeleteChar),
+    ("r", Action::ReplaceChar),
+    ("~", Action::ToggleCase),
+    ("J", Action::JoinLines),
+    ("gJ", Action::JoinLinesRaw),
+    ("<C-a>", Action::Increment),
+    ("<C-x>", Action::Decrement),
+    ("d", Action::Delete),
+    ("c", Action::Change),
+    ("y", Action::Yank),
+    (">", Action::ShiftRight),
+    ("<lt>", Action::ShiftLeft),
+    ("=", Action::Reindent),
+    ("!", Action::Filter),
+    ("p", Action::Paste),
+    ("P", Action::PasteBefore),
+    ("\"", Action::SelectRegister),
+    (".", Action::Repeat),
+    ("u", Action::Undo),
+    ("<C-r>", Action::Redo),
+    ("q", Action::RecordMacro),
+    ("@", Action::ExecuteMacro),
+    ("m", Action::SetMark),
+    ("<C-o>", Action::JumpOlder),
+    ("<C-i>", Action::JumpNewer),
+    ("<Tab>", Action::JumpNewer),
+    ("<C-d>", Action::ScrollHalfDown),
+    ("<C-u>", Action::ScrollHalfUp),
+    ("<C-f>", Action::PageDown),
+    ("<PageDown>", Action::PageDown),
+    ("<C-b>", Action::PageUp),
+    ("<PageUp>", Action::PageUp),
+    ("<C-e>", Action::ScrollDown),
+    ("<C-y>", Action::ScrollUp),
+    ("zt", Action::ScrollTop),
+    ("zz", Action::ScrollCenter),
+    ("zb", Action::ScrollBottom),
+    ("<C-s>", Action::Save),
+];
+
+const OPERATOR_DEFAULTS: &[(&str, Action)] = &[
+    ("<Esc>", Action::Cancel),
+    ("d", Action::Delete),
+    ("c", Action::Change),
+    ("y", Action::Yank),
+    (">", Action::ShiftRight),
+    ("<lt>", Action::ShiftLeft),
+    ("=", Action::Reindent),
+    ("!", Action::Filter),
+    ("i", Action::InnerObject),
+    ("a", Action::AroundObject),
+];
+
+const VISUAL_DEFAULTS: &[(&str, Action)] = &[
+    ("<Esc>", Action::NormalMode),
+    ("v", Action::Visual),
+    ("V", Action::VisualLine),
+    ("o", Action::VisualSwap),
+    (":", Action::EnterCommand),
+    ("i", Action::InnerObject),
+    ("a", Action::AroundObject),
+    ("d", Action::Delete),
+    ("x", Action::Delete),
+    ("c", Action::Change),
+    ("s", Action::Change),
+    ("y", Action::Yank),
+    (">", Action::ShiftRight),
+    ("<lt>", Action::ShiftLeft),
+    ("=", Action::Reindent),
+    ("!", Action::Filter),
+    ("\"", Action::SelectRegister),
+    ("<C-d>", Action::ScrollHalfDown),
+    ("<C-u>", Action::ScrollHalfUp),
+    ("<C-f>", Action::PageDown),
+    ("<C-b>", Action::PageUp),
+    ("<C-e>", Action::ScrollDown),
+    ("<C-y>", Action::ScrollUp),
+];
+
+const INSERT_DEFAULTS: &[(&str, Action)] = &[
+    ("<Esc>", Action::NormalMode),
+    ("<Up>", Action::MoveUp),
+    ("<Down>", Action::MoveDown),
+    ("<Left>", Action::MoveLeft),
+    ("<Right>", Action::MoveRight),
+    ("<Tab>", Action::InsertTab),
+    ("<CR>", Action::BreakLine),
+    ("<BS>", Action::Backspace),
+    ("<Del>", Action::DeleteChar),
+    ("<Home>", Action::FirstColumn),
+    ("<End>", Action::LineEnd),
+    ("<PageUp>", Action::PageUp),
+    ("<PageDown>", Action::PageDown),
+    ("<C-w>", Action::DeleteWordBack),
+    ("<C-u>", Action::DeleteLineBack),
+    ("<C-r>", Action::InsertRegister),
+    ("<C-o>", Action::InsertNormal),
+    ("<C-n>", Action::CompleteNext),
+    ("<C-p>", Action::CompletePrev),
+    ("<C-t>", Action::IndentMore),
+    ("<C-d>", Action::IndentLess),
+    ("<C-s>", Action::Save),
+];
+
+const COMMAND_DEFAULTS: &[(&str, Action)] = &[
+    ("<Esc>", Action::NormalMode),
+    ("<Left>", Action::MoveLeft),
+    ("<Right>", Action::MoveRight),
+    ("<CR>", Action::ExecCommand),
+    ("<BS>", Action::Backspace),
+    ("<Up>", Action::MoveUp),
+    ("<Down>", Action::MoveDown),
+    ("<Home>", Action::FirstColumn),
+    ("<End>", Action::LineEnd),
+    ("<C-b>", Action::FirstColumn),
+    ("<C-e>", Action::LineEnd),
+    ("<C-w>", Action::DeleteWordBack),
+    ("<C-u>", Action::DeleteLineBack),
+    ("<Tab>", Action::CompleteNext),
+    ("<S-Tab>", Action::CompletePrev),
+    ("<C-s>", Action::Save),
+];
+
+#[derive(Clone, Debug)]
+pub enum Target {
+    Action(Action),
+    Keys { rhs: Vec<Key>, noremap: bool },
+}
+
+#[derive(Default)]
+struct KeyTrie {
+    children: HashMap<Key, KeyTrie>,
+    target: Option<Target>,
+}
+impl KeyTrie {
+    fn insert(&mut self, keys: &[Key], target: Target) {
+        let node = keys.iter().fold(self, |node, key| {
+            node.children.entry(key.clone()).or_default()
+        });
+        node.target = Some(target);
+    }
+
+    fn remove(&mut self, keys: &[Key]) -> bool {
+        match keys.split_first() {
+            None => self.target.take().is_some(),
+            Some((key, rest)) => {
+                let Some(child) = self.children.get_mut(key) else {
+                    return false;
+                };
+                let removed = child.remove(rest);
+                if child.target.is_none() && child.children.is_empty() {
+                    self.children.remove(key);
+                }
+                removed
+            }
+        }
+    }
+
+    fn get(&self, keys: &[Key]) -> Option<&KeyTrie> {
+        keys.iter()
+            .try_fold(self, |node, key| node.children.get(key))
+    }
+
+    fn collect(&self, prefix: &mut Vec<Key>, out: &mut Vec<(Vec<Key>, Target)>) {
+        if let Some(target) = &self.target {
+            out.push((prefix.clone(), target.clone()));
+        }
+        for (key, child) in &self.children {
+            prefix.push(key.clone());
+            child.collect(prefix, out);
+            prefix.pop();
+        }
+    }
+}
+
+pub enum Resolution {
+    Pending,
+    Mapped(usize, Target),
+    Unmapped,
+}
+
+pub struct KeyMaps {
+    defaults: HashMap<MapMode, KeyTrie>,
+    user: HashMap<MapMode, KeyTrie>,
+    pub leader: Key,
+    depth: usize,
+}
+impl KeyMaps {
+    pub fn new() -> Self {
+        let mut defaults: HashMap<MapMode, KeyTrie> = HashMap::new();
+        let tables = [
+            (MapMode::Normal, MOTION_DEFAULTS),
+            (MapMode::Normal, NORMAL_DEFAULTS),
+            (MapMode::Operator, MOTION_DEFAULTS),
+            (MapMode::Operator, OPERATOR_DEFAULTS),
+            (MapMode::Visual, MOTION_DEFAULTS),
+            (MapMode::Visual, VISUAL_DEFAULTS),
+            (MapMode::Insert, INSERT_DEFAULTS),
+            (MapMode::Command, COMMAND_DEFAULTS),
+        ];
+        for (mode, table) in tables {
+            let trie = defaults.entry(mode).or_default();
+            for (lhs, action) in table {
+                let keys = parse_keys(lhs, &Key::char('\\')).expect("Invalid default mapping");
+                trie.insert(&keys, Target::Action(*action));
+            }
+        }
+        for mode in [
+            MapMode::Normal,
+            MapMode::Insert,
+            MapMode::Command,
+            MapMode::Visual,
+            MapMode::Operator,
+        ] {
+            let trie = defaults.entry(mode).or_default();
+            for (action, name) in ACTIONS {
+                trie.insert(&[Key::Plug(name.to_string())], Target::Action(*action));
+            }
+        }
+
+        Self {
+            defaults,
+            user: HashMap::new(),
+            leader: Key::char('\\'),
+            depth: 0,
+        }
+    }
+
+    pub fn map(
+        &mut self,
+        modes: &[MapMode],
+        lhs: &str,
+        rhs: &str,
+        noremap: bool,
+    ) -> Result<(), String> {
+        let lhs = parse_keys(lhs, &self.leader)?;
+        let rhs = parse_keys(rhs, &self.leader)?;
+        for mode in modes {
+            self.user.entry(*mode).or_default().insert(
+                &lhs,
+                Target::Keys {
+                    rhs: rhs.clone(),
+                    noremap,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    pub fn unmap(&mut self, modes: &[MapMode], lhs: &str) -> Result<(), String> {
+        let lhs = parse_keys(lhs, &self.leader)?;
+        let mut removed = false;
+        for mode in modes {
+            if let Some(trie) = self.user.get_mut(mode) {
+                removed |= trie.remove(&lhs);
+            }
+        }
+        if removed {
+            Ok(())
+        } else {
+            Err(format!("No such mapping: {}", keys_to_string(&lhs)))
+        }
+    }
+
+    pub fn describe(&self, modes: &[MapMode], lhs: Option<&str>) -> Result<Vec<String>, String> {
+        let lhs = lhs.map(|l| parse_keys(l, &self.leader)).transpose()?;
+        let mut lines = Vec::new();
+        for mode in modes {
+            let Some(trie) = self.user.get(mode) else {
+                continue;
+            };
+            let mut found = Vec::new();
+            trie.collect(&mut Vec::new(), &mut found);
+            found.sort_by_key(|(keys, _)| keys_to_string(keys));
+            for (keys, target) in found {
+                if lhs.as_ref().is_some_and(|l| !keys.starts_with(l)) {
+                    continue;
+                }
+                if let Target::Keys { rhs, noremap } = target {
+                    lines.push(format!(
+                        "{}  {} {}{}",
+                        mode.prefix(),
+                        keys_to_string(&keys),
+                        if noremap { "*" } else { "" },
+                        keys_to_string(&rhs)
+                    ));
+                }
+            }
+        }
+        Ok(lines)
+    }
+
+    pub fn resolve(&self, mode: MapMode, pending: &[(Key, bool)], flush: bool) -> Resolution {
+        let keys: Vec<Key> = pending.iter().map(|(k, _)| k.clone()).collect();
+        let tries = |remap: bool| {
+            let user = self.user.get(&mode).filter(|_| remap);
+            [user, self.defaults.get(&mode)].into_iter().flatten()
+        };
+
+        let remap = pending.iter().all(|(_, r)| *r);
+        let longer = tries(remap).any(|trie| {
+            trie.get(&keys)
+                .is_some_and(|node| !node.children.is_empty())
+        });
+        if longer && !flush {
+            return Resolution::Pending;
+        }
+
+        for len in (1..=keys.len()).rev() {
+            let remap = pending[..len].iter().all(|(_, r)| *r);
+            let target = tries(remap)
+                .find_map(|trie| trie.get(&keys[..len]).and_then(|node| node.target.clone()));
+            if let Some(target) = target {
+                return Resolution::Mapped(len, target);
+            }
+        }
+        Resolution::Unmapped
+    }
+
+    pub fn enter_mapping(&mut self) -> bool {
+        self.depth += 1;
+        self.depth <= MAX_MAP_DEPTH
+    }
+
+    pub fn reset_depth(&mut self) {
+        self.depth = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(notation: &str) -> Vec<Key> {
+        parse_keys(notation, &Key::char(',')).unwrap()
+    }
+
+    fn ctrl(c: char) -> Key {
+        Key::Press(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    #[test]
+    fn special_keys() {
+        assert_eq!(
+            keys("a<lt>b"),
+            [Key::char('a'), Key::char('<'), Key::char('b')]
+        );
+        assert_eq!(
+            keys("<C-X><esc><CR>"),
+            [
+                ctrl('x'),
+                Key::Press(KeyCode::Esc, KeyModifiers::NONE),
+                Key::Press(KeyCode::Enter, KeyModifiers::NONE),
+            ]
+        );
+        assert_eq!(keys("<S-a><Space>"), [Key::char('A'), Key::char(' ')]);
+        assert_eq!(
+            keys("<S-Tab>"),
+            [Key::Press(KeyCode::BackTab, KeyModifiers::NONE)]
+        );
+        assert_eq!(keys("<Leader>x"), [Key::char(','), Key::char('x')]);
+        assert_eq!(
+            keys("<Plug>(name)y"),
+            [Key::Plug("(name)".into()), Key::char('y')]
+        );
+        assert_eq!(keys_to_string(&keys("<lt>a<C-x><Bar>")), "<lt>a<C-x><Bar>");
+    }
+
+    #[test]
+    fn unknown_names_are_literal() {
+        assert_eq!(
+            keys("<foo>"),
+            "<foo>".chars().map(Key::char).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            keys("<X-a>"),
+            "<X-a>".chars().map(Key::char).collect::<Vec<_>>()
+        );
+        assert_eq!(keys("a<"), [Key::char('a'), Key::char('<')]);
+        assert!(parse_keys("", &Key::char(',')).is_err());
+        assert!(parse_keys("<Plug>", &Key::char(',')).is_err());
+    }
+
+    fn pending(notation: &str, remap: bool) -> Vec<(Key, bool)> {
+        keys(notation).into_iter().map(|k| (k, remap)).collect()
+    }
+
+    fn resolve(maps: &KeyMaps, notation: &str, remap: bool, flush: bool) -> String {
+        match maps.resolve(MapMode::Normal, &pending(notation, remap), flush) {
+            Resolution::Pending => "pending".into(),
+            Resolution::Mapped(len, Target::Keys { rhs, .. }) => {
+                format!("{} {}", len, keys_to_string(&rhs))
+            }
+            Resolution::Mapped(len, Target::Action(action)) => format!("{} {:?}", len, action),
+            Resolution::Unmapped => "unmapped".into(),
+        }
+    }
+
+    #[test]
+    fn prefix_of_a_longer_mapping() {
+        let mut maps = KeyMaps::new();
+        maps.map(&[MapMode::Normal], ",a", "x", false).unwrap();
+        maps.map(&[MapMode::Normal], ",ab", "y", true).unwrap();
+        assert_eq!(resolve(&maps, ",", true, false), "pending");
+        assert_eq!(resolve(&maps, ",a", true, false), "pending");
+        assert_eq!(resolve(&maps, ",ab", true, false), "3 y");
+        assert_eq!(resolve(&maps, ",ac", true, false), "2 x");
+        assert_eq!(resolve(&maps, ",c", true, false), "1 RepeatFindReverse");
+    }
+
+    #[test]
+    fn timeout_flushes_the_longest_match() {
+        let mut maps = KeyMaps::new();
+        maps.map(&[MapMode::Normal], ",a", "x", false).unwrap();
+        maps.map(&[MapMode::Normal], ",ab", "y", false).unwrap();
+        assert_eq!(resolve(&maps, ",a", true, true), "2 x");
+        assert_eq!(resolve(&maps, ",", true, true), "1 RepeatFindReverse");
+        assert_eq!(resolve(&maps, "g", true, false), "pending");
+        assert_eq!(resolve(&maps, "g", true, true), "unmapped");
+        assert_eq!(resolve(&maps, "¤", true, false), "unmapped");
+    }
+
+    #[test]
+    fn noremap_keys_skip_user_mappings() {
+        let mut maps = KeyMaps::new();
+        maps.map(&[MapMode::Normal], "x", "dd", false).unwrap();
+        assert_eq!(resolve(&maps, "x", true, false), "1 dd");
+        assert_eq!(resolve(&maps, "x", false, false), "1 DeleteChar");
+        maps.unmap(&[MapMode::Normal], "x").unwrap();
+        assert_eq!(resolve(&maps, "x", true, false), "1 DeleteChar");
+        assert!(maps.unmap(&[MapMode::Normal], "x").is_err());
+    }
+
+    #[test]
+    fn mapping_depth_is_limited() {
+        let mut maps = KeyMaps::new();
+        assert!((0..MAX_MAP_DEPTH).all(|_| maps.enter_mapping()));
+        assert!(!maps.enter_mapping());
+        maps.reset_depth();
+        assert!(maps.enter_mapping());
+    }
+}
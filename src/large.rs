@@ -0,0 +1,189 @@
+use std::{
+    cmp,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use crate::{editor::render_text, encoding::Encoding};
+
+pub const MIB: u64 = 1 << 20;
+const CHUNK_SIZE: usize = 1 << 20;
+const PAGE_LIMIT: u64 = 4 * MIB;
+
+pub struct LargeRows {
+    file: File,
+    size: u64,
+    pub encoding: Encoding,
+    offsets: Arc<Mutex<Vec<u64>>>,
+    scanned: Arc<AtomicU64>,
+    shown: u64,
+    pub cx: usize,
+    pub cy: usize,
+    y_offset: usize,
+    x_offset: usize,
+}
+impl LargeRows {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut head = vec![0; cmp::min(size, CHUNK_SIZE as u64) as usize];
+        file.read_exact(&mut head)?;
+        let encoding = match Encoding::detect(&head) {
+            Encoding::Utf16Le | Encoding::Utf16Be => Encoding::Utf8,
+            enc => enc,
+        };
+
+        let offsets = Arc::new(Mutex::new(vec![0]));
+        let scanned = Arc::new(AtomicU64::new(0));
+        let reader = File::open(path)?;
+        let (o, s) = (Arc::clone(&offsets), Arc::clone(&scanned));
+        thread::spawn(move || index_lines(reader, o, s));
+        Ok(Self {
+            file,
+            size,
+            encoding,
+            offsets,
+            scanned,
+            shown: 0,
+            cx: 0,
+            cy: 0,
+            y_offset: 0,
+            x_offset: 0,
+        })
+    }
+
+    pub fn indexing(&self) -> bool {
+        self.scanned.load(Ordering::Acquire) < self.size
+    }
+
+    pub fn progress(&self) -> u64 {
+        match self.size {
+            0 => 100,
+            size => cmp::min(self.scanned.load(Ordering::Acquire), size) * 100 / size,
+        }
+    }
+
+    pub fn poll_progress(&mut self) -> bool {
+        let scanned = self.scanned.load(Ordering::Acquire);
+        let changed = scanned != self.shown;
+        self.shown = scanned;
+        changed
+    }
+
+    pub fn num_rows(&self) -> usize {
+        let offsets = self.offsets.lock().unwrap_or_else(|e| e.into_inner());
+        self.rows_in(&offsets)
+    }
+
+    fn rows_in(&self, offsets: &[u64]) -> usize {
+        let last = offsets.last().copied().unwrap_or(0);
+        let rows = if self.indexing() || (last == self.size && self.size > 0) {
+            offsets.len() - 1
+        } else {
+            offsets.len()
+        };
+        cmp::max(rows, 1)
+    }
+
+    pub fn page(&self, top: usize, count: usize) -> Vec<String> {
+        let (start, end, n) = {
+            let offsets = self.offsets.lock().unwrap_or_else(|e| e.into_inner());
+            let bottom = cmp::min(top + count, self.rows_in(&offsets));
+            if top >= bottom || top >= offsets.len() {
+                return Vec::new();
+            }
+            let end = offsets.get(bottom).copied().unwrap_or(self.size);
+            (offsets[top], end, bottom - top)
+        };
+        let mut bytes = Vec::new();
+        let mut file = &self.file;
+        let read = file.seek(SeekFrom::Start(start)).and_then(|_| {
+            file.take(cmp::min(end - start, PAGE_LIMIT))
+                .read_to_end(&mut bytes)
+        });
+        if read.is_err() {
+            return Vec::new();
+        }
+        bytes
+            .split(|b| *b == b'\n')
+            .take(n)
+            .map(|line| {
+                let line = line.strip_suffix(b"\r").unwrap_or(line);
+                render_text(&self.encoding.decode(line))
+            })
+            .collect()
+    }
+
+    pub fn goto(&mut self, y: usize) {
+        self.cy = cmp::min(y, self.num_rows() - 1);
+        self.clamp_x();
+    }
+
+    pub fn move_x(&mut self, delta: isize) {
+        self.cx = self.cx.saturating_add_signed(delta);
+        self.clamp_x();
+    }
+
+    fn clamp_x(&mut self) {
+        let len = self
+            .page(self.cy, 1)
+            .first()
+            .map_or(0, |line| line.chars().count());
+        self.cx = cmp::min(self.cx, len.saturating_sub(1));
+    }
+
+    pub fn y_offset(&self) -> usize {
+        self.y_offset
+    }
+
+    pub fn x_offset(&self) -> usize {
+        self.x_offset
+    }
+
+    pub fn scroll(&mut self, width: usize, height: usize) {
+        self.y_offset = cmp::min(self.y_offset, self.cy);
+        if self.cy >= self.y_offset + height {
+            self.y_offset = self.cy + 1 - height;
+        }
+        self.x_offset = cmp::min(self.x_offset, self.cx);
+        if self.cx >= self.x_offset + width {
+            self.x_offset = self.cx + 1 - width;
+        }
+    }
+
+    pub fn screen_pos(&self) -> (usize, usize) {
+        (self.cx - self.x_offset, self.cy - self.y_offset)
+    }
+}
+
+fn index_lines(mut file: File, offsets: Arc<Mutex<Vec<u64>>>, scanned: Arc<AtomicU64>) {
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut pos = 0;
+    loop {
+        if Arc::strong_count(&offsets) == 1 {
+            return;
+        }
+        let n = match file.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        let found = buf[..n]
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| **b == b'\n')
+            .map(|(i, _)| pos + i as u64 + 1);
+        offsets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend(found);
+        pos += n as u64;
+        scanned.store(pos, Ordering::Release);
+    }
+    scanned.store(u64::MAX, Ordering::Release);
+}
@@ -1,11 +1,38 @@
+mod autocmd;
+mod compress;
 mod editor;
+mod encoding;
+mod hex;
+mod history;
+mod indent;
+mod keymap;
+mod large;
+mod mark;
+mod motion;
+mod options;
 mod out;
+mod pattern;
+mod range;
+mod register;
+mod script;
+mod undo;
 
 use editor::Editor;
-use std::io::{self};
+use std::{
+    env,
+    io::{self},
+    path::PathBuf,
+};
 
 pub const TAB_SZ: usize = 8;
 
+pub fn config_dir() -> Option<PathBuf> {
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+    Some(config.join("sage"))
+}
+
 fn main() -> io::Result<()> {
     let mut editor = Editor::new()?;
 
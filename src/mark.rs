@@ -0,0 +1,119 @@
+use std::{collections::HashMap, fs, io, path::PathBuf};
+
+use crate::config_dir;
+
+const MAX_JUMPS: usize = 100;
+
+pub type GlobalMarks = HashMap<char, (PathBuf, (usize, usize))>;
+
+fn marks_file() -> Option<PathBuf> {
+    Some(config_dir()?.join("marks"))
+}
+
+pub fn load_global() -> GlobalMarks {
+    let Some(contents) = marks_file().and_then(|f| fs::read_to_string(f).ok()) else {
+        return GlobalMarks::new();
+    };
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut it = line.splitn(4, ' ');
+            let name = it.next()?.chars().next().filter(char::is_ascii_uppercase)?;
+            let y = it.next()?.parse().ok()?;
+            let x = it.next()?.parse().ok()?;
+            Some((name, (PathBuf::from(it.next()?), (x, y))))
+        })
+        .collect()
+}
+
+pub fn save_global(marks: &GlobalMarks) -> io::Result<()> {
+    let Some(file) = marks_file() else {
+        return Ok(());
+    };
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut names: Vec<_> = marks.keys().collect();
+    names.sort();
+    let contents: String = names
+        .into_iter()
+        .map(|name| {
+            let (path, (x, y)) = &marks[name];
+            format!("{} {} {} {}\n", name, y, x, path.display())
+        })
+        .collect();
+    fs::write(file, contents)
+}
+
+#[derive(Default)]
+pub struct Marks {
+    marks: HashMap<char, (usize, usize)>,
+    jumps: Vec<(usize, usize)>,
+    jump: usize,
+}
+impl Marks {
+    pub fn get(&self, name: char) -> Option<(usize, usize)> {
+        self.marks.get(&name).copied()
+    }
+
+    pub fn set(&mut self, name: char, pos: (usize, usize)) {
+        self.marks.insert(name, pos);
+    }
+
+    pub fn list(&self) -> Vec<(char, (usize, usize))> {
+        let mut marks: Vec<_> = self.marks.iter().map(|(c, pos)| (*c, *pos)).collect();
+        marks.sort_by_key(|(c, _)| (c.is_ascii_alphabetic(), *c));
+        marks
+    }
+
+    pub fn global(&self) -> impl Iterator<Item = (char, (usize, usize))> + '_ {
+        self.marks
+            .iter()
+            .filter(|(c, _)| c.is_ascii_uppercase())
+            .map(|(c, pos)| (*c, *pos))
+    }
+
+    pub fn push_jump(&mut self, pos: (usize, usize)) {
+        self.jumps.retain(|(_, y)| *y != pos.1);
+        self.jumps.push(pos);
+        if self.jumps.len() > MAX_JUMPS {
+            self.jumps.remove(0);
+        }
+        self.jump = self.jumps.len();
+    }
+
+    pub fn jump_back(&mut self, from: (usize, usize), count: usize) -> Option<(usize, usize)> {
+        if self.jump == self.jumps.len() {
+            self.push_jump(from);
+            self.jump -= 1;
+        }
+        let target = self.jump.checked_sub(count)?;
+        self.jump = target;
+        self.jumps.get(target).copied()
+    }
+
+    pub fn jump_forward(&mut self, count: usize) -> Option<(usize, usize)> {
+        let target = self.jump + count;
+        let pos = self.jumps.get(target).copied()?;
+        self.jump = target;
+        Some(pos)
+    }
+
+    pub fn shift(&mut self, i: usize, added: usize, removed: usize) {
+        let moved = |y: usize| {
+            if y >= i + removed {
+                y + added - removed
+            } else {
+                i
+            }
+        };
+        self.marks
+            .retain(|c, (_, y)| !c.is_ascii_alphabetic() || *y < i || *y >= i + removed);
+        for (_, y) in self.marks.values_mut().filter(|(_, y)| *y >= i) {
+            *y = moved(*y);
+        }
+        for (_, y) in self.jumps.iter_mut().filter(|(_, y)| *y >= i) {
+            *y = moved(*y);
+        }
+    }
+}
@@ -0,0 +1,575 @@
+use std::{cmp, cmp::Reverse, ops::Range};
+
+use crate::{editor::EditorRows, options::Options};
+
+type Span = ((usize, usize), (usize, usize));
+
+struct Tag {
+    closing: bool,
+    name: String,
+    start: (usize, usize),
+    end: (usize, usize),
+}
+
+fn key((x, y): (usize, usize)) -> (usize, usize) {
+    (y, x)
+}
+
+impl EditorRows {
+    pub fn next_pos(&self, (x, y): (usize, usize)) -> Option<((usize, usize), bool)> {
+        let raw = self.get_raw(y);
+        if x < raw.len() {
+            let x = x + raw[x..].chars().next().map_or(1, |c| c.len_utf8());
+            return Some(((x, y), x == raw.len()));
+        }
+        (y + 1 < self.num_rows()).then_some(((0, y + 1), true))
+    }
+
+    pub fn prev_pos(&self, (x, y): (usize, usize)) -> Option<(usize, usize)> {
+        if x > 0 {
+            let raw = self.get_raw(y);
+            let x = raw[..x].char_indices().last().map_or(0, |(i, _)| i);
+            return Some((x, y));
+        }
+        (y > 0).then(|| (self.get_raw(y - 1).len(), y - 1))
+    }
+
+    fn word_class(&self, (x, y): (usize, usize), big: bool, opts: &Options) -> u8 {
+        match self.get_raw(y)[x..].chars().next() {
+            None => 0,
+            Some(c) if c.is_whitespace() => 0,
+            Some(_) if big => 1,
+            Some(c) if opts.is_keyword(c) => 2,
+            Some(_) => 1,
+        }
+    }
+
+    pub fn word_forward(
+        &self,
+        pos: (usize, usize),
+        big: bool,
+        opts: &Options,
+        stop_at_eol: bool,
+    ) -> Option<(usize, usize)> {
+        let class = |p| self.word_class(p, big, opts);
+        let start = class(pos);
+        let last_line = pos.1 + 1 == self.num_rows();
+        let (mut p, boundary) = self.next_pos(pos)?;
+        if boundary && last_line {
+            return stop_at_eol.then_some(p);
+        }
+        if boundary && stop_at_eol {
+            return Some(p);
+        }
+
+        if start != 0 {
+            while class(p) == start {
+                let Some((next, boundary)) = self.next_pos(p) else {
+                    return Some(p);
+                };
+                p = next;
+                if boundary && stop_at_eol {
+                    return Some(p);
+                }
+            }
+        }
+        while class(p) == 0 && !self.get_raw(p.1).is_empty() {
+            let Some((next, boundary)) = self.next_pos(p) else {
+                return Some(p);
+            };
+            p = next;
+            if boundary && stop_at_eol {
+                return Some(p);
+            }
+        }
+        Some(p)
+    }
+
+    pub fn word_end(
+        &self,
+        pos: (usize, usize),
+        big: bool,
+        opts: &Options,
+        stop: bool,
+    ) -> Option<(usize, usize)> {
+        let class = |p| self.word_class(p, big, opts);
+        let skip = |mut p, c| {
+            while class(p) == c {
+                p = self.next_pos(p)?.0;
+            }
+            Some(p)
+        };
+        let start = class(pos);
+        let mut p = self.next_pos(pos)?.0;
+        if class(p) == start && start != 0 {
+            p = skip(p, start)?;
+        } else if !stop || start == 0 {
+            p = skip(p, 0)?;
+            p = skip(p, class(p))?;
+        }
+        self.prev_pos(p)
+    }
+
+    pub fn word_backward(
+        &self,
+        pos: (usize, usize),
+        big: bool,
+        opts: &Options,
+    ) -> Option<(usize, usize)> {
+        let class = |p| self.word_class(p, big, opts);
+        let mut p = self.prev_pos(pos)?;
+        while class(p) == 0 {
+            if self.get_raw(p.1).is_empty() {
+                return Some(p);
+            }
+            match self.prev_pos(p) {
+                Some(prev) => p = prev,
+                None => return Some(p),
+            }
+        }
+        let c = class(p);
+        while class(p) == c {
+            match self.prev_pos(p) {
+                Some(prev) => p = prev,
+                None => return Some(p),
+            }
+        }
+        self.next_pos(p).map(|(p, _)| p)
+    }
+
+    pub fn word_end_backward(
+        &self,
+        pos: (usize, usize),
+        big: bool,
+        opts: &Options,
+    ) -> Option<(usize, usize)> {
+        let class = |p| self.word_class(p, big, opts);
+        let start = class(pos);
+        let mut p = self.prev_pos(pos)?;
+        if start != 0 {
+            while class(p) == start {
+                match self.prev_pos(p) {
+                    Some(prev) => p = prev,
+                    None => return Some(p),
+                }
+            }
+        }
+        while class(p) == 0 && !self.get_raw(p.1).is_empty() {
+            match self.prev_pos(p) {
+                Some(prev) => p = prev,
+                None => return Some(p),
+            }
+        }
+        Some(p)
+    }
+
+    pub fn match_pair(
+        &self,
+        (x, y): (usize, usize),
+        lines: Range<usize>,
+    ) -> Option<(usize, usize)> {
+        let c = *self.get_erows().get(y)?.raw.as_bytes().get(x)?;
+        let (open, close, forward) = match c {
+            b'(' => (b'(', b')', true),
+            b'[' => (b'[', b']', true),
+            b'{' => (b'{', b'}', true),
+            b')' => (b')', b'(', false),
+            b']' => (b']', b'[', false),
+            b'}' => (b'}', b'{', false),
+            _ => return None,
+        };
+        let mut depth = 0;
+        let mut scan = |line: usize, i: usize| {
+            let b = self.get_raw(line).as_bytes()[i];
+            if b == open {
+                depth += 1;
+            } else if b == close {
+                depth -= 1;
+            }
+            depth == 0
+        };
+        if forward {
+            for line in y..cmp::min(lines.end, self.num_rows()) {
+                let start = if line == y { x } else { 0 };
+                if let Some(i) = (start..self.get_raw(line).len()).find(|i| scan(line, *i)) {
+                    return Some((i, line));
+                }
+            }
+        } else {
+            for line in (lines.start..=y).rev() {
+                let end = if line == y {
+                    x + 1
+                } else {
+                    self.get_raw(line).len()
+                };
+                if let Some(i) = (0..end).rev().find(|i| scan(line, *i)) {
+                    return Some((i, line));
+                }
+            }
+        }
+        None
+    }
+
+    pub fn paragraph(&self, y: usize, forward: bool) -> (usize, usize) {
+        let empty = |y: usize| self.get_raw(y).is_empty();
+        let last = self.num_rows() - 1;
+        if forward {
+            if y == last {
+                return (self.get_raw(y).len(), y);
+            }
+            let mut y = y + 1;
+            while y < last && empty(y) {
+                y += 1;
+            }
+            while y < last && !empty(y) {
+                y += 1;
+            }
+            if empty(y) {
+                (0, y)
+            } else {
+                (self.get_raw(y).len(), y)
+            }
+        } else {
+            if y == 0 {
+                return (0, 0);
+            }
+            let mut y = y - 1;
+            while y > 0 && empty(y) {
+                y -= 1;
+            }
+            while y > 0 && !empty(y) {
+                y -= 1;
+            }
+            (0, y)
+        }
+    }
+
+    fn is_sentence_start(&self, x: usize, y: usize) -> bool {
+        let raw = self.get_raw(y);
+        if raw.is_empty() {
+            return y == 0 || !self.get_raw(y - 1).is_empty();
+        }
+        if raw[x..].starts_with(char::is_whitespace) {
+            return false;
+        }
+        let mut before = raw[..x].trim_end();
+        let mut gap = before.len() < x;
+        let mut y = y;
+        while before.is_empty() {
+            if y == 0 || self.get_raw(y - 1).is_empty() {
+                return true;
+            }
+            y -= 1;
+            before = self.get_raw(y).trim_end();
+            gap = true;
+        }
+        gap && before
+            .trim_end_matches([')', ']', '"', '\''])
+            .ends_with(['.', '!', '?'])
+    }
+
+    pub fn sentence(&self, (x, y): (usize, usize), forward: bool) -> (usize, usize) {
+        let positions = |y: usize| -> Vec<usize> {
+            let raw = self.get_raw(y);
+            if raw.is_empty() {
+                vec![0]
+            } else {
+                raw.char_indices().map(|(i, _)| i).collect()
+            }
+        };
+        if forward {
+            for line in y..self.num_rows() {
+                let found = positions(line)
+                    .into_iter()
+                    .filter(|i| line > y || *i > x)
+                    .find(|i| self.is_sentence_start(*i, line));
+                if let Some(i) = found {
+                    return (i, line);
+                }
+            }
+            let last = self.num_rows() - 1;
+            (self.get_raw(last).len(), last)
+        } else {
+            for line in (0..=y).rev() {
+                let found = positions(line)
+                    .into_iter()
+                    .rev()
+                    .filter(|i| line < y || *i < x)
+                    .find(|i| self.is_sentence_start(*i, line));
+                if let Some(i) = found {
+                    return (i, line);
+                }
+            }
+            (0, 0)
+        }
+    }
+
+    pub fn text_object(
+        &self,
+        pos: (usize, usize),
+        c: char,
+        around: bool,
+        count: usize,
+        opts: &Options,
+    ) -> Option<(Span, bool)> {
+        let span = match c {
+            'w' | 'W' => self.word_object(pos, c == 'W', around, count, opts),
+            's' => self.sentence_object(pos, around, count),
+            'p' => {
+                return self
+                    .paragraph_object(pos.1, around, count)
+                    .map(|s| (s, true))
+            }
+            '"' | '\'' | '`' => self.quote_object(pos, c, around),
+            '(' | ')' | 'b' => self.block_object(pos, b'(', b')', around, count),
+            '[' | ']' => self.block_object(pos, b'[', b']', around, count),
+            '{' | '}' | 'B' => self.block_object(pos, b'{', b'}', around, count),
+            '<' | '>' => self.block_object(pos, b'<', b'>', around, count),
+            't' => self.tag_object(pos, around, count),
+            _ => None,
+        };
+        span.map(|s| (s, false))
+    }
+
+    fn word_object(
+        &self,
+        (x, y): (usize, usize),
+        big: bool,
+        around: bool,
+        count: usize,
+        opts: &Options,
+    ) -> Option<Span> {
+        let mut runs: Vec<(usize, usize, u8)> = Vec::new();
+        for (i, c) in self.get_raw(y).char_indices() {
+            let class = self.word_class((i, y), big, opts);
+            match runs.last_mut() {
+                Some(run) if run.2 == class => run.1 = i + c.len_utf8(),
+                _ => runs.push((i, i + c.len_utf8(), class)),
+            }
+        }
+        let last = runs.len().checked_sub(1)?;
+        let k = runs.iter().position(|r| x < r.1).unwrap_or(last);
+        let (first, end) = if !around {
+            (k, cmp::min(k + count - 1, last))
+        } else if runs[k].2 == 0 {
+            (k, cmp::min(k + 2 * count - 1, last))
+        } else {
+            let mut j = k;
+            let mut trailing = false;
+            for i in 0..count {
+                if i > 0 {
+                    if j == last {
+                        break;
+                    }
+                    j += 1;
+                }
+                trailing = j < last && runs[j + 1].2 == 0;
+                if trailing {
+                    j += 1;
+                }
+            }
+            match k.checked_sub(1) {
+                Some(prev) if !trailing && runs[prev].2 == 0 => (prev, j),
+                _ => (k, j),
+            }
+        };
+        Some(((runs[first].0, y), (runs[end].1, y)))
+    }
+
+    fn sentence_object(&self, pos: (usize, usize), around: bool, count: usize) -> Option<Span> {
+        let start = if self.is_sentence_start(pos.0, pos.1) {
+            pos
+        } else {
+            self.sentence(pos, false)
+        };
+        let end = (0..count).fold(start, |p, _| self.sentence(p, true));
+        let blank = |(x, y): (usize, usize)| {
+            self.get_raw(y)[x..]
+                .chars()
+                .next()
+                .is_none_or(char::is_whitespace)
+        };
+        let mut trimmed = end;
+        while let Some(prev) = self.prev_pos(trimmed) {
+            if key(prev) < key(start) || !blank(prev) {
+                break;
+            }
+            trimmed = prev;
+        }
+        if !around {
+            return Some((start, trimmed));
+        }
+        if trimmed.1 == end.1 && trimmed != end {
+            return Some((start, end));
+        }
+        let lead = self.get_raw(start.1)[..start.0].trim_end().len();
+        Some(((lead, start.1), trimmed))
+    }
+
+    fn paragraph_object(&self, y: usize, around: bool, count: usize) -> Option<Span> {
+        let blank = |y: usize| self.get_raw(y).trim().is_empty();
+        let last = self.num_rows() - 1;
+        let run_end = |y: usize| {
+            let mut end = y;
+            while end < last && blank(end + 1) == blank(y) {
+                end += 1;
+            }
+            end
+        };
+        let mut start = y;
+        while start > 0 && blank(start - 1) == blank(y) {
+            start -= 1;
+        }
+        let runs = if around { 2 * count } else { count };
+        let mut end = run_end(y);
+        for _ in 1..runs {
+            if end == last {
+                break;
+            }
+            end = run_end(end + 1);
+        }
+        if around && !blank(y) && !blank(end) {
+            while start > 0 && blank(start - 1) {
+                start -= 1;
+            }
+        }
+        Some(((0, start), (0, end)))
+    }
+
+    fn quote_object(&self, (x, y): (usize, usize), q: char, around: bool) -> Option<Span> {
+        let raw = self.get_raw(y);
+        let mut quotes = Vec::new();
+        let mut escaped = false;
+        for (i, c) in raw.char_indices() {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quotes.push(i);
+            }
+        }
+        let pair = quotes.chunks_exact(2).find(|pair| x <= pair[1])?;
+        let (open, close) = (pair[0], pair[1] + 1);
+        if !around {
+            return Some(((open + 1, y), (close - 1, y)));
+        }
+        let after = raw[close..].len() - raw[close..].trim_start().len();
+        if after > 0 {
+            Some(((open, y), (close + after, y)))
+        } else {
+            Some(((raw[..open].trim_end().len(), y), (close, y)))
+        }
+    }
+
+    fn block_object(
+        &self,
+        pos: (usize, usize),
+        open: u8,
+        close: u8,
+        around: bool,
+        count: usize,
+    ) -> Option<Span> {
+        let byte = |(x, y): (usize, usize)| self.get_raw(y).as_bytes().get(x).copied();
+        let mut from = if byte(pos) == Some(close) {
+            self.prev_pos(pos)
+        } else {
+            Some(pos)
+        };
+        let mut o = pos;
+        for _ in 0..count {
+            let mut depth = 0;
+            let mut p = from?;
+            loop {
+                match byte(p) {
+                    Some(b) if b == open && depth == 0 => break,
+                    Some(b) if b == open => depth -= 1,
+                    Some(b) if b == close => depth += 1,
+                    _ => {}
+                }
+                p = self.prev_pos(p)?;
+            }
+            o = p;
+            from = self.prev_pos(p);
+        }
+
+        let mut depth = 0;
+        let mut c = o;
+        loop {
+            c = self.next_pos(c)?.0;
+            match byte(c) {
+                Some(b) if b == close && depth == 0 => break,
+                Some(b) if b == close => depth -= 1,
+                Some(b) if b == open => depth += 1,
+                _ => {}
+            }
+        }
+
+        if around {
+            return Some((o, (c.0 + 1, c.1)));
+        }
+        let mut s = (o.0 + 1, o.1);
+        if s.0 == self.get_raw(s.1).len() && s.1 + 1 < c.1 {
+            s = (0, s.1 + 1);
+        }
+        let e = if c.1 > s.1 && self.get_raw(c.1)[..c.0].trim().is_empty() {
+            (0, c.1)
+        } else {
+            c
+        };
+        Some((s, e))
+    }
+
+    fn tag_object(&self, pos: (usize, usize), around: bool, count: usize) -> Option<Span> {
+        let mut tags = Vec::new();
+        for y in 0..self.num_rows() {
+            let raw = self.get_raw(y);
+            let mut i = 0;
+            while let Some(lt) = raw[i..].find('<') {
+                let start = i + lt;
+                let Some(gt) = raw[start..].find('>') else {
+                    break;
+                };
+                let end = start + gt + 1;
+                let inner = &raw[start + 1..end - 1];
+                let name: String = inner
+                    .trim_start_matches('/')
+                    .chars()
+                    .take_while(|c| c.is_alphanumeric() || "-_:.".contains(*c))
+                    .collect();
+                if name.is_empty() {
+                    i = start + 1;
+                    continue;
+                }
+                if !inner.ends_with('/') {
+                    tags.push(Tag {
+                        closing: inner.starts_with('/'),
+                        name,
+                        start: (start, y),
+                        end: (end, y),
+                    });
+                }
+                i = end;
+            }
+        }
+
+        let mut stack: Vec<usize> = Vec::new();
+        let mut pairs = Vec::new();
+        for (i, tag) in tags.iter().enumerate() {
+            if !tag.closing {
+                stack.push(i);
+            } else if let Some(k) = stack.iter().rposition(|j| tags[*j].name == tag.name) {
+                pairs.push((&tags[stack[k]], tag));
+                stack.truncate(k);
+            }
+        }
+        pairs.retain(|(o, c)| key(o.start) <= key(pos) && key(pos) < key(c.end));
+        pairs.sort_by_key(|(o, _)| Reverse(key(o.start)));
+        let (o, c) = pairs.get(count - 1)?;
+        if around {
+            Some((o.start, c.end))
+        } else {
+            Some((o.end, c.start))
+        }
+    }
+}
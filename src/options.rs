@@ -0,0 +1,410 @@
+use std::path::Path;
+
+use crate::{encoding::Encoding, TAB_SZ};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Bool,
+    Number,
+    String,
+    List,
+}
+
+const DEFAULT_ISKEYWORD: &str = "@,48-57,_,192-255";
+
+const FILETYPES: &[(&str, &[&str])] = &[
+    ("rust", &["rs"]),
+    ("c", &["c", "h"]),
+    ("cpp", &["cpp", "cc", "cxx", "hpp", "hh"]),
+    ("python", &["py"]),
+    ("javascript", &["js", "mjs", "cjs"]),
+    ("typescript", &["ts"]),
+    ("go", &["go"]),
+    ("java", &["java"]),
+    ("ruby", &["rb"]),
+    ("lua", &["lua"]),
+    ("sh", &["sh", "bash", "zsh"]),
+    ("perl", &["pl", "pm"]),
+    ("php", &["php"]),
+    ("html", &["html", "htm"]),
+    ("xml", &["xml", "svg"]),
+    ("css", &["css", "scss", "less"]),
+    ("lisp", &["lisp", "lsp", "el", "cl"]),
+    ("scheme", &["scm", "ss", "rkt"]),
+    ("clojure", &["clj", "cljs", "edn"]),
+    ("markdown", &["md", "markdown"]),
+    ("toml", &["toml"]),
+    ("json", &["json"]),
+    ("yaml", &["yaml", "yml"]),
+    ("rhai", &["rhai"]),
+];
+
+const FILETYPE_ISKEYWORD: &[(&str, &str)] = &[
+    ("html", "@,48-57,_,192-255,-"),
+    ("xml", "@,48-57,_,192-255,-,:"),
+    ("css", "@,48-57,_,192-255,-"),
+    ("sh", "@,48-57,_,192-255,$"),
+    ("perl", "@,48-57,_,192-255,$"),
+    ("php", "@,48-57,_,192-255,$"),
+    ("lisp", "@,48-57,_,192-255,-,*,!,?,+,/,<,=,>,:"),
+    ("scheme", "@,48-57,_,192-255,-,*,!,?,+,/,<,=,>,:"),
+    ("clojure", "@,48-57,_,192-255,-,*,!,?,+,/,<,=,>,:,'"),
+];
+
+const OPTIONS: &[(&str, &str, Kind)] = &[
+    ("number", "nu", Kind::Bool),
+    ("relativenumber", "rnu", Kind::Bool),
+    ("numberwidth", "nuw", Kind::Number),
+    ("signcolumn", "scl", Kind::String),
+    ("wrap", "wrap", Kind::Bool),
+    ("linebreak", "lbr", Kind::Bool),
+    ("showbreak", "sbr", Kind::String),
+    ("scrolloff", "so", Kind::Number),
+    ("iskeyword", "isk", Kind::List),
+    ("filetype", "ft", Kind::String),
+    ("autoindent", "ai", Kind::Bool),
+    ("smartindent", "si", Kind::Bool),
+    ("expandtab", "et", Kind::Bool),
+    ("shiftwidth", "sw", Kind::Number),
+    ("softtabstop", "sts", Kind::Number),
+    ("autoread", "ar", Kind::Bool),
+    ("readonly", "ro", Kind::Bool),
+    ("modifiable", "ma", Kind::Bool),
+    ("fileencoding", "fenc", Kind::String),
+    ("largefile", "lf", Kind::Number),
+];
+
+pub struct Options {
+    pub number: bool,
+    pub relativenumber: bool,
+    pub numberwidth: usize,
+    pub signcolumn: String,
+    pub wrap: bool,
+    pub linebreak: bool,
+    pub showbreak: String,
+    pub scrolloff: usize,
+    pub iskeyword: String,
+    pub filetype: String,
+    pub autoindent: bool,
+    pub smartindent: bool,
+    pub expandtab: bool,
+    pub shiftwidth: usize,
+    pub softtabstop: usize,
+    pub autoread: bool,
+    pub readonly: bool,
+    pub modifiable: bool,
+    pub fileencoding: String,
+    pub largefile: usize,
+    keywords: [bool; 256],
+}
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            number: false,
+            relativenumber: false,
+            numberwidth: 4,
+            signcolumn: "auto".into(),
+            wrap: false,
+            linebreak: false,
+            showbreak: String::new(),
+            scrolloff: 0,
+            iskeyword: DEFAULT_ISKEYWORD.into(),
+            filetype: String::new(),
+            autoindent: false,
+            smartindent: false,
+            expandtab: false,
+            shiftwidth: 8,
+            softtabstop: 0,
+            autoread: false,
+            readonly: false,
+            modifiable: true,
+            fileencoding: Encoding::Utf8.name().into(),
+            largefile: 100,
+            keywords: parse_iskeyword(DEFAULT_ISKEYWORD).unwrap_or([false; 256]),
+        }
+    }
+}
+impl Options {
+    fn lookup(name: &str) -> Option<(&'static str, Kind)> {
+        OPTIONS
+            .iter()
+            .find(|(long, short, _)| *long == name || *short == name)
+            .map(|(long, _, kind)| (*long, *kind))
+    }
+
+    fn get_bool(&self, name: &str) -> bool {
+        match name {
+            "number" => self.number,
+            "relativenumber" => self.relativenumber,
+            "wrap" => self.wrap,
+            "linebreak" => self.linebreak,
+            "autoindent" => self.autoindent,
+            "smartindent" => self.smartindent,
+            "expandtab" => self.expandtab,
+            "autoread" => self.autoread,
+            "readonly" => self.readonly,
+            "modifiable" => self.modifiable,
+            _ => false,
+        }
+    }
+
+    fn set_bool(&mut self, name: &str, value: bool) {
+        match name {
+            "number" => self.number = value,
+            "relativenumber" => self.relativenumber = value,
+            "wrap" => self.wrap = value,
+            "linebreak" => self.linebreak = value,
+            "autoindent" => self.autoindent = value,
+            "smartindent" => self.smartindent = value,
+            "expandtab" => self.expandtab = value,
+            "autoread" => self.autoread = value,
+            "readonly" => self.readonly = value,
+            "modifiable" => self.modifiable = value,
+            _ => {}
+        }
+    }
+
+    fn get_value(&self, name: &str) -> String {
+        match name {
+            "numberwidth" => self.numberwidth.to_string(),
+            "signcolumn" => self.signcolumn.clone(),
+            "showbreak" => self.showbreak.clone(),
+            "scrolloff" => self.scrolloff.to_string(),
+            "largefile" => self.largefile.to_string(),
+            "iskeyword" => self.iskeyword.clone(),
+            "filetype" => self.filetype.clone(),
+            "fileencoding" => self.fileencoding.clone(),
+            "shiftwidth" => self.shiftwidth.to_string(),
+            "softtabstop" => self.softtabstop.to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn set_value(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let number = || {
+            value
+                .parse::<usize>()
+                .map_err(|_| format!("Number required after =: {}={}", name, value))
+        };
+        match name {
+            "numberwidth" => self.numberwidth = number()?.clamp(1, 20),
+            "signcolumn" => match value {
+                "auto" | "yes" | "no" => self.signcolumn = value.into(),
+                _ => return Err(format!("Invalid argument: signcolumn={}", value)),
+            },
+            "showbreak" => self.showbreak = value.into(),
+            "fileencoding" => {
+                let enc = Encoding::from_name(value)
+                    .ok_or_else(|| format!("Invalid argument: fileencoding={}", value))?;
+                self.fileencoding = enc.name().into();
+            }
+            "scrolloff" => self.scrolloff = number()?,
+            "largefile" => self.largefile = number()?,
+            "shiftwidth" => self.shiftwidth = number()?,
+            "softtabstop" => self.softtabstop = number()?,
+            "iskeyword" => {
+                self.keywords = parse_iskeyword(value)
+                    .ok_or_else(|| format!("Invalid argument: iskeyword={}", value))?;
+                self.iskeyword = value.into();
+            }
+            "filetype" => {
+                let isk = FILETYPE_ISKEYWORD
+                    .iter()
+                    .find(|(ft, _)| *ft == value)
+                    .map_or(DEFAULT_ISKEYWORD, |(_, isk)| isk);
+                self.set_value("iskeyword", isk)?;
+                self.filetype = value.into();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn combine(&self, name: &str, kind: Kind, op: char, value: &str) -> Result<String, String> {
+        let current = self.get_value(name);
+        Ok(match (kind, op) {
+            (Kind::Number, _) => {
+                let (a, b) = (current.parse::<usize>(), value.parse::<usize>());
+                let (Ok(a), Ok(b)) = (a, b) else {
+                    return Err(format!("Number required after =: {}={}", name, value));
+                };
+                match op {
+                    '+' => a.saturating_add(b),
+                    '-' => a.saturating_sub(b),
+                    _ => a.saturating_mul(b),
+                }
+                .to_string()
+            }
+            (Kind::List, '-') => current
+                .split(',')
+                .filter(|item| *item != value)
+                .collect::<Vec<_>>()
+                .join(","),
+            (Kind::List, _) if current.is_empty() => value.into(),
+            (Kind::List, '+') => format!("{},{}", current, value),
+            (Kind::List, _) => format!("{},{}", value, current),
+            (_, '-') => current.replacen(value, "", 1),
+            (_, '+') => format!("{}{}", current, value),
+            _ => format!("{}{}", value, current),
+        })
+    }
+
+    pub fn complete(&self, arg: &str) -> Vec<String> {
+        if let Some((name, value)) = arg.split_once('=') {
+            let Some((long, kind)) = Self::lookup(name) else {
+                return Vec::new();
+            };
+            let values: Vec<String> = match long {
+                "filetype" => FILETYPES.iter().map(|(ft, _)| ft.to_string()).collect(),
+                "signcolumn" => vec!["auto".into(), "yes".into(), "no".into()],
+                "fileencoding" => Encoding::NAMES.iter().map(|e| e.to_string()).collect(),
+                _ if kind == Kind::Bool => Vec::new(),
+                _ => vec![self.get_value(long)],
+            };
+            return values
+                .into_iter()
+                .filter(|v| v.starts_with(value))
+                .map(|v| format!("{}={}", name, v))
+                .collect();
+        }
+        let (no, prefix) = match arg.strip_prefix("no") {
+            Some(rest) => ("no", rest),
+            None => ("", arg),
+        };
+        let mut names: Vec<String> = OPTIONS
+            .iter()
+            .filter(|(long, _, kind)| {
+                long.starts_with(prefix) && (no.is_empty() || *kind == Kind::Bool)
+            })
+            .map(|(long, _, _)| format!("{}{}", no, long))
+            .collect();
+        if no.is_empty() {
+            names.extend(
+                OPTIONS
+                    .iter()
+                    .filter(|(long, _, kind)| {
+                        *kind == Kind::Bool && format!("no{}", long).starts_with(arg)
+                    })
+                    .map(|(long, _, _)| format!("no{}", long)),
+            );
+        }
+        names.sort();
+        names
+    }
+
+    pub fn shiftwidth(&self) -> usize {
+        match self.shiftwidth {
+            0 => TAB_SZ,
+            sw => sw,
+        }
+    }
+
+    pub fn is_keyword(&self, c: char) -> bool {
+        match self.keywords.get(c as usize) {
+            Some(keyword) => *keyword,
+            None => c.is_alphanumeric(),
+        }
+    }
+
+    pub fn detect_filetype(&mut self, path: Option<&Path>) {
+        let ext = path
+            .and_then(|p| p.extension())
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        let ft = FILETYPES
+            .iter()
+            .find(|(_, exts)| exts.contains(&ext))
+            .map_or("", |(ft, _)| ft);
+        let _ = self.set_value("filetype", ft);
+    }
+
+    pub fn set(&mut self, arg: &str) -> Result<Option<String>, String> {
+        let unknown = |name: &str| format!("Unknown option: {}", name);
+
+        if let Some((name, value)) = arg.split_once('=') {
+            let (name, op) = match name.char_indices().last() {
+                Some((i, op @ ('+' | '-' | '^'))) => (&name[..i], Some(op)),
+                _ => (name, None),
+            };
+            let (name, kind) = Self::lookup(name).ok_or_else(|| unknown(name))?;
+            if kind == Kind::Bool {
+                return Err(format!("Invalid argument: {}", arg));
+            }
+            let value = match op {
+                Some(op) => self.combine(name, kind, op, value)?,
+                None => value.to_string(),
+            };
+            self.set_value(name, &value)?;
+            return Ok(None);
+        }
+
+        if let Some(name) = arg.strip_suffix('?') {
+            let (name, kind) = Self::lookup(name).ok_or_else(|| unknown(name))?;
+            return Ok(Some(match kind {
+                Kind::Bool if self.get_bool(name) => format!("  {}", name),
+                Kind::Bool => format!("no{}", name),
+                _ => format!("  {}={}", name, self.get_value(name)),
+            }));
+        }
+
+        let (name, value) = if let Some(name) = arg.strip_suffix('!') {
+            (name, None)
+        } else if let Some(name) = arg.strip_prefix("inv") {
+            (name, None)
+        } else if let Some(name) = arg
+            .strip_prefix("no")
+            .filter(|n| Self::lookup(n).is_some_and(|(_, k)| k == Kind::Bool))
+        {
+            (name, Some(false))
+        } else {
+            (arg, Some(true))
+        };
+
+        let (name, kind) = Self::lookup(name).ok_or_else(|| unknown(name))?;
+        match kind {
+            Kind::Bool => {
+                let value = value.unwrap_or(!self.get_bool(name));
+                self.set_bool(name, value);
+                Ok(None)
+            }
+            _ if value == Some(true) => Ok(Some(format!("  {}={}", name, self.get_value(name)))),
+            _ => Err(format!("Invalid argument: {}", arg)),
+        }
+    }
+}
+
+fn parse_iskeyword(value: &str) -> Option<[bool; 256]> {
+    let code = |s: &str| -> Option<usize> {
+        if s.bytes().all(|b| b.is_ascii_digit()) {
+            s.parse().ok().filter(|c| *c < 256)
+        } else {
+            let mut chars = s.chars();
+            let c = chars.next()? as usize;
+            (chars.next().is_none() && c < 256).then_some(c)
+        }
+    };
+
+    let mut table = [false; 256];
+    for part in value.split(',') {
+        let (part, on) = match part.strip_prefix('^') {
+            Some(rest) if !rest.is_empty() => (rest, false),
+            _ => (part, true),
+        };
+        if part == "@" {
+            for (c, keyword) in table.iter_mut().enumerate() {
+                if char::from(c as u8).is_alphabetic() {
+                    *keyword = on;
+                }
+            }
+            continue;
+        }
+        let (lo, hi) = match part.split_once('-') {
+            Some((lo, hi)) if !lo.is_empty() && !hi.is_empty() => (code(lo)?, code(hi)?),
+            _ => (code(part)?, code(part)?),
+        };
+        for keyword in table.iter_mut().take(hi + 1).skip(lo) {
+            *keyword = on;
+        }
+    }
+    Some(table)
+}
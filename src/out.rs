@@ -11,9 +11,16 @@ use std::{
 
 use crate::{
     editor::{EditorRows, Mode},
+    encoding::char_width,
+    hex::{self, HexRows, Pane, BYTES_PER_LINE},
+    large::LargeRows,
+    options::Options,
     TAB_SZ,
 };
 
+const BREAKAT: &str = " \t!@*-+;:,./?";
+const POPUP_HEIGHT: usize = 10;
+
 #[derive(Debug)]
 pub enum Direction {
     Up,
@@ -22,27 +29,94 @@ pub enum Direction {
     Right,
 }
 
+#[derive(Clone, Copy)]
+pub enum ScreenPos {
+    Top,
+    Middle,
+    Bottom,
+}
+
+pub struct Popup {
+    pub items: Vec<String>,
+    pub selected: Option<usize>,
+    pub col: usize,
+}
+
+#[derive(Default)]
+struct Gutter {
+    signs: usize,
+    numbers: usize,
+}
+impl Gutter {
+    fn new(rows: &EditorRows, opts: &Options) -> Self {
+        let signs = match opts.signcolumn.as_str() {
+            "yes" => 2,
+            "auto" if rows.has_signs() => 2,
+            _ => 0,
+        };
+        let numbers = if opts.number || opts.relativenumber {
+            let digits = rows.num_rows().to_string().len();
+            cmp::max(opts.numberwidth.saturating_sub(1), digits) + 1
+        } else {
+            0
+        };
+        Self { signs, numbers }
+    }
+
+    fn width(&self) -> usize {
+        self.signs + self.numbers
+    }
+}
+
 pub struct Output {
     size: (usize, usize),
+    gutter: Gutter,
     c_ctrl: CursorController,
     out: BufWriter<Stdout>,
     stt_msg: Option<StatusMessage>,
     cmd_msg: Option<StatusMessage>,
     pub cmd: Option<String>,
+    pub visual: Option<((usize, usize), bool)>,
+    pub recording: Option<char>,
+    pub popup: Option<Popup>,
     pub dirty: u64,
+    tick: u64,
 }
 impl Output {
     pub fn new() -> io::Result<Self> {
         let size = terminal::size().map(|(x, y)| (x as usize, y as usize - 2))?;
-        Ok(Self {
+        Ok(Self::with_size(size))
+    }
+
+    pub fn with_size(size: (usize, usize)) -> Self {
+        Self {
             size,
+            gutter: Gutter::default(),
             c_ctrl: CursorController::new(size),
             out: BufWriter::new(io::stdout()),
             stt_msg: None,
             cmd_msg: None,
             cmd: None,
+            visual: None,
+            recording: None,
+            popup: None,
             dirty: 0,
-        })
+            tick: 0,
+        }
+    }
+
+    pub fn resize(&mut self, w: u16, h: u16) {
+        self.size = (w as usize, (h as usize).saturating_sub(2));
+        self.c_ctrl.screen_size = self.size;
+    }
+
+    pub fn touch(&mut self) {
+        self.dirty += 1;
+        self.tick += 1;
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.tick
     }
 
     pub fn clear_screen(&self) -> io::Result<()> {
@@ -54,20 +128,56 @@ impl Output {
         )
     }
 
-    pub fn render_screen(&mut self, rows: &EditorRows, mode: &Mode) -> io::Result<()> {
+    pub fn render_screen(
+        &mut self,
+        rows: &EditorRows,
+        hex: Option<&mut HexRows>,
+        large: Option<&mut LargeRows>,
+        mode: &Mode,
+        opts: &Options,
+    ) -> io::Result<()> {
         queue!(self.out, cursor::Hide, cursor::MoveTo(0, 0))?;
 
-        self.c_ctrl.scroll(rows);
-        let c_x = (self.c_ctrl.rx - self.c_ctrl.x_offset) as u16;
-        let c_y = (self.c_ctrl.cy - self.c_ctrl.y_offset) as u16;
+        let (c_x, c_y) = match (hex, large) {
+            (Some(hex), _) => {
+                hex.scroll(self.size.1);
+                self.render_hex(hex)?;
+                self.render_bar(rows, Some(&*hex), None, opts)?;
+                let (x, y) = hex.screen_pos();
+                (x as u16, y as u16)
+            }
+            (None, Some(large)) => {
+                let numbers = match opts.number {
+                    true => cmp::max(opts.numberwidth, large.num_rows().to_string().len() + 1),
+                    false => 0,
+                };
+                large.scroll(self.size.0.saturating_sub(numbers), self.size.1);
+                self.render_large(large, numbers)?;
+                self.render_bar(rows, None, Some(&*large), opts)?;
+                let (x, y) = large.screen_pos();
+                ((numbers + x) as u16, y as u16)
+            }
+            (None, None) => {
+                self.gutter = Gutter::new(rows, opts);
+                let width = self.text_width();
+                self.c_ctrl.scroll(rows, width, opts);
+                let (c_x, c_y) = self.c_ctrl.screen_pos(rows, width, opts);
 
-        self.render_lines(rows)?;
-        self.render_bar(rows)?;
+                let visible = self.c_ctrl.y_offset..self.c_ctrl.y_offset + self.size.1;
+                let matched = rows
+                    .match_pair((self.c_ctrl.cx, self.c_ctrl.cy), visible)
+                    .map(|(x, y)| (rx_for(rows.get_raw(y), x), y));
+                self.render_lines(rows, opts, matched)?;
+                self.render_bar(rows, None, None, opts)?;
+                ((self.gutter.width() + c_x) as u16, c_y as u16)
+            }
+        };
 
         match mode {
             Mode::Command => self.render_command()?,
             _ => {
                 self.render_message()?;
+                self.render_popup(rows, (c_x, c_y))?;
                 queue!(self.out, cursor::Show, cursor::MoveTo(c_x, c_y))?;
             }
         };
@@ -75,98 +185,440 @@ impl Output {
         Ok(())
     }
 
-    fn render_lines(&mut self, rows: &EditorRows) -> io::Result<()> {
+    fn text_width(&self) -> usize {
+        self.size.0.saturating_sub(self.gutter.width())
+    }
+
+    fn segments(&self, rows: &EditorRows, line: usize, opts: &Options) -> Vec<(usize, usize)> {
+        let render = rows.get_render(line);
+        let width = self.text_width();
+        if opts.wrap {
+            wrap_segments(render, width, opts)
+        } else {
+            let len = render.chars().count();
+            let start = cmp::min(self.c_ctrl.x_offset, len);
+            vec![(start, cmp::min(len, start + width))]
+        }
+    }
+
+    fn render_lines(
+        &mut self,
+        rows: &EditorRows,
+        opts: &Options,
+        matched: Option<(usize, usize)>,
+    ) -> io::Result<()> {
+        let mut line = self.c_ctrl.y_offset;
+        let mut segs = Vec::new();
+        let mut seg = 0;
         for i in 0..self.size.1 {
             queue!(self.out, Clear(ClearType::UntilNewLine))?;
-            let i_offset = i + self.c_ctrl.y_offset;
-            if i_offset >= rows.num_rows() {
+            if line >= rows.num_rows() {
                 if rows.num_rows() == 1 && i == self.size.1 / 3 && rows.get_raw(0).is_empty() {
                     let mut msg = "-- Sage Text Editor --".to_string();
                     msg.truncate(self.size.0);
 
                     let mut padding = (self.size.0 - msg.len()) / 2;
                     if padding != 0 {
-                        self.out.write(b"~")?;
+                        self.out.write_all(b"~")?;
                         padding -= 1
                     }
 
                     for _ in 0..padding {
-                        self.out.write(b" ")?;
+                        self.out.write_all(b" ")?;
                     }
-                    self.out.write(msg.as_bytes())?;
+                    self.out.write_all(msg.as_bytes())?;
                 } else {
-                    self.out.write(b"~")?;
+                    self.out.write_all(b"~")?;
                 }
             } else {
-                let row = rows.get_render(i_offset);
-                let len = cmp::min(row.len().saturating_sub(self.c_ctrl.x_offset), self.size.0);
-                let start = if len > 0 { self.c_ctrl.x_offset } else { len };
-                let content = &rows.get_render(i_offset)[start..start + len];
-                self.out.write(content.as_bytes())?;
+                if seg == 0 {
+                    segs = self.segments(rows, line, opts);
+                }
+                self.render_gutter(rows, opts, line, seg == 0)?;
+                if seg > 0 && !opts.showbreak.is_empty() {
+                    queue!(
+                        self.out,
+                        style::SetForegroundColor(style::Color::DarkGrey),
+                        style::Print(&opts.showbreak),
+                        style::ResetColor
+                    )?;
+                }
+                let (start, end) = segs[seg];
+                let content: Vec<char> = rows
+                    .get_render(line)
+                    .chars()
+                    .skip(start)
+                    .take(end - start)
+                    .collect();
+                let selected = self.selected_cols(rows, line);
+                let color_at = |rx: usize| {
+                    if matched == Some((rx, line)) {
+                        Some(style::Color::DarkCyan)
+                    } else if selected.is_some_and(|(a, b)| (a..b).contains(&rx)) {
+                        Some(style::Color::DarkGrey)
+                    } else {
+                        None
+                    }
+                };
+                if content.is_empty() && selected.is_some() {
+                    queue!(
+                        self.out,
+                        style::SetBackgroundColor(style::Color::DarkGrey),
+                        style::Print(' '),
+                        style::ResetColor
+                    )?;
+                }
+                let mut i = 0;
+                while i < content.len() {
+                    let color = color_at(start + i);
+                    let j = (i..content.len())
+                        .find(|j| color_at(start + j) != color)
+                        .unwrap_or(content.len());
+                    let text: String = content[i..j].iter().collect();
+                    match color {
+                        Some(color) => queue!(
+                            self.out,
+                            style::SetBackgroundColor(color),
+                            style::Print(text),
+                            style::ResetColor
+                        )?,
+                        None => self.out.write_all(text.as_bytes())?,
+                    }
+                    i = j;
+                }
+
+                seg += 1;
+                if seg >= segs.len() {
+                    line += 1;
+                    seg = 0;
+                }
             }
-            self.out.write(b"\r\n")?;
+            self.out.write_all(b"\r\n")?;
         }
         Ok(())
     }
 
-    fn render_bar(&mut self, rows: &EditorRows) -> io::Result<()> {
-        let c_x = self.c_ctrl.rx - self.c_ctrl.x_offset;
-        let c_y = self.c_ctrl.cy - self.c_ctrl.y_offset;
+    fn render_hex(&mut self, hex: &HexRows) -> io::Result<()> {
+        for i in 0..self.size.1 {
+            queue!(self.out, Clear(ClearType::UntilNewLine))?;
+            let line = hex.y_offset() + i;
+            if line >= hex.num_lines() {
+                self.out.write_all(b"~\r\n")?;
+                continue;
+            }
+            let start = line * BYTES_PER_LINE;
+            let bytes = hex.line(line);
+            let marked = |j: usize, pane: Pane| start + j == hex.cursor && hex.pane != pane;
+            write!(self.out, "{:08x}: ", start)?;
+            for j in 0..BYTES_PER_LINE {
+                let text = match bytes.get(j) {
+                    Some(b) => format!("{:02x}", b),
+                    None => "  ".into(),
+                };
+                self.print_marked(&text, marked(j, Pane::Hex))?;
+                if j % 2 == 1 {
+                    self.out.write_all(b" ")?;
+                }
+            }
+            self.out.write_all(b" ")?;
+            for (j, b) in bytes.iter().enumerate() {
+                self.print_marked(&hex::printable(*b).to_string(), marked(j, Pane::Ascii))?;
+            }
+            self.out.write_all(b"\r\n")?;
+        }
+        Ok(())
+    }
+
+    fn render_large(&mut self, large: &LargeRows, numbers: usize) -> io::Result<()> {
+        let top = large.y_offset();
+        let lines = large.page(top, self.size.1);
+        let width = self.size.0.saturating_sub(numbers);
+        for i in 0..self.size.1 {
+            queue!(self.out, Clear(ClearType::UntilNewLine))?;
+            match lines.get(i) {
+                Some(line) => {
+                    if numbers > 0 {
+                        queue!(
+                            self.out,
+                            style::SetForegroundColor(style::Color::DarkGrey),
+                            style::Print(format!("{:>w$} ", top + i + 1, w = numbers - 1)),
+                            style::ResetColor
+                        )?;
+                    }
+                    let text: String = line.chars().skip(large.x_offset()).take(width).collect();
+                    self.out.write_all(text.as_bytes())?;
+                }
+                None => self.out.write_all(b"~")?,
+            }
+            self.out.write_all(b"\r\n")?;
+        }
+        Ok(())
+    }
+
+    fn print_marked(&mut self, text: &str, marked: bool) -> io::Result<()> {
+        if marked {
+            queue!(
+                self.out,
+                style::SetBackgroundColor(style::Color::DarkGrey),
+                style::Print(text),
+                style::ResetColor
+            )
+        } else {
+            self.out.write_all(text.as_bytes())
+        }
+    }
+
+    fn render_popup(&mut self, rows: &EditorRows, (c_x, c_y): (u16, u16)) -> io::Result<()> {
+        let Some(popup) = &self.popup else {
+            return Ok(());
+        };
+        let raw = rows.get_raw(self.c_ctrl.cy);
+        let back = self.c_ctrl.rx.saturating_sub(rx_for(raw, popup.col));
+        let height = cmp::min(popup.items.len(), POPUP_HEIGHT);
+        let longest = popup.items.iter().map(|i| i.chars().count()).max();
+        let width = cmp::min(longest.unwrap_or(0) + 2, self.size.0);
+        let x = cmp::min((c_x as usize).saturating_sub(back), self.size.0 - width);
+        let y = if c_y as usize + 1 + height <= self.size.1 {
+            c_y as usize + 1
+        } else {
+            (c_y as usize).saturating_sub(height)
+        };
+        let first = popup.selected.map_or(0, |s| (s + 1).saturating_sub(height));
+        for (i, item) in popup.items.iter().enumerate().skip(first).take(height) {
+            let color = if popup.selected == Some(i) {
+                style::Color::DarkCyan
+            } else {
+                style::Color::DarkGrey
+            };
+            let text: String = format!(" {:<w$}", item, w = width - 1)
+                .chars()
+                .take(width)
+                .collect();
+            queue!(
+                self.out,
+                cursor::MoveTo(x as u16, (y + i - first) as u16),
+                style::SetBackgroundColor(color),
+                style::Print(text),
+                style::ResetColor
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn selection(&self) -> Option<((usize, usize), (usize, usize))> {
+        let (anchor, _) = self.visual?;
+        let cursor = self.cursor();
+        if (anchor.1, anchor.0) <= (cursor.1, cursor.0) {
+            Some((anchor, cursor))
+        } else {
+            Some((cursor, anchor))
+        }
+    }
+
+    fn selected_cols(&self, rows: &EditorRows, line: usize) -> Option<(usize, usize)> {
+        let (s, e) = self.selection()?;
+        if line < s.1 || line > e.1 {
+            return None;
+        }
+        if self.visual.is_some_and(|(_, linewise)| linewise) {
+            return Some((0, usize::MAX));
+        }
+        let raw = rows.get_raw(line);
+        let a = if line == s.1 { rx_for(raw, s.0) } else { 0 };
+        let b = if line == e.1 {
+            rx_for(raw, e.0 + 1)
+        } else {
+            usize::MAX
+        };
+        Some((a, b))
+    }
+
+    fn render_gutter(
+        &mut self,
+        rows: &EditorRows,
+        opts: &Options,
+        line: usize,
+        first: bool,
+    ) -> io::Result<()> {
+        if !first {
+            write!(self.out, "{:width$}", "", width = self.gutter.width())?;
+            return Ok(());
+        }
+        if self.gutter.signs > 0 {
+            let text = rows
+                .sign_at(line)
+                .map(|s| s.text.as_str())
+                .unwrap_or_default();
+            write!(self.out, "{:<2}", text)?;
+        }
+        if self.gutter.numbers > 0 {
+            let width = self.gutter.numbers - 1;
+            let rel = line.abs_diff(self.c_ctrl.cy);
+            let color = if rel == 0 {
+                style::Color::Yellow
+            } else {
+                style::Color::DarkGrey
+            };
+            queue!(self.out, style::SetForegroundColor(color))?;
+            match (opts.number, opts.relativenumber) {
+                (true, true) if rel == 0 => write!(self.out, "{:<width$} ", line + 1)?,
+                (_, true) => write!(self.out, "{:>width$} ", rel)?,
+                _ => write!(self.out, "{:>width$} ", line + 1)?,
+            }
+            queue!(self.out, style::ResetColor)?;
+        }
+        Ok(())
+    }
+
+    fn render_bar(
+        &mut self,
+        rows: &EditorRows,
+        hex: Option<&HexRows>,
+        large: Option<&LargeRows>,
+        opts: &Options,
+    ) -> io::Result<()> {
         self.out
-            .write(&style::Attribute::Reverse.to_string().as_bytes())?;
-        let info_f = format!(
-            "\"{}\"{} {}L, {}B",
+            .write_all(style::Attribute::Reverse.to_string().as_bytes())?;
+        let name = format!(
+            "\"{}\"{}{}{}",
             rows.filename
                 .as_ref()
                 .and_then(|p| p.file_name())
                 .and_then(|n| n.to_str())
                 .unwrap_or("No name"),
             if self.dirty > 0 { "*" } else { "" },
-            rows.num_rows(),
-            rows.filename
-                .as_ref()
-                .and_then(|p| metadata(p).ok().map(|meta| meta.len()))
-                .unwrap_or(0),
-        );
-        self.out.write(info_f.as_bytes())?;
-        let row = rows.get_raw(self.c_ctrl.cy).len().saturating_sub(1);
-        let info_c = format!(
-            "{}:{}/{} ({}) {}",
-            c_y, c_x, self.c_ctrl.rx, row, self.c_ctrl.cmdx,
+            if opts.readonly { " [RO]" } else { "" },
+            if opts.modifiable { "" } else { " [-]" },
         );
+        let size = rows
+            .filename
+            .as_ref()
+            .and_then(|p| metadata(p).ok().map(|meta| meta.len()))
+            .unwrap_or(0);
+        let compression = rows
+            .compression
+            .map(|c| format!(" {}", c.name()))
+            .unwrap_or_default();
+        let (info_f, info_c) = match (hex, large) {
+            (Some(hex), _) => (
+                format!("{} {}B hex{}", name, hex.len(), compression),
+                format!("{:08x}/{:08x}", hex.cursor, hex.len()),
+            ),
+            (None, Some(large)) => {
+                let indexing = match large.indexing() {
+                    true => format!(" indexing {}%", large.progress()),
+                    false => String::new(),
+                };
+                (
+                    format!(
+                        "{} {}L, {}B {}{}",
+                        name,
+                        large.num_rows(),
+                        size,
+                        opts.fileencoding,
+                        indexing
+                    ),
+                    format!("{}:{}", large.cy + 1, large.cx + 1),
+                )
+            }
+            (None, None) => {
+                let c_x = self.c_ctrl.rx - self.c_ctrl.x_offset;
+                let c_y = self.c_ctrl.cy - self.c_ctrl.y_offset;
+                let row = rows.get_raw(self.c_ctrl.cy).len().saturating_sub(1);
+                (
+                    format!(
+                        "{} {}L, {}B {}{}",
+                        name,
+                        rows.num_rows(),
+                        size,
+                        opts.fileencoding,
+                        compression
+                    ),
+                    format!(
+                        "{}:{}/{} ({}) {}",
+                        c_y, c_x, self.c_ctrl.rx, row, self.c_ctrl.cmdx,
+                    ),
+                )
+            }
+        };
+        self.out.write_all(info_f.as_bytes())?;
         let info_c_pos = self.size.0 - info_c.len();
         for i in info_f.len()..self.size.0 {
             if i >= info_c_pos {
                 let index = i - info_c_pos..i - info_c_pos + 1;
-                self.out.write(info_c[index].as_bytes())?;
+                self.out.write_all(info_c[index].as_bytes())?;
             } else {
-                self.out.write(b" ")?;
+                self.out.write_all(b" ")?;
             }
         }
         self.out
-            .write(&style::Attribute::Reset.to_string().as_bytes())?;
-        self.out.write("\r\n".to_string().as_bytes())?;
+            .write_all(style::Attribute::Reset.to_string().as_bytes())?;
+        self.out.write_all("\r\n".to_string().as_bytes())?;
         Ok(())
     }
 
     fn render_message(&mut self) -> io::Result<()> {
         queue!(self.out, Clear(ClearType::CurrentLine))?;
+        let mut len = 0;
         if let Some(msg) = self.cmd_msg.as_ref().or(self.stt_msg.as_ref()) {
             let content = &msg.content;
             let style = match msg.level {
                 MessageLevel::Normal => style::Attribute::Reset.to_string(),
                 MessageLevel::Danger => style::SetBackgroundColor(style::Color::Red).to_string(),
             };
-            self.out.write(style.as_bytes())?;
-            self.out
-                .write(content[..cmp::min(content.len(), self.size.0)].as_bytes())?;
+            len = cmp::min(content.len(), self.size.0);
+            self.out.write_all(style.as_bytes())?;
+            self.out.write_all(&content.as_bytes()[..len])?;
             self.out
-                .write(style::Attribute::Reset.to_string().as_bytes())?;
+                .write_all(style::Attribute::Reset.to_string().as_bytes())?;
+        }
+        if let Some(reg) = self.recording {
+            let rec = format!("recording @{}", reg);
+            let rec = &rec[..cmp::min(rec.len(), self.size.0 - len)];
+            self.out.write_all(rec.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn render_wildmenu(&mut self) -> io::Result<()> {
+        let Some(popup) = &self.popup else {
+            return Ok(());
+        };
+        let selected = popup.selected.unwrap_or(0);
+        let widths: Vec<usize> = popup.items.iter().map(|i| i.chars().count() + 2).collect();
+        let mut first = 0;
+        while first < selected && widths[first..=selected].iter().sum::<usize>() > self.size.0 {
+            first += 1;
+        }
+        queue!(
+            self.out,
+            cursor::MoveTo(0, self.size.1 as u16),
+            style::SetBackgroundColor(style::Color::DarkGrey),
+            Clear(ClearType::UntilNewLine)
+        )?;
+        let mut used = 0;
+        for (i, item) in popup.items.iter().enumerate().skip(first) {
+            if used + widths[i] > self.size.0 {
+                break;
+            }
+            used += widths[i];
+            if popup.selected == Some(i) {
+                queue!(
+                    self.out,
+                    style::SetBackgroundColor(style::Color::DarkCyan),
+                    style::Print(format!(" {} ", item)),
+                    style::SetBackgroundColor(style::Color::DarkGrey)
+                )?;
+            } else {
+                queue!(self.out, style::Print(format!(" {} ", item)))?;
+            }
         }
+        queue!(self.out, style::ResetColor)?;
         Ok(())
     }
 
     fn render_command(&mut self) -> io::Result<()> {
+        self.render_wildmenu()?;
         let y = (self.size.1 + 2) as u16;
         queue!(
             self.out,
@@ -174,10 +626,10 @@ impl Output {
             cursor::Hide,
             cursor::MoveTo(0, y),
         )?;
-        self.out.write(b":")?;
+        self.out.write_all(b":")?;
 
         if let Some(cmd) = &self.cmd {
-            self.out.write(cmd.as_bytes())?;
+            self.out.write_all(cmd.as_bytes())?;
         }
         queue!(
             self.out,
@@ -191,8 +643,31 @@ impl Output {
     pub fn insert(&mut self, e_rows: &mut EditorRows, c: char) {
         let (x, y) = (self.c_ctrl.cx, self.c_ctrl.cy);
         e_rows.get_erow_mut(y).insert(x, c);
-        self.c_ctrl.cx += 1;
-        self.dirty += 1;
+        self.c_ctrl.cx += c.len_utf8();
+        self.touch();
+    }
+
+    pub fn replace_char(&mut self, e_rows: &mut EditorRows, c: char) -> Option<char> {
+        let (x, y) = (self.c_ctrl.cx, self.c_ctrl.cy);
+        let erow = e_rows.get_erow_mut(y);
+        let orig = erow.raw[x..].chars().next();
+        let len = orig.map_or(0, |o| o.len_utf8());
+        erow.replace_range(x..x + len, c.encode_utf8(&mut [0; 4]));
+        self.c_ctrl.cx += c.len_utf8();
+        self.touch();
+        orig
+    }
+
+    pub fn restore_char(&mut self, e_rows: &mut EditorRows, orig: Option<char>) {
+        let y = self.c_ctrl.cy;
+        let erow = e_rows.get_erow_mut(y);
+        let Some((x, c)) = erow.raw[..self.c_ctrl.cx].char_indices().last() else {
+            return;
+        };
+        let restored = orig.map(String::from).unwrap_or_default();
+        erow.replace_range(x..x + c.len_utf8(), &restored);
+        self.c_ctrl.cx = x;
+        self.touch();
     }
 
     pub fn new_line(&mut self, dir: Direction, e_rows: &mut EditorRows) {
@@ -205,18 +680,7 @@ impl Output {
 
         self.c_ctrl.cy = y;
         self.c_ctrl.cx = 0;
-        self.dirty += 1;
-    }
-
-    pub fn delete_line(&mut self, e_rows: &mut EditorRows) {
-        let n_rows = e_rows.num_rows().saturating_sub(1);
-        if self.c_ctrl.cy == 0 && n_rows == 0 {
-            e_rows.clear_erow(self.c_ctrl.cy);
-        } else {
-            e_rows.delete_erow(self.c_ctrl.cy);
-            self.c_ctrl.cy = self.c_ctrl.cy.saturating_sub(1);
-        }
-        self.c_ctrl.cx = 0;
+        self.touch();
     }
 
     pub fn break_line(&mut self, e_rows: &mut EditorRows) {
@@ -229,27 +693,26 @@ impl Output {
 
         self.c_ctrl.cx = 0;
         self.c_ctrl.cy += 1;
-        self.dirty += 1;
+        self.touch();
     }
 
     pub fn delete_char(&mut self, e_rows: &mut EditorRows, mode: &Mode) {
-        if e_rows.get_raw(self.c_ctrl.cy).len() == 0 && mode != &Mode::Command {
+        if e_rows.get_raw(self.c_ctrl.cy).is_empty() && mode != &Mode::Command {
             return;
         }
 
         let erow_mut = e_rows.get_erow_mut(self.c_ctrl.cy);
         match mode {
-            Mode::Normal => {
+            Mode::Normal | Mode::Visual => {
                 erow_mut.delete_char(self.c_ctrl.cx);
-                if self.c_ctrl.cx > erow_mut.raw.len().saturating_sub(1) {
-                    self.c_ctrl.cx = erow_mut.raw.len().saturating_sub(1)
-                }
-                self.dirty += 1;
+                self.c_ctrl.clamp_x(e_rows, mode);
+                self.touch();
             }
-            Mode::Insert => {
+            Mode::Insert | Mode::Replace => {
                 if self.c_ctrl.cx > 0 {
-                    erow_mut.delete_char(self.c_ctrl.cx - 1);
-                    self.c_ctrl.mv(Direction::Left, &e_rows, &mode);
+                    let prev = prev_char(&erow_mut.raw, self.c_ctrl.cx);
+                    erow_mut.delete_char(prev);
+                    self.c_ctrl.cx = prev;
                 } else {
                     if self.c_ctrl.cy > 0 {
                         let prev_erow_content = e_rows.get_raw(self.c_ctrl.cy - 1);
@@ -259,147 +722,242 @@ impl Output {
                         self.c_ctrl.cy -= 1;
                     }
                 }
-                self.dirty += 1;
+                self.touch();
             }
             Mode::Command => {
+                let i = self.cmd_cursor();
                 if let Some(cmd) = &mut self.cmd {
-                    if self.c_ctrl.cmdx > 1 {
-                        cmd.remove(self.c_ctrl.cmdx - 2);
-                        self.c_ctrl.mv(Direction::Left, &e_rows, &mode);
+                    if let Some((prev, _)) = cmd[..i].char_indices().last() {
+                        cmd.remove(prev);
+                        self.c_ctrl.mv(Direction::Left, e_rows, mode);
                     }
                 }
             }
         }
     }
 
-    pub fn goto_y(&mut self, y: usize) {
-        self.c_ctrl.cy = y;
+    pub fn find_char(
+        &mut self,
+        e_rows: &EditorRows,
+        c: char,
+        forward: bool,
+        till: bool,
+        skip: bool,
+    ) -> bool {
+        let chars: Vec<(usize, char)> = e_rows.get_raw(self.c_ctrl.cy).char_indices().collect();
+        let cur = chars
+            .iter()
+            .position(|(i, _)| *i >= self.c_ctrl.cx)
+            .unwrap_or(chars.len());
+        let offset = if till && skip { 2 } else { 1 };
+        let found = if forward {
+            (cur + offset..chars.len())
+                .find(|i| chars[*i].1 == c)
+                .map(|i| if till { i - 1 } else { i })
+        } else {
+            (0..cur.saturating_sub(offset - 1))
+                .rev()
+                .find(|i| chars[*i].1 == c)
+                .map(|i| if till { i + 1 } else { i })
+        };
+        match found {
+            Some(i) => {
+                self.c_ctrl.cx = chars[i].0;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn goto_match(&mut self, e_rows: &EditorRows) -> bool {
+        let (cx, cy) = (self.c_ctrl.cx, self.c_ctrl.cy);
+        let row = e_rows.get_raw(cy);
+        let Some(x) = row.get(cx..).and_then(|r| r.find(|c| "()[]{}".contains(c))) else {
+            return false;
+        };
+        match e_rows.match_pair((cx + x, cy), 0..e_rows.num_rows()) {
+            Some((x, y)) => {
+                self.c_ctrl.cx = x;
+                self.c_ctrl.cy = y;
+                true
+            }
+            None => false,
+        }
     }
 
     pub fn goto_end_line(&mut self, e_rows: &EditorRows, mode: &Mode) {
-        let sub = match mode {
-            Mode::Insert => 0,
-            _=> 1,
+        let raw = e_rows.get_raw(self.c_ctrl.cy);
+        self.c_ctrl.cx = match mode {
+            Mode::Insert => raw.len(),
+            _ => last_char(raw),
         };
-        self.c_ctrl.cx = e_rows.get_raw(self.c_ctrl.cy).len().saturating_sub(sub);
     }
 
     pub fn goto_start_line(&mut self, e_rows: &EditorRows) {
-        let curr_erow = e_rows.get_raw(self.c_ctrl.cy);
-        let erow_len = curr_erow.len().saturating_sub(1);
-        let mut pos = 0;
-        if erow_len > 0 {
-            while curr_erow.as_bytes()[pos].is_ascii_whitespace() {
-                pos += 1;
-            }
-        }
-        self.c_ctrl.cx = cmp::min(pos, curr_erow.len().saturating_sub(1));
+        let raw = e_rows.get_raw(self.c_ctrl.cy);
+        let pos = raw.len()
+            - raw
+                .trim_start_matches(|c: char| c.is_ascii_whitespace())
+                .len();
+        self.c_ctrl.cx = cmp::min(pos, last_char(raw));
     }
 
-    pub fn next_word(&mut self, e_rows: &EditorRows, to_end: bool) {
-        if e_rows.get_raw(self.c_ctrl.cy).len().saturating_sub(1) == self.c_ctrl.cx {
-            self.c_ctrl.mv(Direction::Down, e_rows, &Mode::Normal);
-            self.c_ctrl.cx = 0;
-        } else {
-            let curr_erow = e_rows.get_raw(self.c_ctrl.cy);
-            let erow_len = curr_erow.len().saturating_sub(1);
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.c_ctrl.cx, self.c_ctrl.cy)
+    }
 
-            if erow_len == 0 {
-                return;
-            }
+    pub fn set_cursor(&mut self, (x, y): (usize, usize), e_rows: &EditorRows, mode: &Mode) {
+        self.c_ctrl.cy = cmp::min(y, e_rows.num_rows() - 1);
+        self.c_ctrl.cx = x;
+        self.c_ctrl.clamp_x(e_rows, mode);
+    }
 
-            let mut pos = self.c_ctrl.cx;
-            if !to_end {
-                if !curr_erow.as_bytes()[pos].is_ascii_alphabetic() {
-                    while pos < erow_len && !curr_erow.as_bytes()[pos].is_ascii_alphabetic() {
-                        pos += 1;
-                    }
-                } else {
-                    while pos < erow_len
-                        && (curr_erow.as_bytes()[pos].is_ascii_alphabetic()
-                            || curr_erow.as_bytes()[pos].is_ascii_alphanumeric())
-                    {
-                        pos += 1;
-                    }
-                }
-            }
+    pub fn move_display_line(
+        &mut self,
+        dir: Direction,
+        e_rows: &EditorRows,
+        mode: &Mode,
+        opts: &Options,
+    ) {
+        if !opts.wrap {
+            return self.c_ctrl.mv(dir, e_rows, mode);
+        }
+        let width = self.text_width();
+        let sbr = opts.showbreak.chars().count();
+        let cy = self.c_ctrl.cy;
+        let rx = self.c_ctrl.get_rx(e_rows.get_raw(cy));
+        let segs = wrap_segments(e_rows.get_render(cy), width, opts);
+        let k = segment_of(&segs, rx);
+        let col = rx - segs[k].0 + if k > 0 { sbr } else { 0 };
 
-            while pos < erow_len && curr_erow.as_bytes()[pos].is_ascii_whitespace() {
-                pos += 1;
+        let (line, k) = match dir {
+            Direction::Down if k + 1 < segs.len() => (cy, k + 1),
+            Direction::Down if cy + 1 < e_rows.num_rows() => (cy + 1, 0),
+            Direction::Up if k > 0 => (cy, k - 1),
+            Direction::Up if cy > 0 => {
+                let segs = wrap_segments(e_rows.get_render(cy - 1), width, opts);
+                (cy - 1, segs.len() - 1)
             }
+            _ => return,
+        };
+        let (start, end) = wrap_segments(e_rows.get_render(line), width, opts)[k];
+        let col = col.saturating_sub(if k > 0 { sbr } else { 0 });
+        let rx = cmp::min(start + col, cmp::max(end, start + 1) - 1);
 
-            if to_end {
-                if !curr_erow.as_bytes()[pos].is_ascii_alphabetic() {
-                    while pos < erow_len && !curr_erow.as_bytes()[pos].is_ascii_alphabetic() {
-                        pos += 1;
-                    }
-                } else {
-                    while pos < erow_len
-                        && (curr_erow.as_bytes()[pos].is_ascii_alphabetic()
-                            || curr_erow.as_bytes()[pos].is_ascii_alphanumeric())
-                    {
-                        pos += 1;
-                    }
-                }
-                while pos > 0 && curr_erow.as_bytes()[pos].is_ascii_whitespace() {
-                    pos -= 1;
-                }
-            }
+        self.c_ctrl.cy = line;
+        self.c_ctrl.cx = cx_for_rx(e_rows.get_raw(line), rx);
+        self.c_ctrl.clamp_x(e_rows, mode);
+    }
 
-            self.c_ctrl.cx = cmp::min(pos, erow_len);
-        }
+    pub fn goto_display_edge(
+        &mut self,
+        to_end: bool,
+        e_rows: &EditorRows,
+        mode: &Mode,
+        opts: &Options,
+    ) {
+        let cy = self.c_ctrl.cy;
+        let rx = self.c_ctrl.get_rx(e_rows.get_raw(cy));
+        let segs = self.segments(e_rows, cy, opts);
+        let (start, end) = segs[segment_of(&segs, rx)];
+        let rx = if to_end {
+            cmp::max(end, start + 1) - 1
+        } else {
+            start
+        };
+        self.c_ctrl.cx = cx_for_rx(e_rows.get_raw(cy), rx);
+        self.c_ctrl.clamp_x(e_rows, mode);
     }
 
-    pub fn prev_word(&mut self, e_rows: &EditorRows, to_start: bool) {
-        if self.c_ctrl.cx == 0 && self.c_ctrl.cy != 0 {
-            self.c_ctrl.mv(Direction::Up, e_rows, &Mode::Normal);
-            self.c_ctrl.cx = e_rows.get_raw(self.c_ctrl.cy).len().saturating_sub(1);
+    pub fn scroll_view(
+        &mut self,
+        lines: isize,
+        with_cursor: bool,
+        e_rows: &EditorRows,
+        mode: &Mode,
+        opts: &Options,
+    ) {
+        let last = e_rows.num_rows() - 1;
+        let shift = |v: usize| cmp::min(v.saturating_add_signed(lines), last);
+        self.c_ctrl.y_offset = shift(self.c_ctrl.y_offset);
+        if with_cursor {
+            self.c_ctrl.cy = shift(self.c_ctrl.cy);
+        }
+
+        let width = self.text_width();
+        let so = self.c_ctrl.scrolloff(opts);
+        let (top, bottom) = self.c_ctrl.visible_range(e_rows, width, opts);
+        let top = if top == 0 { 0 } else { top + so };
+        let bottom = if bottom == last {
+            last
         } else {
-            let curr_erow = e_rows.get_raw(self.c_ctrl.cy);
+            bottom.saturating_sub(so)
+        };
+        self.c_ctrl.cy = self.c_ctrl.cy.clamp(top, cmp::max(top, bottom));
+        self.c_ctrl.clamp_x(e_rows, mode);
+    }
 
-            let mut pos = self.c_ctrl.cx;
-            if !curr_erow.as_bytes()[pos].is_ascii_alphabetic()
-                && !curr_erow.as_bytes()[pos].is_ascii_alphanumeric()
-            {
-                while pos > 0
-                    && !curr_erow.as_bytes()[pos].is_ascii_alphabetic()
-                    && !curr_erow.as_bytes()[pos].is_ascii_alphanumeric()
-                {
-                    pos -= 1;
-                }
-            } else {
-                while pos > 0
-                    && (curr_erow.as_bytes()[pos].is_ascii_alphabetic()
-                        || curr_erow.as_bytes()[pos].is_ascii_alphanumeric())
-                {
-                    pos -= 1;
-                }
-            }
+    pub fn half_page(&self) -> usize {
+        cmp::max(self.size.1 / 2, 1)
+    }
 
-            while pos > 0 && curr_erow.as_bytes()[pos].is_ascii_whitespace() {
-                pos -= 1;
-            }
+    pub fn page_height(&self) -> usize {
+        cmp::max(self.size.1.saturating_sub(2), 1)
+    }
 
-            if to_start {
-                if !curr_erow.as_bytes()[pos].is_ascii_alphabetic() {
-                    while pos > 0 && !curr_erow.as_bytes()[pos].is_ascii_alphabetic() {
-                        pos -= 1;
-                    }
-                } else {
-                    while pos > 0
-                        && (curr_erow.as_bytes()[pos].is_ascii_alphabetic()
-                            || curr_erow.as_bytes()[pos].is_ascii_alphanumeric())
-                    {
-                        pos -= 1;
-                    }
-                }
-                while pos > 0 && curr_erow.as_bytes()[pos].is_ascii_whitespace() {
-                    pos += 1;
-                }
-            }
+    pub fn page(&self, e_rows: &EditorRows, opts: &Options) -> usize {
+        let (top, bottom) = self.c_ctrl.visible_range(e_rows, self.text_width(), opts);
+        cmp::max((bottom - top + 1).saturating_sub(2), 1)
+    }
 
-            self.c_ctrl.cx = cmp::min(pos, curr_erow.len().saturating_sub(1));
+    pub fn align_view(&mut self, pos: ScreenPos, e_rows: &EditorRows, opts: &Options) {
+        let so = self.c_ctrl.scrolloff(opts);
+        let height = self.size.1;
+        let cy = self.c_ctrl.cy;
+        if !opts.wrap {
+            self.c_ctrl.y_offset = match pos {
+                ScreenPos::Top => cy.saturating_sub(so),
+                ScreenPos::Middle => cy.saturating_sub(height / 2),
+                ScreenPos::Bottom => (cy + so + 1).saturating_sub(height),
+            };
+            return;
         }
+        let width = self.text_width();
+        let lines = |y: usize| wrap_segments(e_rows.get_render(y), width, opts).len();
+        let above = match pos {
+            ScreenPos::Top => so,
+            ScreenPos::Middle => height.saturating_sub(lines(cy)) / 2,
+            ScreenPos::Bottom => height.saturating_sub(lines(cy) + so),
+        };
+        let mut top = cy;
+        let mut used = 0;
+        while top > 0 && used + lines(top - 1) <= above {
+            used += lines(top - 1);
+            top -= 1;
+        }
+        self.c_ctrl.y_offset = top;
+    }
+
+    pub fn goto_screen_line(
+        &mut self,
+        pos: ScreenPos,
+        offset: usize,
+        e_rows: &EditorRows,
+        opts: &Options,
+    ) {
+        let width = self.text_width();
+        let so = self.c_ctrl.scrolloff(opts);
+        let (top, bottom) = self.c_ctrl.visible_range(e_rows, width, opts);
+        let last = e_rows.num_rows() - 1;
+        let y = match pos {
+            ScreenPos::Top => top + cmp::max(offset, if top == 0 { 0 } else { so }),
+            ScreenPos::Middle => top + (bottom - top) / 2,
+            ScreenPos::Bottom => {
+                bottom.saturating_sub(cmp::max(offset, if bottom == last { 0 } else { so }))
+            }
+        };
+        self.c_ctrl.cy = y.clamp(top, bottom);
+        self.goto_start_line(e_rows);
     }
 
     pub fn move_cursor(&mut self, dir: Direction, e_rows: &EditorRows, mode: &Mode) {
@@ -427,12 +985,21 @@ impl Output {
     }
 
     pub fn push_cmd(&mut self, c: char) {
-        self.cmd.get_or_insert_with(String::new).push(c);
+        let i = self.cmd_cursor();
+        self.cmd.get_or_insert_with(String::new).insert(i, c);
         self.c_ctrl.cmdx += 1;
     }
 
-    pub fn get_cmd(&self) -> Option<Vec<&str>> {
-        self.cmd.as_ref().map(|cmd| cmd.split(' ').collect())
+    pub fn cmd_cursor(&self) -> usize {
+        let cmd = self.cmd.as_deref().unwrap_or_default();
+        cmd.char_indices()
+            .nth(self.c_ctrl.cmdx - 1)
+            .map_or(cmd.len(), |(i, _)| i)
+    }
+
+    pub fn set_cmd_cursor(&mut self, i: usize) {
+        let cmd = self.cmd.as_deref().unwrap_or_default();
+        self.c_ctrl.cmdx = cmd[..cmp::min(i, cmd.len())].chars().count() + 1;
     }
 
     pub fn clear_cmd(&mut self) {
@@ -466,23 +1033,19 @@ impl CursorController {
         let n_rows = e_rows.num_rows() - 1;
         let row = e_rows.get_raw(self.cy);
         let row_len = match mode {
-            Mode::Normal => row.len().saturating_sub(1),
+            Mode::Normal | Mode::Visual => row.len().saturating_sub(1),
             _ => row.len(),
         };
         match mode {
             Mode::Command => match dir {
                 Direction::Left => self.cmdx = self.cmdx.saturating_sub(1),
-                Direction::Right => {
-                    if self.cmdx < row_len {
-                        self.cmdx += 1;
-                    }
-                }
+                Direction::Right if self.cmdx < row_len => self.cmdx += 1,
                 _ => {}
             },
             _ => {
                 match dir {
                     Direction::Up => self.cy = self.cy.saturating_sub(1),
-                    Direction::Left => self.cx = self.cx.saturating_sub(1),
+                    Direction::Left => self.cx = prev_char(row, self.cx),
                     Direction::Down => {
                         if self.cy < n_rows {
                             self.cy += 1;
@@ -490,34 +1053,80 @@ impl CursorController {
                     }
                     Direction::Right => {
                         if self.cx < row_len {
-                            self.cx += 1;
-                            if row.chars().nth(self.cx) == Some('\t') {
-                                self.cx += 1;
+                            self.cx = next_char(row, self.cx);
+                            if row[self.cx..].starts_with('\t') {
+                                self.cx = next_char(row, self.cx);
                             }
                         }
                     }
                 };
-
-                let new_row = e_rows.get_raw(self.cy);
-                let new_row_len = match mode {
-                    Mode::Normal => new_row.len().saturating_sub(1),
-                    _ => new_row.len(),
-                };
-                self.cx = cmp::min(self.cx, new_row_len);
+                self.clamp_x(e_rows, mode);
             }
         }
     }
+    fn clamp_x(&mut self, e_rows: &EditorRows, mode: &Mode) {
+        let raw = e_rows.get_raw(self.cy);
+        let last = match mode {
+            Mode::Normal | Mode::Visual => last_char(raw),
+            _ => raw.len(),
+        };
+        self.cx = cmp::min(self.cx, last);
+        while !raw.is_char_boundary(self.cx) {
+            self.cx -= 1;
+        }
+    }
+
     fn get_rx(&self, raw: &str) -> usize {
-        raw.chars().take(self.cx).fold(0, |rx, c| {
-            if c == '\t' {
-                (rx + TAB_SZ) & !(TAB_SZ - 1)
-            } else {
-                rx + 1
+        rx_for(raw, self.cx)
+    }
+
+    fn scrolloff(&self, opts: &Options) -> usize {
+        cmp::min(opts.scrolloff, self.screen_size.1.saturating_sub(1) / 2)
+    }
+
+    fn visible_range(&self, e_rows: &EditorRows, width: usize, opts: &Options) -> (usize, usize) {
+        let last = e_rows.num_rows() - 1;
+        let top = cmp::min(self.y_offset, last);
+        if !opts.wrap {
+            let bottom = top + self.screen_size.1.saturating_sub(1);
+            return (top, cmp::min(bottom, last));
+        }
+        let mut used = 0;
+        let mut bottom = top;
+        for line in top..=last {
+            used += wrap_segments(e_rows.get_render(line), width, opts).len();
+            if used > self.screen_size.1 && line > top {
+                break;
             }
-        })
+            bottom = line;
+        }
+        (top, bottom)
     }
 
-    fn scroll(&mut self, e_rows: &EditorRows) {
+    fn cursor_segment(&self, e_rows: &EditorRows, width: usize, opts: &Options) -> (usize, usize) {
+        let segs = wrap_segments(e_rows.get_render(self.cy), width, opts);
+        let sbr = opts.showbreak.chars().count();
+        let k = segment_of(&segs, self.rx);
+        let x = self.rx - segs[k].0 + if k > 0 { sbr } else { 0 };
+        if x >= width {
+            (k + 1, sbr)
+        } else {
+            (k, x)
+        }
+    }
+
+    fn screen_pos(&self, e_rows: &EditorRows, width: usize, opts: &Options) -> (usize, usize) {
+        if !opts.wrap {
+            return (self.rx - self.x_offset, self.cy - self.y_offset);
+        }
+        let (k, x) = self.cursor_segment(e_rows, width, opts);
+        let above: usize = (self.y_offset..self.cy)
+            .map(|line| wrap_segments(e_rows.get_render(line), width, opts).len())
+            .sum();
+        (x, above + k)
+    }
+
+    fn scroll(&mut self, e_rows: &EditorRows, width: usize, opts: &Options) {
         self.rx = 0;
         if self.cy < e_rows.num_rows() {
             let row = e_rows.get_raw(self.cy);
@@ -527,18 +1136,114 @@ impl CursorController {
             self.rx = self.get_rx(row);
         }
 
-        self.y_offset = cmp::min(self.y_offset, self.cy);
-        if self.cy >= self.y_offset + self.screen_size.1 {
-            self.y_offset = self.cy - self.screen_size.1 + 1;
+        let so = self.scrolloff(opts);
+        let below = cmp::min(self.cy + so, e_rows.num_rows() - 1);
+        self.y_offset = cmp::min(self.y_offset, self.cy.saturating_sub(so));
+        if opts.wrap {
+            self.x_offset = 0;
+            let (k, _) = self.cursor_segment(e_rows, width, opts);
+            let margin: usize = (self.cy + 1..=below)
+                .map(|line| wrap_segments(e_rows.get_render(line), width, opts).len())
+                .sum();
+            let mut used = cmp::min(k + 1 + margin, self.screen_size.1);
+            let mut top = self.cy;
+            while top > self.y_offset {
+                let height = wrap_segments(e_rows.get_render(top - 1), width, opts).len();
+                if used + height > self.screen_size.1 {
+                    break;
+                }
+                used += height;
+                top -= 1;
+            }
+            self.y_offset = top;
+            return;
+        }
+        if below >= self.y_offset + self.screen_size.1 {
+            self.y_offset = below + 1 - self.screen_size.1;
         }
 
         self.x_offset = cmp::min(self.x_offset, self.rx);
-        if self.rx >= self.x_offset + self.screen_size.0 {
-            self.x_offset = self.rx - self.screen_size.0 + 1;
+        if self.rx >= self.x_offset + width {
+            self.x_offset = self.rx - width + 1;
         }
     }
 }
 
+fn wrap_segments(render: &str, width: usize, opts: &Options) -> Vec<(usize, usize)> {
+    let chars: Vec<char> = render.chars().collect();
+    let width = cmp::max(width, 1);
+    if !opts.wrap || chars.len() <= width {
+        return vec![(0, chars.len())];
+    }
+
+    let sbr = opts.showbreak.chars().count();
+    let mut segs = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let avail = if segs.is_empty() {
+            width
+        } else {
+            cmp::max(width.saturating_sub(sbr), 1)
+        };
+        let mut end = cmp::min(start + avail, chars.len());
+        if opts.linebreak && end < chars.len() {
+            if let Some(brk) = (start + 1..end)
+                .rev()
+                .find(|i| BREAKAT.contains(chars[i - 1]))
+            {
+                end = brk;
+            }
+        }
+        segs.push((start, end));
+        start = end;
+    }
+    segs
+}
+
+fn segment_of(segs: &[(usize, usize)], rx: usize) -> usize {
+    segs.iter()
+        .rposition(|(start, _)| *start <= rx)
+        .unwrap_or(0)
+}
+
+fn prev_char(raw: &str, cx: usize) -> usize {
+    raw[..cx].char_indices().last().map_or(0, |(i, _)| i)
+}
+
+fn next_char(raw: &str, cx: usize) -> usize {
+    raw[cx..].chars().next().map_or(cx, |c| cx + c.len_utf8())
+}
+
+fn last_char(raw: &str) -> usize {
+    prev_char(raw, raw.len())
+}
+
+fn rx_for(raw: &str, cx: usize) -> usize {
+    let chars = raw.char_indices().take_while(|(i, _)| *i < cx);
+    chars.fold(0, |rx, (_, c)| {
+        if c == '\t' {
+            (rx + TAB_SZ) & !(TAB_SZ - 1)
+        } else {
+            rx + char_width(c)
+        }
+    })
+}
+
+fn cx_for_rx(raw: &str, rx: usize) -> usize {
+    let mut cur = 0;
+    for (i, c) in raw.char_indices() {
+        cur = if c == '\t' {
+            (cur + TAB_SZ) & !(TAB_SZ - 1)
+        } else {
+            cur + char_width(c)
+        };
+        if cur > rx {
+            return i;
+        }
+    }
+    raw.len()
+}
+
 pub enum MessageLevel {
     Normal,
     Danger,
@@ -555,3 +1260,43 @@ impl StatusMessage {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(cy: usize) -> Output {
+        let mut out = Output::with_size((80, 20));
+        out.c_ctrl.cy = cy;
+        out
+    }
+
+    #[test]
+    fn start_line_skips_indent() {
+        let rows = EditorRows::from_lines(&["  \tfoo"]);
+        let mut out = output(0);
+        out.goto_start_line(&rows);
+        assert_eq!(out.c_ctrl.cx, 3);
+    }
+
+    #[test]
+    fn start_line_on_blank_rows() {
+        let rows = EditorRows::from_lines(&["", " ", "  \t"]);
+        for (cy, cx) in [(0, 0), (1, 0), (2, 2)] {
+            let mut out = output(cy);
+            out.goto_start_line(&rows);
+            assert_eq!(out.c_ctrl.cx, cx);
+        }
+    }
+
+    #[test]
+    fn screen_line_onto_whitespace_row() {
+        let rows = EditorRows::from_lines(&["  ", "a", "   "]);
+        let opts = Options::default();
+        let mut out = output(1);
+        out.goto_screen_line(ScreenPos::Top, 0, &rows, &opts);
+        assert_eq!((out.c_ctrl.cx, out.c_ctrl.cy), (1, 0));
+        out.goto_screen_line(ScreenPos::Bottom, 0, &rows, &opts);
+        assert_eq!((out.c_ctrl.cx, out.c_ctrl.cy), (2, 2));
+    }
+}
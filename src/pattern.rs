@@ -0,0 +1,438 @@
+use std::ops::Range;
+
+enum Node {
+    Char(char),
+    Any,
+    Class(Vec<ClassItem>, bool),
+    Start,
+    End,
+    WordStart,
+    WordEnd,
+    Group(Vec<Vec<Node>>),
+    Repeat(Box<Node>, usize, Option<usize>),
+}
+
+enum ClassItem {
+    Range(char, char),
+    Space,
+    Digit,
+    Word,
+}
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            ClassItem::Range(lo, hi) => (*lo..=*hi).contains(&c),
+            ClassItem::Space => c == ' ' || c == '\t',
+            ClassItem::Digit => c.is_ascii_digit(),
+            ClassItem::Word => is_word(c),
+        }
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// A search pattern in Vim's magic syntax: `^` `$` `.` `*` `[...]`, `\+` `\=`
+/// `\?` `\{n,m}`, `\(...\)` `\|`, `\<` `\>`, `\s` `\d` `\w` and their negations,
+/// and `\c` or `\C` to ignore or match case.
+pub struct Pattern {
+    alts: Vec<Vec<Node>>,
+    icase: bool,
+}
+impl Pattern {
+    pub fn new(pat: &str) -> Result<Self, String> {
+        let mut chars: Vec<char> = Vec::with_capacity(pat.len());
+        let mut icase = false;
+        let mut it = pat.chars();
+        while let Some(c) = it.next() {
+            if c != '\\' {
+                chars.push(c);
+                continue;
+            }
+            match it.next() {
+                Some('c') => icase = true,
+                Some('C') => icase = false,
+                Some(e) => chars.extend(['\\', e]),
+                None => return Err("Trailing \\ in pattern".into()),
+            }
+        }
+        let mut parser = Parser { s: &chars, i: 0 };
+        let alts = parser.alternation()?;
+        if parser.i < chars.len() {
+            return Err("Unmatched \\)".into());
+        }
+        Ok(Self { alts, icase })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find(text).is_some()
+    }
+
+    /// The byte range of the leftmost match in `text`.
+    pub fn find(&self, text: &str) -> Option<Range<usize>> {
+        let offsets: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+        let m = Matcher {
+            text: &text.chars().collect::<Vec<char>>(),
+            icase: self.icase,
+        };
+        let byte = |i: usize| offsets.get(i).copied().unwrap_or(text.len());
+        (0..=offsets.len()).find_map(|start| {
+            let mut end = None;
+            let found = self.alts.iter().any(|alt| {
+                m.seq(alt, start, &mut |j| {
+                    end = Some(j);
+                    true
+                })
+            });
+            found.then(|| byte(start)..byte(end.unwrap_or(start)))
+        })
+    }
+}
+
+struct Parser<'a> {
+    s: &'a [char],
+    i: usize,
+}
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.s.get(self.i).copied()
+    }
+
+    fn escaped(&self, c: char) -> bool {
+        self.peek() == Some('\\') && self.s.get(self.i + 1) == Some(&c)
+    }
+
+    fn branch_end(&self) -> bool {
+        self.peek().is_none() || self.escaped('|') || self.escaped(')')
+    }
+
+    fn alternation(&mut self) -> Result<Vec<Vec<Node>>, String> {
+        let mut alts = vec![self.branch()?];
+        while self.escaped('|') {
+            self.i += 2;
+            alts.push(self.branch()?);
+        }
+        Ok(alts)
+    }
+
+    fn branch(&mut self) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        if self.peek() == Some('^') {
+            self.i += 1;
+            nodes.push(Node::Start);
+        }
+        let anchored = nodes.len();
+        while !self.branch_end() {
+            let atom = self.atom(nodes.len() == anchored)?;
+            let node = match self.multi()? {
+                Some((min, max)) => Node::Repeat(Box::new(atom), min, max),
+                None => atom,
+            };
+            nodes.push(node);
+        }
+        Ok(nodes)
+    }
+
+    fn multi(&mut self) -> Result<Option<(usize, Option<usize>)>, String> {
+        if self.peek() == Some('*') {
+            self.i += 1;
+            return Ok(Some((0, None)));
+        }
+        if self.peek() != Some('\\') {
+            return Ok(None);
+        }
+        let bounds = match self.s.get(self.i + 1) {
+            Some('+') => (1, None),
+            Some('=' | '?') => (0, Some(1)),
+            Some('{') => {
+                self.i += 2;
+                return self.braces().map(Some);
+            }
+            _ => return Ok(None),
+        };
+        self.i += 2;
+        Ok(Some(bounds))
+    }
+
+    fn braces(&mut self) -> Result<(usize, Option<usize>), String> {
+        let close = self.s[self.i..]
+            .iter()
+            .position(|c| *c == '}')
+            .ok_or("Unmatched \\{")?;
+        let body: String = self.s[self.i..self.i + close].iter().collect();
+        self.i += close + 1;
+        let num = |s: &str| s.trim().parse::<usize>().map_err(|_| "Invalid \\{n,m}");
+        let (min, max) = match body.split_once(',') {
+            Some((min, max)) => (
+                if min.is_empty() { 0 } else { num(min)? },
+                if max.is_empty() {
+                    None
+                } else {
+                    Some(num(max)?)
+                },
+            ),
+            None if body.is_empty() => (0, None),
+            None => (num(&body)?, Some(num(&body)?)),
+        };
+        if max.is_some_and(|max| max < min) {
+            return Err("Invalid \\{n,m}".into());
+        }
+        Ok((min, max))
+    }
+
+    fn atom(&mut self, first: bool) -> Result<Node, String> {
+        let c = self.s[self.i];
+        self.i += 1;
+        let node = match c {
+            '.' => Node::Any,
+            '$' if self.branch_end() => Node::End,
+            '*' if first => Node::Char('*'),
+            '[' => self.class().unwrap_or(Node::Char('[')),
+            '\\' => {
+                let e = self.s[self.i];
+                self.i += 1;
+                let class = |item, negated| Node::Class(vec![item], negated);
+                match e {
+                    '(' => {
+                        let alts = self.alternation()?;
+                        if !self.escaped(')') {
+                            return Err("Unmatched \\(".into());
+                        }
+                        self.i += 2;
+                        Node::Group(alts)
+                    }
+                    ')' => return Err("Unmatched \\)".into()),
+                    '<' => Node::WordStart,
+                    '>' => Node::WordEnd,
+                    's' | 'S' => class(ClassItem::Space, e == 'S'),
+                    'd' | 'D' => class(ClassItem::Digit, e == 'D'),
+                    'w' | 'W' => class(ClassItem::Word, e == 'W'),
+                    't' => Node::Char('\t'),
+                    '+' | '=' | '?' | '{' => return Err(format!("\\{} follows nothing", e)),
+                    e if e.is_alphanumeric() => return Err(format!("Unknown item \\{}", e)),
+                    e => Node::Char(e),
+                }
+            }
+            c => Node::Char(c),
+        };
+        Ok(node)
+    }
+
+    /// A `[...]` collection, or `None` if it is not closed and `[` is literal.
+    fn class(&mut self) -> Option<Node> {
+        let start = self.i;
+        let mut items = Vec::new();
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.i += 1;
+        }
+        let mut first = true;
+        loop {
+            let c = match self.peek() {
+                None => {
+                    self.i = start;
+                    return None;
+                }
+                Some(']') if !first => {
+                    self.i += 1;
+                    return Some(Node::Class(items, negated));
+                }
+                Some('\\') if self.s.get(self.i + 1).is_some() => {
+                    self.i += 2;
+                    match self.s[self.i - 1] {
+                        's' => {
+                            items.push(ClassItem::Space);
+                            continue;
+                        }
+                        'd' => {
+                            items.push(ClassItem::Digit);
+                            continue;
+                        }
+                        'w' => {
+                            items.push(ClassItem::Word);
+                            continue;
+                        }
+                        't' => '\t',
+                        e => e,
+                    }
+                }
+                Some(c) => {
+                    self.i += 1;
+                    c
+                }
+            };
+            first = false;
+            let hi = match (self.peek(), self.s.get(self.i + 1)) {
+                (Some('-'), Some(&hi)) if hi != ']' => {
+                    self.i += 2;
+                    hi
+                }
+                _ => c,
+            };
+            items.push(ClassItem::Range(c, hi));
+        }
+    }
+}
+
+struct Matcher<'a> {
+    text: &'a [char],
+    icase: bool,
+}
+impl Matcher<'_> {
+    fn seq(&self, nodes: &[Node], i: usize, k: &mut dyn FnMut(usize) -> bool) -> bool {
+        match nodes.split_first() {
+            None => k(i),
+            Some((node, rest)) => self.node(node, i, &mut |j| self.seq(rest, j, k)),
+        }
+    }
+
+    fn node(&self, node: &Node, i: usize, k: &mut dyn FnMut(usize) -> bool) -> bool {
+        match node {
+            Node::Group(alts) => alts.iter().any(|alt| self.seq(alt, i, k)),
+            Node::Repeat(inner, min, max) => self.repeat(inner, *min, *max, i, k),
+            atom => self.atom(atom, i).is_some_and(k),
+        }
+    }
+
+    fn repeat(
+        &self,
+        inner: &Node,
+        min: usize,
+        max: Option<usize>,
+        i: usize,
+        k: &mut dyn FnMut(usize) -> bool,
+    ) -> bool {
+        if let Node::Group(_) = inner {
+            return self.repeat_group(inner, min, max, 0, i, k);
+        }
+        // Single characters are collected in a loop rather than by recursion,
+        // so `.*` on a long line does not grow the stack.
+        let mut ends = vec![i];
+        while max.is_none_or(|max| ends.len() <= max) {
+            match self.atom(inner, ends[ends.len() - 1]) {
+                Some(j) if j != ends[ends.len() - 1] => ends.push(j),
+                _ => break,
+            }
+        }
+        if ends.len() <= min {
+            return false;
+        }
+        ends[min..].iter().rev().any(|j| k(*j))
+    }
+
+    fn repeat_group(
+        &self,
+        inner: &Node,
+        min: usize,
+        max: Option<usize>,
+        count: usize,
+        i: usize,
+        k: &mut dyn FnMut(usize) -> bool,
+    ) -> bool {
+        if max.is_none_or(|max| count < max)
+            && self.node(inner, i, &mut |j| {
+                (j != i || count < min) && self.repeat_group(inner, min, max, count + 1, j, k)
+            })
+        {
+            return true;
+        }
+        count >= min && k(i)
+    }
+
+    fn atom(&self, node: &Node, i: usize) -> Option<usize> {
+        let word_at = |j: usize| self.text.get(j).is_some_and(|c| is_word(*c));
+        let before = i > 0 && word_at(i - 1);
+        let matched = match node {
+            Node::Start => return (i == 0).then_some(i),
+            Node::End => return (i == self.text.len()).then_some(i),
+            Node::WordStart => return (!before && word_at(i)).then_some(i),
+            Node::WordEnd => return (before && !word_at(i)).then_some(i),
+            node => {
+                let c = *self.text.get(i)?;
+                match node {
+                    Node::Char(p) if self.icase => c.to_lowercase().eq(p.to_lowercase()),
+                    Node::Char(p) => c == *p,
+                    Node::Any => true,
+                    Node::Class(items, negated) => {
+                        let hit = |c: char| items.iter().any(|item| item.matches(c));
+                        let found = hit(c)
+                            || (self.icase
+                                && (c.to_lowercase().any(hit) || c.to_uppercase().any(hit)));
+                        found != *negated
+                    }
+                    _ => false,
+                }
+            }
+        };
+        matched.then_some(i + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pat: &str, text: &str) -> Option<Range<usize>> {
+        Pattern::new(pat).unwrap().find(text)
+    }
+
+    #[test]
+    fn literals_and_anchors() {
+        assert_eq!(find("bc", "abcd"), Some(1..3));
+        assert_eq!(find("^ab", "abab"), Some(0..2));
+        assert_eq!(find("^b", "ab"), None);
+        assert_eq!(find("b$", "abab"), Some(3..4));
+        assert_eq!(find("a^b$c", "xa^b$c"), Some(1..6));
+        assert_eq!(find("^$", ""), Some(0..0));
+        assert_eq!(find("\\.", "a.b"), Some(1..2));
+    }
+
+    #[test]
+    fn multis() {
+        assert_eq!(find("ab*c", "xac abbbc"), Some(1..3));
+        assert_eq!(find("ab\\+c", "xac abbbc"), Some(4..9));
+        assert_eq!(find("colou\\=r", "color"), Some(0..5));
+        assert_eq!(find("a\\{2,3}", "caaaa"), Some(1..4));
+        assert_eq!(find("a\\{2}", "ca"), None);
+        assert_eq!(find("*a", "x*a"), Some(1..3));
+        assert_eq!(find("^*a", "*a"), Some(0..2));
+        assert_eq!(
+            find("x.*y", &format!("x{}y", "z".repeat(100_000))).map(|r| r.end),
+            Some(100_002)
+        );
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(find("[0-9]\\+", "ab 123 c"), Some(3..6));
+        assert_eq!(find("[^a-c ]", "abc d"), Some(4..5));
+        assert_eq!(find("[]x]", "a]"), Some(1..2));
+        assert_eq!(find("\\s\\d\\w", "a 1_"), Some(1..4));
+        assert_eq!(find("\\S\\+", "  ab "), Some(2..4));
+        assert_eq!(find("[", "a[b"), Some(1..2));
+        assert_eq!(find("é.", "aéb"), Some(1..4));
+    }
+
+    #[test]
+    fn groups_and_words() {
+        assert_eq!(find("foo\\|bar", "a bar foo"), Some(2..5));
+        assert_eq!(find("\\(ab\\)\\+c", "xababc"), Some(1..6));
+        assert_eq!(find("\\<is\\>", "this is"), Some(5..7));
+        assert_eq!(find("^\\(a\\|b\\)$", "b"), Some(0..1));
+    }
+
+    #[test]
+    fn ignore_case() {
+        assert_eq!(find("\\cFOO", "a foo"), Some(2..5));
+        assert_eq!(find("FOO", "a foo"), None);
+        assert_eq!(find("\\c[A-C]", "xb"), Some(1..2));
+    }
+
+    #[test]
+    fn invalid() {
+        for pat in ["\\(a", "a\\)", "\\+", "a\\{3,1}", "\\j", "a\\"] {
+            assert!(Pattern::new(pat).is_err(), "{}", pat);
+        }
+    }
+}
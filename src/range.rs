@@ -0,0 +1,305 @@
+use std::cmp::{self, Ordering};
+
+use crate::{editor::EditorRows, indent, pattern::Pattern};
+
+pub type LineRange = (usize, usize);
+
+impl EditorRows {
+    pub fn parse_range<'a>(
+        &self,
+        cmd: &'a str,
+        cur: usize,
+    ) -> Result<(Option<LineRange>, &'a str), String> {
+        let last = self.num_rows() - 1;
+        if let Some(rest) = cmd.strip_prefix('%') {
+            return Ok((Some((0, last)), rest));
+        }
+        let (start, mut rest) = self.parse_address(cmd, cur)?;
+        let start = match start {
+            Some(start) => start,
+            // A missing first address is the current line, as in `,5`.
+            None if rest.starts_with([',', ';']) => cur,
+            None => return Ok((None, rest)),
+        };
+        let mut end = start;
+        if let Some(sep @ (',' | ';')) = rest.chars().next() {
+            let base = if sep == ';' { start } else { cur };
+            let (addr, r) = self.parse_address(&rest[1..], base)?;
+            end = addr.unwrap_or(base);
+            rest = r;
+        }
+        if cmp::max(start, end) > last {
+            return Err("Invalid range".into());
+        }
+        Ok((Some((cmp::min(start, end), cmp::max(start, end))), rest))
+    }
+
+    pub fn parse_dest(&self, s: &str, cur: usize) -> Result<usize, String> {
+        let s = s.trim();
+        if s == "0" {
+            return Ok(0);
+        }
+        match self.parse_address(s, cur)? {
+            (Some(y), "") if y < self.num_rows() => Ok(y + 1),
+            _ => Err("Invalid address".into()),
+        }
+    }
+
+    pub fn move_lines(&mut self, (start, end): LineRange, dest: usize) -> Result<usize, String> {
+        if dest > start && dest <= end {
+            return Err("Cannot move a range of lines into itself".into());
+        }
+        let lines: Vec<String> = (start..=end).map(|y| self.get_raw(y).to_string()).collect();
+        let n = lines.len();
+        self.delete_erows(start, end + 1);
+        let at = if dest > end { dest - n } else { dest };
+        for (i, line) in lines.into_iter().enumerate() {
+            self.insert_erow(at + i, line);
+        }
+        Ok(at + n - 1)
+    }
+
+    pub fn copy_lines(&mut self, (start, end): LineRange, dest: usize) -> usize {
+        let lines: Vec<String> = (start..=end).map(|y| self.get_raw(y).to_string()).collect();
+        let n = lines.len();
+        for (i, line) in lines.into_iter().enumerate() {
+            self.insert_erow(dest + i, line);
+        }
+        dest + n - 1
+    }
+
+    pub fn sort_lines(&mut self, (start, end): LineRange, flags: &SortFlags) -> usize {
+        let mut lines: Vec<String> = (start..=end).map(|y| self.get_raw(y).to_string()).collect();
+        lines.sort_by(|a, b| flags.compare(a, b));
+        if flags.unique {
+            lines.dedup_by(|a, b| flags.compare(a, b) == Ordering::Equal);
+        }
+        for (i, line) in lines.iter().enumerate() {
+            let row = self.get_erow_mut(start + i);
+            let len = row.raw.len();
+            row.replace_range(0..len, line);
+        }
+        self.delete_erows(start + lines.len(), end + 1);
+        end + 1 - start - lines.len()
+    }
+
+    pub fn retab_lines(&mut self, (start, end): LineRange, expandtab: bool, all: bool) -> usize {
+        let mut changed = 0;
+        for y in start..=end {
+            let line = indent::retab(self.get_raw(y), expandtab, all);
+            if line != self.get_raw(y) {
+                let row = self.get_erow_mut(y);
+                let len = row.raw.len();
+                row.replace_range(0..len, &line);
+                changed += 1;
+            }
+        }
+        changed
+    }
+
+    fn parse_address<'a>(
+        &self,
+        s: &'a str,
+        cur: usize,
+    ) -> Result<(Option<usize>, &'a str), String> {
+        let (mut line, mut rest) = match s.chars().next() {
+            Some('.') => (Some(cur), &s[1..]),
+            Some('$') => (Some(self.num_rows() - 1), &s[1..]),
+            Some('\'') => {
+                let name = s[1..].chars().next().ok_or("Invalid range")?;
+                let (_, y) = self.get_mark(name).ok_or("Mark not set")?;
+                (Some(y), &s[1 + name.len_utf8()..])
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let (n, rest) = split_number(s);
+                (Some(n.saturating_sub(1)), rest)
+            }
+            _ => (None, s),
+        };
+        while let Some(sign @ ('+' | '-')) = rest.chars().next() {
+            let (n, r) = split_number(&rest[1..]);
+            let n = if r.len() == rest.len() - 1 { 1 } else { n };
+            let base = line.unwrap_or(cur);
+            line = Some(match sign {
+                '+' => base.saturating_add(n),
+                _ => base.checked_sub(n).ok_or("Invalid range")?,
+            });
+            rest = r;
+        }
+        Ok((line, rest))
+    }
+}
+
+fn split_number(s: &str) -> (usize, &str) {
+    let len = s.len() - s.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    (s[..len].parse().unwrap_or(usize::MAX), &s[len..])
+}
+
+#[derive(Default)]
+pub struct SortFlags {
+    numeric: bool,
+    ignore_case: bool,
+    unique: bool,
+    use_match: bool,
+    reverse: bool,
+    pattern: Option<Pattern>,
+}
+impl SortFlags {
+    pub fn parse(args: &str, reverse: bool) -> Result<Self, String> {
+        let mut flags = Self {
+            reverse,
+            ..Default::default()
+        };
+        let mut rest = args;
+        while let Some(c) = rest.chars().next() {
+            rest = &rest[c.len_utf8()..];
+            match c {
+                'n' => flags.numeric = true,
+                'i' => flags.ignore_case = true,
+                'u' => flags.unique = true,
+                'r' => flags.use_match = true,
+                c if c.is_whitespace() => {}
+                c if !c.is_alphanumeric() && c != '"' => {
+                    let (pat, tail) = rest.split_once(c).unwrap_or((rest, ""));
+                    flags.pattern = (!pat.is_empty()).then(|| Pattern::new(pat)).transpose()?;
+                    rest = tail;
+                }
+                _ => return Err(format!("Invalid argument: {}{}", c, rest)),
+            }
+        }
+        Ok(flags)
+    }
+
+    fn key<'a>(&self, line: &'a str) -> &'a str {
+        let Some(pat) = &self.pattern else {
+            return line;
+        };
+        match pat.find(line) {
+            Some(m) if self.use_match => &line[m],
+            Some(m) => &line[m.end..],
+            None => "",
+        }
+    }
+
+    fn compare(&self, a: &str, b: &str) -> Ordering {
+        let (a, b) = (self.key(a), self.key(b));
+        let ord = if self.numeric {
+            first_number(a).cmp(&first_number(b))
+        } else if self.ignore_case {
+            a.to_lowercase().cmp(&b.to_lowercase())
+        } else {
+            a.cmp(b)
+        };
+        if self.reverse {
+            ord.reverse()
+        } else {
+            ord
+        }
+    }
+}
+
+fn first_number(s: &str) -> Option<i64> {
+    let start = s.find(|c: char| c.is_ascii_digit())?;
+    let len = s[start..].len()
+        - s[start..]
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .len();
+    let n: i64 = s[start..start + len].parse().unwrap_or(i64::MAX);
+    Some(if s[..start].ends_with('-') { -n } else { n })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> EditorRows {
+        let lines: Vec<String> = (1..=10).map(|n| n.to_string()).collect();
+        let mut rows =
+            EditorRows::from_lines(&lines.iter().map(String::as_str).collect::<Vec<_>>());
+        rows.set_mark('a', (0, 2));
+        rows
+    }
+
+    fn range(cmd: &str) -> Result<(Option<LineRange>, &str), String> {
+        rows().parse_range(cmd, 4)
+    }
+
+    #[test]
+    fn single_addresses() {
+        assert_eq!(range("d"), Ok((None, "d")));
+        assert_eq!(range(".d"), Ok((Some((4, 4)), "d")));
+        assert_eq!(range("$"), Ok((Some((9, 9)), "")));
+        assert_eq!(range("%d"), Ok((Some((0, 9)), "d")));
+        assert_eq!(range("3j"), Ok((Some((2, 2)), "j")));
+        assert_eq!(range("'a"), Ok((Some((2, 2)), "")));
+        assert_eq!(range("'b"), Err("Mark not set".into()));
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(range(".+2"), Ok((Some((6, 6)), "")));
+        assert_eq!(range("+"), Ok((Some((5, 5)), "")));
+        assert_eq!(range("-3"), Ok((Some((1, 1)), "")));
+        assert_eq!(range("$-1"), Ok((Some((8, 8)), "")));
+        assert_eq!(range("'a++"), Ok((Some((4, 4)), "")));
+        assert_eq!(range(".-5"), Err("Invalid range".into()));
+    }
+
+    #[test]
+    fn two_addresses() {
+        assert_eq!(range("2,4d"), Ok((Some((1, 3)), "d")));
+        assert_eq!(range("5,2"), Ok((Some((1, 4)), "")));
+        assert_eq!(range("3,"), Ok((Some((2, 4)), "")));
+        assert_eq!(range(",7"), Ok((Some((4, 6)), "")));
+        assert_eq!(range("2;+1"), Ok((Some((1, 2)), "")));
+        assert_eq!(range("2,+1"), Ok((Some((1, 5)), "")));
+    }
+
+    #[test]
+    fn beyond_the_last_line() {
+        assert_eq!(range("11"), Err("Invalid range".into()));
+        assert_eq!(range("$+1"), Err("Invalid range".into()));
+        assert_eq!(range("1,20"), Err("Invalid range".into()));
+        assert_eq!(
+            range("99999999999999999999999"),
+            Err("Invalid range".into())
+        );
+        assert_eq!(
+            range("+99999999999999999999999"),
+            Err("Invalid range".into())
+        );
+        let rows = rows();
+        assert_eq!(rows.parse_dest("0", 4), Ok(0));
+        assert_eq!(rows.parse_dest("$", 4), Ok(10));
+        assert_eq!(rows.parse_dest("11", 4), Err("Invalid address".into()));
+    }
+
+    fn sorted(args: &str, reverse: bool, lines: &[&str]) -> Vec<String> {
+        let flags = SortFlags::parse(args, reverse).unwrap();
+        let mut rows = EditorRows::from_lines(lines);
+        rows.sort_lines((0, lines.len() - 1), &flags);
+        (0..rows.num_rows())
+            .map(|y| rows.get_raw(y).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn sort_flags() {
+        let lines = ["b10", "a9", "B2", "a9"];
+        assert_eq!(sorted("", false, &lines), ["B2", "a9", "a9", "b10"]);
+        assert_eq!(sorted("", true, &lines), ["b10", "a9", "a9", "B2"]);
+        assert_eq!(sorted("n", false, &lines), ["B2", "a9", "a9", "b10"]);
+        assert_eq!(sorted("i u", false, &lines), ["a9", "b10", "B2"]);
+        assert_eq!(sorted("n", false, &["x", "-3", "2"]), ["x", "-3", "2"]);
+        assert!(SortFlags::parse("z", false).is_err());
+        assert!(SortFlags::parse("/\\(/", false).is_err());
+    }
+
+    #[test]
+    fn sort_on_pattern() {
+        let lines = ["a=3", "c", "b=1"];
+        assert_eq!(sorted("/=/", false, &lines), ["c", "b=1", "a=3"]);
+        assert_eq!(sorted("/^\\w/ n", false, &lines), ["c", "b=1", "a=3"]);
+        assert_eq!(sorted("/.=/ r", false, &lines), ["c", "a=3", "b=1"]);
+    }
+}
@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+
+#[derive(Clone, Default)]
+pub struct Register {
+    pub text: String,
+    pub linewise: bool,
+}
+
+#[derive(Default)]
+pub struct Registers {
+    regs: HashMap<char, Register>,
+}
+impl Registers {
+    pub fn get(&self, name: char) -> Option<&Register> {
+        let name = if name == '"' {
+            '"'
+        } else {
+            name.to_ascii_lowercase()
+        };
+        self.regs.get(&name)
+    }
+
+    pub fn set(&mut self, name: char, text: String, linewise: bool) {
+        match name {
+            'A'..='Z' => {
+                let reg = self.regs.entry(name.to_ascii_lowercase()).or_default();
+                if linewise && !reg.linewise && !reg.text.is_empty() {
+                    reg.text.push('\n');
+                }
+                reg.text.push_str(&text);
+                reg.linewise |= linewise;
+            }
+            '_' => {}
+            _ => {
+                self.regs.insert(name, Register { text, linewise });
+            }
+        }
+    }
+
+    pub fn yank(&mut self, text: String, linewise: bool) {
+        self.set('0', text.clone(), linewise);
+        self.set('"', text, linewise);
+    }
+
+    pub fn delete(&mut self, text: String, linewise: bool) {
+        if linewise {
+            for i in (1..9).rev() {
+                if let Some(prev) = self.regs.remove(&char::from(b'0' + i)) {
+                    self.regs.insert(char::from(b'1' + i), prev);
+                }
+            }
+            self.set('1', text.clone(), true);
+        } else {
+            self.set('-', text.clone(), false);
+        }
+        self.set('"', text, linewise);
+    }
+}
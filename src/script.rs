@@ -0,0 +1,326 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, Scope, AST};
+
+use crate::{config_dir, editor::EditorRows, register::Registers};
+
+const MAX_OPERATIONS: u64 = 50_000_000;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+pub enum Request {
+    Command(String),
+    Map {
+        modes: String,
+        lhs: String,
+        rhs: String,
+        noremap: bool,
+    },
+}
+
+#[derive(Default)]
+pub struct ScriptState {
+    pub rows: EditorRows,
+    pub cursor: (usize, usize),
+    pub mode: String,
+    pub registers: Registers,
+    pub changed: bool,
+    pub locked: Option<&'static str>,
+    pub messages: Vec<(String, bool)>,
+    pub requests: Vec<Request>,
+    commands: Vec<(String, String)>,
+}
+
+impl ScriptState {
+    pub fn new(rows: EditorRows, cursor: (usize, usize), mode: &str, registers: Registers) -> Self {
+        Self {
+            rows,
+            cursor,
+            mode: mode.into(),
+            registers,
+            ..Default::default()
+        }
+    }
+}
+
+pub struct Scripts {
+    engine: Engine,
+    state: Rc<RefCell<ScriptState>>,
+    plugins: Vec<(String, AST, Scope<'static>)>,
+    commands: HashMap<String, (usize, String)>,
+}
+impl Scripts {
+    pub fn new() -> Self {
+        let state = Rc::new(RefCell::new(ScriptState::default()));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        register_api(&mut engine, &state);
+
+        Self {
+            engine,
+            state,
+            plugins: Vec::new(),
+            commands: HashMap::new(),
+        }
+    }
+
+    pub fn plugin_dir() -> Option<PathBuf> {
+        Some(config_dir()?.join("plugins"))
+    }
+
+    pub fn enter(&mut self, state: ScriptState) {
+        *self.state.borrow_mut() = state;
+    }
+
+    pub fn leave(&mut self) -> ScriptState {
+        self.state.take()
+    }
+
+    pub fn load_plugins(&mut self) -> Vec<String> {
+        let Some(dir) = Self::plugin_dir() else {
+            return Vec::new();
+        };
+        let Ok(entries) = fs::read_dir(&dir) else {
+            return Vec::new();
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "rhai"))
+            .collect();
+        paths.sort();
+
+        let mut errors = Vec::new();
+        for path in paths {
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("plugin")
+                .to_string();
+            if let Err(e) = self.load(&name, &path) {
+                errors.push(format!("{}: {}", name, e));
+            }
+        }
+        errors
+    }
+
+    fn load(&mut self, name: &str, path: &Path) -> Result<(), String> {
+        let ast = self
+            .engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| e.to_string())?;
+        let mut scope = Scope::new();
+        let res = self.engine.run_ast_with_scope(&mut scope, &ast);
+
+        let index = self.plugins.len();
+        self.plugins.push((name.into(), ast, scope));
+        let commands = std::mem::take(&mut self.state.borrow_mut().commands);
+        for (cmd, func) in commands {
+            self.commands.insert(cmd, (index, func));
+        }
+        res.map_err(|e| e.to_string())
+    }
+
+    pub fn has_command(&self, name: &str) -> bool {
+        self.commands.contains_key(name)
+    }
+
+    pub fn command_names(&self) -> impl Iterator<Item = &String> {
+        self.commands.keys()
+    }
+
+    pub fn call_command(&mut self, name: &str, args: &str) -> Result<(), String> {
+        let Some((index, func)) = self.commands.get(name).cloned() else {
+            return Err(format!("Unknown command \'{}\'", name));
+        };
+        let (plugin, ast, scope) = &mut self.plugins[index];
+        let takes_args = ast
+            .iter_functions()
+            .any(|f| f.name == func && f.params.len() == 1);
+
+        // Keep the plugin's top-level variables between calls instead of
+        // re-running its top-level statements in a fresh scope.
+        let opts = || CallFnOptions::new().eval_ast(false).rewind_scope(false);
+        let res = if takes_args {
+            self.engine.call_fn_with_options::<Dynamic>(
+                opts(),
+                scope,
+                ast,
+                &func,
+                (args.to_string(),),
+            )
+        } else {
+            self.engine
+                .call_fn_with_options::<Dynamic>(opts(), scope, ast, &func, ())
+        };
+        let res = res.map(|_| ()).map_err(|e| format!("{}: {}", plugin, e));
+
+        let commands = std::mem::take(&mut self.state.borrow_mut().commands);
+        for (cmd, func) in commands {
+            self.commands.insert(cmd, (index, func));
+        }
+        res
+    }
+}
+
+fn row_index(state: &ScriptState, i: i64, extra: usize) -> ScriptResult<usize> {
+    let n_rows = state.rows.num_rows() + extra;
+    if i < 0 || i as usize >= n_rows {
+        return Err(format!("Line {} out of range", i).into());
+    }
+    Ok(i as usize)
+}
+
+fn check_modifiable(state: &ScriptState) -> ScriptResult<()> {
+    match state.locked {
+        Some(msg) => Err(msg.into()),
+        None => Ok(()),
+    }
+}
+
+fn register_api(engine: &mut Engine, state: &Rc<RefCell<ScriptState>>) {
+    let s = state.clone();
+    engine.register_fn("line_count", move || s.borrow().rows.num_rows() as i64);
+
+    let s = state.clone();
+    engine.register_fn("get_line", move |i: i64| -> ScriptResult<String> {
+        let state = s.borrow();
+        let i = row_index(&state, i, 0)?;
+        Ok(state.rows.get_raw(i).to_string())
+    });
+
+    let s = state.clone();
+    engine.register_fn("set_line", move |i: i64, text: &str| -> ScriptResult<()> {
+        let mut state = s.borrow_mut();
+        check_modifiable(&state)?;
+        let i = row_index(&state, i, 0)?;
+        let erow = state.rows.get_erow_mut(i);
+        erow.clear();
+        erow.push_str(text);
+        state.changed = true;
+        Ok(())
+    });
+
+    let s = state.clone();
+    engine.register_fn(
+        "insert_line",
+        move |i: i64, text: &str| -> ScriptResult<()> {
+            let mut state = s.borrow_mut();
+            check_modifiable(&state)?;
+            let i = row_index(&state, i, 1)?;
+            state.rows.insert_erow(i, text.into());
+            state.changed = true;
+            Ok(())
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn("delete_line", move |i: i64| -> ScriptResult<()> {
+        let mut state = s.borrow_mut();
+        check_modifiable(&state)?;
+        let i = row_index(&state, i, 0)?;
+        if state.rows.num_rows() == 1 {
+            state.rows.clear_erow(i);
+        } else {
+            state.rows.delete_erow(i);
+        }
+        state.changed = true;
+        Ok(())
+    });
+
+    let s = state.clone();
+    engine.register_fn(
+        "place_sign",
+        move |i: i64, text: &str, group: &str| -> ScriptResult<()> {
+            let mut state = s.borrow_mut();
+            let i = row_index(&state, i, 0)?;
+            state.rows.place_sign(i, text, group);
+            Ok(())
+        },
+    );
+    let s = state.clone();
+    engine.register_fn("unplace_signs", move |group: &str| {
+        s.borrow_mut().rows.unplace_signs(None, Some(group));
+    });
+
+    let s = state.clone();
+    engine.register_fn("cursor_x", move || s.borrow().cursor.0 as i64);
+    let s = state.clone();
+    engine.register_fn("cursor_y", move || s.borrow().cursor.1 as i64);
+    let s = state.clone();
+    engine.register_fn("set_cursor", move |x: i64, y: i64| {
+        s.borrow_mut().cursor = (x.max(0) as usize, y.max(0) as usize);
+    });
+
+    let s = state.clone();
+    engine.register_fn("mode", move || s.borrow().mode.clone());
+
+    let s = state.clone();
+    engine.register_fn("filename", move || {
+        s.borrow()
+            .rows
+            .filename
+            .as_ref()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default()
+    });
+
+    let s = state.clone();
+    engine.register_fn("get_register", move |name: char| {
+        s.borrow()
+            .registers
+            .get(name)
+            .map(|r| r.text.clone())
+            .unwrap_or_default()
+    });
+    let s = state.clone();
+    engine.register_fn("set_register", move |name: char, text: &str| {
+        s.borrow_mut()
+            .registers
+            .set(name, text.into(), text.ends_with('\n'));
+    });
+
+    let s = state.clone();
+    engine.register_fn("message", move |msg: &str| {
+        s.borrow_mut().messages.push((msg.into(), false));
+    });
+    let s = state.clone();
+    engine.register_fn("error", move |msg: &str| {
+        s.borrow_mut().messages.push((msg.into(), true));
+    });
+
+    let s = state.clone();
+    engine.register_fn("command", move |cmd: &str| {
+        let cmd = cmd.strip_prefix(':').unwrap_or(cmd);
+        s.borrow_mut().requests.push(Request::Command(cmd.into()));
+    });
+
+    let s = state.clone();
+    engine.register_fn(
+        "register_command",
+        move |name: &str, func: &str| -> ScriptResult<()> {
+            if !name.starts_with(|c: char| c.is_ascii_uppercase()) {
+                return Err("User commands must start with an uppercase letter".into());
+            }
+            s.borrow_mut().commands.push((name.into(), func.into()));
+            Ok(())
+        },
+    );
+
+    for (fn_name, noremap) in [("map", false), ("noremap", true)] {
+        let s = state.clone();
+        engine.register_fn(fn_name, move |modes: &str, lhs: &str, rhs: &str| {
+            s.borrow_mut().requests.push(Request::Map {
+                modes: modes.into(),
+                lhs: lhs.into(),
+                rhs: rhs.into(),
+                noremap,
+            });
+        });
+    }
+}
@@ -0,0 +1,90 @@
+use std::cmp;
+
+use crate::editor::ERow;
+
+/// The lines from `start` that were `old` and became `new`, and where the
+/// cursor was before the change.
+pub struct Change {
+    pub start: usize,
+    pub old: Vec<String>,
+    pub new: Vec<String>,
+    pub cursor: (usize, usize),
+}
+
+/// The changes made to a buffer, found by comparing its lines with a copy
+/// taken each time the editor is back in Normal mode.
+#[derive(Default)]
+pub struct Undo {
+    text: Vec<String>,
+    done: Vec<Change>,
+    undone: Vec<Change>,
+    tick: u64,
+    cursor: (usize, usize),
+}
+impl Undo {
+    pub fn new(rows: &[ERow], tick: u64) -> Self {
+        Self {
+            text: rows.iter().map(|r| r.raw.clone()).collect(),
+            tick,
+            ..Default::default()
+        }
+    }
+
+    /// Records everything changed since the last sync as one change.
+    pub fn sync(&mut self, rows: &[ERow], tick: u64, cursor: (usize, usize)) {
+        if tick != self.tick {
+            self.tick = tick;
+            self.record(rows);
+        }
+        self.cursor = cursor;
+    }
+
+    fn record(&mut self, rows: &[ERow]) {
+        let same = |(a, b): &(&String, &ERow)| **a == b.raw;
+        let prefix = self.text.iter().zip(rows).take_while(same).count();
+        if prefix == self.text.len() && prefix == rows.len() {
+            return;
+        }
+        let suffix = self
+            .text
+            .iter()
+            .rev()
+            .zip(rows.iter().rev())
+            .take(cmp::min(self.text.len(), rows.len()) - prefix)
+            .take_while(same)
+            .count();
+        let new: Vec<String> = rows[prefix..rows.len() - suffix]
+            .iter()
+            .map(|r| r.raw.clone())
+            .collect();
+        let old = self
+            .text
+            .splice(prefix..self.text.len() - suffix, new.iter().cloned())
+            .collect();
+        self.done.push(Change {
+            start: prefix,
+            old,
+            new,
+            cursor: self.cursor,
+        });
+        self.undone.clear();
+    }
+
+    pub fn undo(&mut self) -> Option<&Change> {
+        let change = self.done.pop()?;
+        let end = change.start + change.new.len();
+        self.text
+            .splice(change.start..end, change.old.iter().cloned());
+        self.undone.push(change);
+        self.undone.last()
+    }
+
+    pub fn redo(&mut self) -> Option<&Change> {
+        let change = self.undone.pop()?;
+        let end = change.start + change.old.len();
+        self.text
+            .splice(change.start..end, change.new.iter().cloned());
+        self.done.push(change);
+        self.done.last()
+    }
+}